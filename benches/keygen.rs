@@ -0,0 +1,37 @@
+//! Compares key generation with the SHA-256 and BLAKE3 hash backends for the
+//! hash-bound schemes. Run with `cargo bench --features blake3`.
+//!
+//! Nearly every input is a block or two, so on CPUs with SHA instructions
+//! the two backends come out about even.
+
+use std::time::{Duration, Instant};
+
+use crypto::SignatureScheme;
+use crypto::horst::Horst;
+use crypto::merkle::Merkle;
+use crypto::util::Blake3;
+use crypto::winternitz::Winternitz;
+
+const ROUNDS: u32 = 10;
+
+fn time<S: SignatureScheme>(scheme: &S) -> Duration {
+    let start = Instant::now();
+    for i in 0..ROUNDS {
+        scheme.gen_keys(Some([i as u8; 32]));
+    }
+    start.elapsed() / ROUNDS
+}
+
+fn compare(name: &str, sha256: Duration, blake3: Duration) {
+    println!("{:<24} sha256 {:>10.2?}  blake3 {:>10.2?}  speedup {:.2}x", name, sha256, blake3,
+             sha256.as_secs_f64() / blake3.as_secs_f64());
+}
+
+fn main() {
+    let horst = || Horst::new(16, 32);
+    compare("horst t=2^16", time(&horst()), time(&horst().with_hasher::<Blake3>()));
+
+    let merkle = Merkle::new(8, Winternitz::new(16));
+    let blake3_merkle = Merkle::new(8, Winternitz::new(16).with_hasher::<Blake3>()).with_hasher::<Blake3>();
+    compare("merkle h=8, w=16", time(&merkle), time(&blake3_merkle));
+}
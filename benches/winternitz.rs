@@ -0,0 +1,35 @@
+//! Signature size against signing and verification time for Winternitz
+//! across `w`. Run with `cargo bench --bench winternitz`.
+//!
+//! Doubling `log w` roughly halves the signature, but chains get `w - 1`
+//! steps long, so time grows about as `w / log w`.
+
+use std::time::{Duration, Instant};
+
+use crypto::{Estimate, SignatureScheme};
+use crypto::winternitz::Winternitz;
+
+const ROUNDS: u32 = 20;
+
+fn time(f: impl Fn(u32)) -> Duration {
+    let start = Instant::now();
+    for i in 0..ROUNDS {
+        f(i);
+    }
+    start.elapsed() / ROUNDS
+}
+
+fn main() {
+    let msg = b"My OS update";
+    for &w in &[4, 16, 64, 256] {
+        let winternitz = Winternitz::new(w);
+        let (private, public) = winternitz.gen_keys(Some([1; 32]));
+        let sig = winternitz.sign(msg, &private);
+
+        let keygen = time(|i| drop(winternitz.gen_keys(Some([i as u8; 32]))));
+        let sign = time(|i| drop(winternitz.sign(&i.to_le_bytes(), &private)));
+        let verify = time(|_| assert!(winternitz.verify(msg, &public, &sig)));
+        println!("w={:<4} signature {:>5} B  keygen {:>10.2?}  sign {:>10.2?}  verify {:>10.2?}",
+                 w, winternitz.signature_size(), keygen, sign, verify);
+    }
+}
@@ -0,0 +1,219 @@
+use std::convert::TryInto;
+
+use sha2::{Digest, Sha512};
+
+use crate::SignatureScheme;
+use crate::framing::{write_len, Reader};
+use crate::util::{record_hashes, Hasher};
+
+fn sha512(data: &[u8]) -> [u8; 64] {
+    let mut digest = [0; 64];
+    digest.copy_from_slice(&Sha512::digest(data));
+    digest
+}
+
+/// A hash computed during verification, along with a SHA-512 digest of its
+/// input which stays meaningful if the scheme's hash function is deprecated.
+pub struct WitnessEntry {
+    input: Box<[u8]>,
+    output: Box<[u8]>,
+    sha512: [u8; 64],
+}
+
+impl WitnessEntry {
+    pub fn input(&self) -> &[u8] {
+        &self.input
+    }
+
+    /// What the scheme's hash function computed
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    pub fn sha512(&self) -> &[u8; 64] {
+        &self.sha512
+    }
+}
+
+
+/// The outcome of an archival verification and every intermediate hash it
+/// computed. Only the hashes computed through `util` are recorded, which
+/// covers all tree and chain hashing.
+///
+/// On the wire, the validity as a byte and the number of entries as a
+/// little-endian `u64`, then for each entry its input and output, each
+/// preceded by its length as a little-endian `u64`, and its SHA-512 digest.
+pub struct ArchiveRecord {
+    valid: bool,
+    entries: Vec<WitnessEntry>,
+}
+
+impl ArchiveRecord {
+    pub fn valid(&self) -> bool {
+        self.valid
+    }
+
+    pub fn entries(&self) -> &[WitnessEntry] {
+        &self.entries
+    }
+
+    /// SHA-512 digest over the whole record, meant to be timestamped or
+    /// countersigned alongside the archived document
+    pub fn digest(&self) -> [u8; 64] {
+        let mut hasher = Sha512::new();
+        hasher.update([self.valid as u8]);
+        for entry in &self.entries {
+            hasher.update((entry.input.len() as u64).to_le_bytes());
+            hasher.update(&entry.input);
+            hasher.update((entry.output.len() as u64).to_le_bytes());
+            hasher.update(&entry.output);
+            hasher.update(entry.sha512);
+        }
+        let mut digest = [0; 64];
+        digest.copy_from_slice(&hasher.finalize());
+        digest
+    }
+
+    /// Checks the record against a digest archived earlier and that every
+    /// entry is internally consistent. The outputs are only recomputed when
+    /// `check_outputs` is set, with `H` as the scheme's hash function
+    /// throughout, so the record can be revalidated by SHA-512 alone once `H`
+    /// is no longer trusted.
+    pub fn revalidate<H: Hasher>(&self, digest: &[u8; 64], check_outputs: bool) -> bool {
+        let consistent = self.entries.iter().all(|entry| {
+            sha512(&entry.input) == entry.sha512
+                && (!check_outputs || H::digest(&[&entry.input]).as_ref() == &*entry.output)
+        });
+
+        consistent && self.digest() == *digest
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.valid as u8];
+        write_len(&mut bytes, self.entries.len());
+        for entry in &self.entries {
+            for part in [&entry.input, &entry.output] {
+                write_len(&mut bytes, part.len());
+                bytes.extend_from_slice(part);
+            }
+            bytes.extend_from_slice(&entry.sha512);
+        }
+        bytes
+    }
+
+    /// Parses a record, or returns `None` if it is malformed. Whether it is
+    /// intact is up to `revalidate`.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut input = Reader::new(bytes);
+        let valid = match input.take(1).ok()? {
+            [0] => false,
+            [1] => true,
+            _ => return None,
+        };
+
+        // Every entry takes at least two lengths and a digest
+        let count = input.len(input.rest().len() / (8 + 8 + 64)).ok()?;
+        let mut entries = Vec::with_capacity(count);
+        for _ in 0..count {
+            let mut part = || {
+                let len = input.len(input.rest().len()).ok()?;
+                input.take(len).ok()
+            };
+            let input_bytes = part()?.into();
+            let output = part()?.into();
+            let sha512 = input.take(64).ok()?.try_into().unwrap();
+            entries.push(WitnessEntry { input: input_bytes, output, sha512 });
+        }
+
+        input.rest().is_empty().then_some(Self { valid, entries })
+    }
+}
+
+
+/// Verifies `sig` as `scheme.verify` would while recording a dual-hash
+/// witness of every intermediate hash.
+pub fn verify<S: SignatureScheme>(scheme: &S, msg: &[u8], public: &S::Public, sig: &S::Signature) -> ArchiveRecord {
+    let (valid, calls) = record_hashes(|| scheme.verify(msg, public, sig));
+
+    let entries = calls.into_iter()
+        .map(|call| WitnessEntry {
+            sha512: sha512(&call.input),
+            output: call.output,
+            input: call.input,
+        })
+        .collect();
+
+    ArchiveRecord { valid, entries }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::merkle::Merkle;
+    use crate::util::{Sha256, Sha512_256};
+    use crate::winternitz::Winternitz;
+
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let msg = b"My signed contract";
+
+        let merkle = Merkle::new(4, Winternitz::new(16));
+        let (private, public) = merkle.gen_keys(None);
+        let sig = merkle.sign(msg, &private);
+
+        let record = verify(&merkle, msg, &public, &sig);
+        assert!(record.valid());
+        assert!(!record.entries().is_empty());
+        assert_eq!(record.entries().last().unwrap().output(), public);
+
+        let digest = record.digest();
+        assert!(record.revalidate::<Sha256>(&digest, true));
+        assert!(record.revalidate::<Sha256>(&digest, false));
+        assert!(!record.revalidate::<Sha256>(&[0; 64], false));
+
+        assert!(!verify(&merkle, b"Another contract", &public, &sig).valid());
+    }
+
+    #[test]
+    fn encoding() {
+        let msg = b"My signed contract";
+        let merkle = Merkle::new(3, Winternitz::new(16));
+        let (private, public) = merkle.gen_keys(None);
+        let record = verify(&merkle, msg, &public, &merkle.sign(msg, &private));
+        let digest = record.digest();
+
+        let bytes = record.to_bytes();
+        let decoded = ArchiveRecord::from_bytes(&bytes).unwrap();
+        assert!(decoded.valid());
+        assert_eq!(decoded.entries().len(), record.entries().len());
+        assert!(decoded.revalidate::<Sha256>(&digest, true));
+
+        // A flipped output still parses, but no longer revalidates
+        let mut tampered = bytes.clone();
+        tampered[1 + 8 + 8 + record.entries()[0].input().len() + 8] ^= 1;
+        assert!(!ArchiveRecord::from_bytes(&tampered).unwrap().revalidate::<Sha256>(&digest, false));
+
+        assert!(ArchiveRecord::from_bytes(&bytes[..bytes.len() - 1]).is_none());
+        assert!(ArchiveRecord::from_bytes(&[bytes, vec![0]].concat()).is_none());
+        assert!(ArchiveRecord::from_bytes(&[2, 0, 0, 0, 0, 0, 0, 0, 0]).is_none());
+    }
+
+    #[test]
+    fn other_hashers() {
+        let msg = b"My signed contract";
+
+        let winternitz = Winternitz::new(16).with_hasher::<Sha512_256>();
+        let merkle = Merkle::new(3, winternitz).with_hasher::<Sha512_256>();
+        let (private, public) = merkle.gen_keys(None);
+        let record = verify(&merkle, msg, &public, &merkle.sign(msg, &private));
+        assert!(record.valid());
+
+        // The outputs are those the scheme computed, not SHA-256 ones
+        assert_eq!(record.entries().last().unwrap().output(), public);
+        let digest = record.digest();
+        assert!(record.revalidate::<Sha512_256>(&digest, true));
+        assert!(!record.revalidate::<Sha256>(&digest, true));
+    }
+}
@@ -0,0 +1,170 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::U256;
+use crate::util::hash;
+
+/// Human-readable part of encoded public keys
+pub const HRP: &str = "hbpk";
+
+const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecodeError(pub(crate) String);
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid bech32 string: {}", self.0)
+    }
+}
+
+impl Error for DecodeError {}
+
+fn error<T>(msg: impl Into<String>) -> Result<T, DecodeError> {
+    Err(DecodeError(msg.into()))
+}
+
+fn polymod(values: impl Iterator<Item = u8>) -> u32 {
+    values.fold(1, |chk, value| {
+        let top = chk >> 25;
+        let chk = (chk & 0x1ffffff) << 5 ^ value as u32;
+        GENERATOR.iter()
+            .enumerate()
+            .filter(|&(i, _)| (top >> i) & 1 == 1)
+            .fold(chk, |chk, (_, gen)| chk ^ gen)
+    })
+}
+
+fn hrp_expand(hrp: &str) -> impl Iterator<Item = u8> + '_ {
+    hrp.bytes().map(|c| c >> 5)
+        .chain(Some(0))
+        .chain(hrp.bytes().map(|c| c & 31))
+}
+
+// Regroups bits, padding the last group with zeros when encoding. When
+// decoding, the padding must be short and all zeros.
+fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Option<Vec<u8>> {
+    let mut acc = 0u32;
+    let mut bits = 0;
+    let mut result = Vec::new();
+    for &value in data {
+        acc = acc << from | value as u32;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            result.push((acc >> bits & ((1 << to) - 1)) as u8);
+        }
+    }
+
+    if pad {
+        if bits > 0 {
+            result.push((acc << (to - bits) & ((1 << to) - 1)) as u8);
+        }
+    } else if bits >= from || acc & ((1 << bits) - 1) != 0 {
+        return None;
+    }
+
+    Some(result)
+}
+
+/// Encodes a 32-byte public key (or fingerprint) as bech32 with the `hbpk`
+/// prefix, so typos are caught when it is copied by hand.
+pub fn encode(public: &U256) -> String {
+    encode_with_hrp(HRP, public)
+}
+
+pub(crate) fn encode_with_hrp(hrp: &str, public: &U256) -> String {
+    let data = convert_bits(public, 8, 5, true).unwrap();
+    let checksum = polymod(hrp_expand(hrp).chain(data.iter().copied()).chain([0; 6])) ^ 1;
+
+    let mut encoded = format!("{}1", hrp);
+    let checksum = (0..6).rev().map(|i| (checksum >> (5 * i) & 31) as u8);
+    encoded.extend(data.into_iter().chain(checksum).map(|d| CHARSET[d as usize] as char));
+    encoded
+}
+
+pub fn decode(encoded: &str) -> Result<U256, DecodeError> {
+    let (hrp, public) = decode_with_hrp(encoded)?;
+    if hrp != HRP {
+        return error(format!("expected prefix '{}', found '{}'", HRP, hrp));
+    }
+
+    Ok(public)
+}
+
+pub(crate) fn decode_with_hrp(encoded: &str) -> Result<(String, U256), DecodeError> {
+    if encoded.to_lowercase() != encoded && encoded.to_uppercase() != encoded {
+        return error("mixed case");
+    }
+    let encoded = encoded.to_lowercase();
+
+    let (hrp, data) = match encoded.rfind('1') {
+        Some(split) => (&encoded[..split], &encoded[split + 1..]),
+        None => return error("missing separator"),
+    };
+    if hrp.is_empty() || !hrp.bytes().all(|c| (33..=126).contains(&c)) {
+        return error("invalid prefix");
+    }
+
+    let data = data.bytes()
+        .map(|c| CHARSET.iter().position(|&d| d == c).map(|d| d as u8))
+        .collect::<Option<Vec<_>>>();
+    let data = match data {
+        Some(data) if data.len() >= 6 => data,
+        _ => return error("invalid characters"),
+    };
+    if polymod(hrp_expand(hrp).chain(data.iter().copied())) != 1 {
+        return error("checksum mismatch");
+    }
+
+    match convert_bits(&data[..data.len() - 6], 5, 8, false) {
+        Some(bytes) if bytes.len() == 32 => {
+            let mut public = [0; 32];
+            public.copy_from_slice(&bytes);
+            Ok((hrp.into(), public))
+        }
+        _ => error("expected 32 bytes"),
+    }
+}
+
+/// The encoded hash of a public key of any size
+pub fn fingerprint(public: impl AsRef<[u8]>) -> String {
+    encode(&hash(public))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let public = hash(b"My public key");
+        let encoded = encode(&public);
+        assert!(encoded.starts_with("hbpk1"));
+        assert_eq!(encoded.len(), 5 + 52 + 6);
+        assert_eq!(decode(&encoded), Ok(public));
+        assert_eq!(decode(&encoded.to_uppercase()), Ok(public));
+
+        // Any single typo is caught
+        for i in 5..encoded.len() {
+            let mut typo = encoded.clone().into_bytes();
+            typo[i] = if typo[i] == b'q' { b'p' } else { b'q' };
+            assert!(decode(std::str::from_utf8(&typo).unwrap()).is_err());
+        }
+
+        assert!(decode(&encoded.replacen("hbpk", "hbpx", 1)).is_err());
+        assert!(decode("hbpk1").is_err());
+    }
+
+    #[test]
+    fn checksum() {
+        // Valid strings from BIP 173
+        for s in &["a12uel5l", "abcdef1qpzry9x8gf2tvdw0s3jn54khce6mua7lmqqqxw"] {
+            let split = s.rfind('1').unwrap();
+            let data = s[split + 1..].bytes().map(|c| CHARSET.iter().position(|&d| d == c).unwrap() as u8);
+            assert_eq!(polymod(hrp_expand(&s[..split]).chain(data)), 1);
+        }
+    }
+}
@@ -0,0 +1,7 @@
+//! Writes the audit corpus to stdout, see `crypto::corpus` for its format.
+//!
+//! Usage: `corpus > corpus.json`
+
+fn main() {
+    println!("{}", crypto::corpus::generate().to_json());
+}
@@ -0,0 +1,186 @@
+//! Generates keys, signs, verifies and serializes in a loop, restarting and
+//! crashing stateful signers at random points, and panics as soon as a leaf
+//! is used twice, a valid signature is rejected or a forgery is accepted.
+//!
+//! Usage: `stress [seconds]`, running for a minute by default.
+
+use std::collections::HashSet;
+use std::env;
+use std::panic;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rand::prelude::{Rng, StdRng, SeedableRng};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crypto::{SignatureScheme, U256};
+use crypto::horst::Horst;
+use crypto::merkle::Merkle;
+use crypto::service::{Persist, Priority, SignerService};
+use crypto::sphincs::Sphincs;
+use crypto::winternitz::{Key, Winternitz};
+
+const CRASH: &[u8] = b"crash";
+
+/// The payload of the injected crashes, which the panic hook keeps quiet
+struct Injected;
+
+/// Winternitz that crashes while signing `CRASH`
+struct Crashing(Winternitz);
+
+impl SignatureScheme for Crashing {
+    type Private = U256;
+    type Public = Key;
+    type Signature = Key;
+
+    fn gen_keys(&self, seed: Option<U256>) -> (Self::Private, Self::Public) {
+        self.0.gen_keys(seed)
+    }
+
+    fn sign(&self, msg: &[u8], private: &Self::Private) -> Self::Signature {
+        if msg == CRASH {
+            panic::panic_any(Injected);
+        }
+        self.0.sign(msg, private)
+    }
+
+    fn verify(&self, msg: &[u8], public: &Self::Public, sig: &Self::Signature) -> bool {
+        self.0.verify(msg, public, sig)
+    }
+}
+
+type Persisted = Arc<Mutex<Option<(U256, usize)>>>;
+
+// Persists into `persisted`, but crashes instead of making write number
+// `crash_at`, recording that in `crashed`
+fn sink(persisted: &Persisted, crashed: &Arc<AtomicBool>, mut crash_at: Option<u32>) -> Persist {
+    let (persisted, crashed) = (Arc::clone(persisted), Arc::clone(crashed));
+    Box::new(move |state| {
+        if let Some(writes) = &mut crash_at {
+            if *writes == 0 {
+                crashed.store(true, Ordering::Relaxed);
+                panic::panic_any(Injected);
+            }
+            *writes -= 1;
+        }
+        *persisted.lock().unwrap() = state;
+    })
+}
+
+#[derive(Default)]
+struct Counters {
+    keys: u64,
+    signatures: u64,
+    restarts: u64,
+    crashes: u64,
+}
+
+fn check<S>(scheme: &S, msg: &[u8], public: &S::Public, sig: &S::Signature, counters: &mut Counters)
+    where S: SignatureScheme, S::Signature: Serialize + DeserializeOwned {
+    let json = serde_json::to_string(sig).unwrap();
+    let sig: S::Signature = serde_json::from_str(&json).unwrap();
+    assert!(scheme.verify(msg, public, &sig), "valid signature rejected");
+
+    let mut tampered = msg.to_vec();
+    tampered[0] ^= 1;
+    assert!(!scheme.verify(&tampered, public, &sig), "signature on a tampered message accepted");
+
+    counters.signatures += 1;
+}
+
+// Uses up a Merkle key through a signer service that is closed, dropped or
+// crashed every few signatures, either mid-sign or while persisting, and
+// restarted from the state it persisted
+fn stateful_round(rng: &mut StdRng, counters: &mut Counters) {
+    let merkle = Merkle::new(4, Crashing(Winternitz::new(16)));
+    let (state, public) = merkle.gen_keys(Some(rng.gen()));
+    counters.keys += 1;
+
+    let persisted = Arc::new(Mutex::new(Some(state)));
+    let mut used = HashSet::new();
+    loop {
+        let state = match *persisted.lock().unwrap() {
+            Some(state) => state,
+            None => return,
+        };
+        let crashed = Arc::new(AtomicBool::new(false));
+        let persist = sink(&persisted, &crashed, rng.gen_ratio(1, 4).then(|| rng.gen_range(0..8)));
+        let service = SignerService::new(Merkle::new(4, Crashing(Winternitz::new(16))), state, persist);
+        for _ in 0..rng.gen_range(1..=6) {
+            let priority = if rng.gen() { Priority::High } else { Priority::Normal };
+            if rng.gen_ratio(1, 8) {
+                crashed.store(true, Ordering::Relaxed);
+                assert!(service.sign(CRASH, priority).is_none(), "crashed signer handed out a signature");
+                break;
+            }
+
+            // Either the key is used up or the sink crashed
+            let msg: [u8; 16] = rng.gen();
+            let sig = match service.sign(&msg, priority) {
+                Some(sig) => sig,
+                None => break,
+            };
+
+            assert!(sig.leaf_idx() >= state.1, "restarted signer handed out leaf {} below the persisted {}",
+                    sig.leaf_idx(), state.1);
+            assert!(used.insert(sig.leaf_idx()), "leaf {} used twice", sig.leaf_idx());
+            check(&merkle, &msg, &public, &sig, counters);
+        }
+
+        if rng.gen() {
+            let closed = service.close();
+            if !crashed.load(Ordering::Relaxed) {
+                assert_eq!(closed, *persisted.lock().unwrap(), "closed with unpersisted state");
+            }
+        } else {
+            drop(service);
+        }
+        counters.restarts += 1;
+        if crashed.load(Ordering::Relaxed) {
+            counters.crashes += 1;
+        }
+    }
+}
+
+fn stateless_round(rng: &mut StdRng, counters: &mut Counters) {
+    let sphincs = Sphincs::new(2, 2, Winternitz::new(16), Horst::new(8, 64));
+    let (private, public) = sphincs.gen_keys(Some(rng.gen()));
+    counters.keys += 1;
+
+    for _ in 0..4 {
+        let msg: [u8; 16] = rng.gen();
+        check(&sphincs, &msg, &public, &sphincs.sign(&msg, &private), counters);
+    }
+}
+
+fn main() {
+    let seconds = env::args().nth(1).map_or(60, |arg| arg.parse().expect("duration must be in seconds"));
+    let duration = Duration::from_secs(seconds);
+
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if !info.payload().is::<Injected>() {
+            default_hook(info);
+        }
+    }));
+
+    let mut rng = StdRng::from_entropy();
+    let mut counters = Counters::default();
+    let start = Instant::now();
+    let mut last_report = start;
+    while start.elapsed() < duration {
+        stateful_round(&mut rng, &mut counters);
+        stateless_round(&mut rng, &mut counters);
+
+        if last_report.elapsed() >= Duration::from_secs(10) {
+            println!("{:>6}s: {} keys, {} signatures, {} restarts, {} crashes", start.elapsed().as_secs(),
+                     counters.keys, counters.signatures, counters.restarts, counters.crashes);
+            last_report = Instant::now();
+        }
+    }
+
+    println!("Passed: {} keys, {} signatures, {} restarts, {} crashes", counters.keys, counters.signatures,
+             counters.restarts, counters.crashes);
+}
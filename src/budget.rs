@@ -0,0 +1,258 @@
+//! Limits on the work a single signature or verification may do, so that
+//! crafted input, e.g. an envelope claiming huge parameters, can't pin a CPU.
+//! Hashes are counted as they are computed and the operation is abandoned as
+//! soon as the budget runs out, which needs panics to unwind.
+
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::time::{Duration, Instant};
+
+
+/// Where deadlines are measured against, so tests can control time
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Bounds the work each `sign` and `verify` may do, as a layer around a
+/// scheme, e.g. `Layered::new(scheme, budget)`. Key generation isn't bounded,
+/// as it only runs on trusted parameters. Nested budgets don't add up: the
+/// innermost one applies. Hashes computed on other threads, e.g. with the
+/// `rayon` feature, aren't counted. Unlimited unless configured.
+///
+/// An exceeded budget unwinds out of the middle of the scheme's code, so it
+/// needs `panic = "unwind"`: built with `panic = "abort"`, running out of
+/// budget aborts the process. Nor is it lock-safe: only bound schemes that
+/// hold no locks and update no shared state while hashing, as a budget
+/// running out in between poisons the lock or leaves the state half-updated.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Budget {
+    max_hash_calls: Option<usize>,
+    max_duration: Option<Duration>,
+}
+
+impl Budget {
+    pub fn with_max_hash_calls(self, max_hash_calls: usize) -> Self {
+        Self { max_hash_calls: Some(max_hash_calls), ..self }
+    }
+
+    pub fn with_max_duration(self, max_duration: Duration) -> Self {
+        Self { max_duration: Some(max_duration), ..self }
+    }
+
+    pub fn with_clock<C: Clock + Clone + 'static>(self, clock: C) -> ClockedBudget<C> {
+        ClockedBudget { budget: self, clock }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BudgetError {
+    HashCalls { max: usize },
+    Deadline { max: Duration },
+}
+
+impl fmt::Display for BudgetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::HashCalls { max } => write!(f, "operation needs more than {} hashes", max),
+            Self::Deadline { max } => write!(f, "operation takes longer than {:?}", max),
+        }
+    }
+}
+
+impl Error for BudgetError {}
+
+// What's left of the budget of the innermost budgeted operation
+struct Remaining {
+    hash_calls: Option<usize>,
+    deadline: Option<(Instant, Box<dyn Clock>)>,
+    budget: Budget,
+}
+
+thread_local! {
+    static REMAINING: RefCell<Option<Remaining>> = const { RefCell::new(None) };
+}
+
+/// Counts a hash against the budget of the running operation, if any,
+/// abandoning it by unwinding once the budget is exceeded
+pub(crate) fn charge() {
+    // The thread-local may be gone while the thread is being torn down
+    let exceeded = REMAINING.try_with(|remaining| {
+        let mut remaining = remaining.borrow_mut();
+        let remaining = remaining.as_mut()?;
+        if let Some(hash_calls) = &mut remaining.hash_calls {
+            match hash_calls.checked_sub(1) {
+                Some(left) => *hash_calls = left,
+                None => return Some(BudgetError::HashCalls { max: remaining.budget.max_hash_calls.unwrap() }),
+            }
+        }
+        match &remaining.deadline {
+            Some((deadline, clock)) if clock.now() > *deadline =>
+                Some(BudgetError::Deadline { max: remaining.budget.max_duration.unwrap() }),
+            _ => None,
+        }
+    });
+
+    // Unwinds without calling the panic hook, as this isn't a bug
+    if let Ok(Some(err)) = exceeded {
+        panic::resume_unwind(Box::new(err));
+    }
+}
+
+/// A `Budget` measuring its deadline against `C` rather than the system
+/// clock, made by `Budget::with_clock`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ClockedBudget<C> {
+    budget: Budget,
+    clock: C,
+}
+
+impl<C: Clock + Clone + 'static> ClockedBudget<C> {
+    pub(crate) fn run<T>(&self, f: impl FnOnce() -> T) -> Result<T, BudgetError> {
+        run_within(self.budget, &self.clock, f)
+    }
+}
+
+/// Runs `f` within `budget`, or returns how it was exceeded
+fn run_within<T, C>(budget: Budget, clock: &C, f: impl FnOnce() -> T) -> Result<T, BudgetError>
+    where C: Clock + Clone + 'static {
+    let deadline = budget.max_duration
+        .map(|max| (clock.now() + max, Box::new(clock.clone()) as Box<dyn Clock>));
+    let remaining = Remaining { hash_calls: budget.max_hash_calls, deadline, budget };
+    let outer = REMAINING.with(|cell| cell.replace(Some(remaining)));
+
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+
+    REMAINING.with(|cell| cell.replace(outer));
+    match result {
+        Ok(result) => Ok(result),
+        Err(payload) => match payload.downcast::<BudgetError>() {
+            Ok(err) => Err(*err),
+            Err(payload) => panic::resume_unwind(payload),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use crate::{Error, Estimate, SignatureScheme, U256};
+    use crate::layer::Layered;
+    use crate::merkle::Merkle;
+    use crate::util::{hash, record_hashes};
+    use crate::winternitz::Winternitz;
+
+    use super::*;
+
+    // Advances by a millisecond whenever it is read
+    #[derive(Clone)]
+    struct TickingClock(Rc<Cell<Instant>>);
+
+    impl Clock for TickingClock {
+        fn now(&self) -> Instant {
+            let now = self.0.get() + Duration::from_millis(1);
+            self.0.set(now);
+            now
+        }
+    }
+
+    #[test]
+    fn it_works() {
+        let msg = b"My OS update";
+        let winternitz = Winternitz::new(16);
+        let (private, public) = winternitz.gen_keys(None);
+        let sig = winternitz.sign(msg, &private);
+
+        let ample = Layered::new(winternitz, Budget::default().with_max_hash_calls(winternitz.verify_hashes()));
+        assert_eq!(ample.try_verify(msg, &public, &sig), Ok(()));
+        assert_eq!(ample.try_verify(b"Another update", &public, &sig), Err(Error::InvalidSignature));
+
+        let tight = Layered::new(winternitz, Budget::default().with_max_hash_calls(10));
+        assert!(!tight.verify(msg, &public, &sig));
+        assert_eq!(tight.try_verify(msg, &public, &sig), Err(Error::OverBudget(BudgetError::HashCalls { max: 10 })));
+        assert!(matches!(tight.try_sign(msg, &private), Err(Error::OverBudget(_))));
+
+        // Outside of the layer, nothing is counted any more
+        assert!(winternitz.verify(msg, &public, &sig));
+    }
+
+    #[test]
+    fn inner_checks() {
+        let merkle = Merkle::new(2, Winternitz::new(16));
+        let (private, _) = merkle.gen_keys(None);
+
+        // The scheme's own checks still apply within the budget
+        let budgeted = Layered::new(merkle, Budget::default().with_max_hash_calls(1 << 20));
+        assert_eq!(budgeted.try_sign(b"My OS update", &(private.0, 4)).err(), Some(Error::MalformedKey));
+    }
+
+    #[test]
+    fn deadline() {
+        let msg = b"My OS update";
+        let merkle = || Merkle::new(4, Winternitz::new(16));
+        let (private, public) = merkle().gen_keys(None);
+        let sig = merkle().sign(msg, &private);
+
+        let max = Duration::from_millis(50);
+        let clock = TickingClock(Rc::new(Cell::new(Instant::now())));
+        let budget = Budget::default().with_max_duration(max);
+        let budgeted = Layered::new(merkle(), budget.with_clock(clock));
+        assert_eq!(budgeted.try_verify(msg, &public, &sig), Err(Error::OverBudget(BudgetError::Deadline { max })));
+
+        let budgeted = Layered::new(merkle(), budget.with_clock(SystemClock));
+        assert!(budgeted.verify(msg, &public, &sig));
+    }
+
+    #[test]
+    fn unwinds_out_of_recording() {
+        let winternitz = Winternitz::new(16);
+        let (private, public) = winternitz.gen_keys(None);
+        let sig = winternitz.sign(b"My OS update", &private);
+
+        // Hashes after the exceeded budget are recorded by the outer recording
+        let budget = Budget::default().with_max_hash_calls(10).with_clock(SystemClock);
+        let (exceeded, calls) = record_hashes(|| {
+            let exceeded = budget.run(|| record_hashes(|| winternitz.verify(b"My OS update", &public, &sig)));
+            hash(b"After the budget");
+            exceeded
+        });
+        assert!(exceeded.is_err());
+        assert_eq!(calls.len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "unrelated")]
+    fn other_panics() {
+        struct Panicking;
+
+        impl SignatureScheme for Panicking {
+            type Private = ();
+            type Public = ();
+            type Signature = ();
+
+            fn gen_keys(&self, _: Option<U256>) -> ((), ()) {
+                ((), ())
+            }
+
+            fn sign(&self, _: &[u8], _: &()) {}
+
+            fn verify(&self, _: &[u8], _: &(), _: &()) -> bool {
+                panic!("unrelated")
+            }
+        }
+
+        Layered::new(Panicking, Budget::default().with_max_hash_calls(0)).verify(b"", &(), &());
+    }
+}
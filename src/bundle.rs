@@ -0,0 +1,98 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{SignatureScheme, U256};
+use crate::bech32::{self, DecodeError};
+use crate::spec::{SchemeSpec, SpecError};
+
+/// Everything needed to verify signatures under a key with a 32-byte public
+/// key, for printed trust anchors and QR codes. It is written as bech32 with
+/// the scheme spec as the prefix, e.g.
+/// `merkle-wots16-h10` `1` `<root>` `<checksum>`, so the checksum covers the
+/// parameters as well as the root.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Bundle {
+    spec: SchemeSpec,
+    public: U256,
+}
+
+impl Bundle {
+    pub fn new(spec: SchemeSpec, public: U256) -> Self {
+        Self { spec, public }
+    }
+
+    pub fn spec(&self) -> &SchemeSpec {
+        &self.spec
+    }
+
+    pub fn public(&self) -> &U256 {
+        &self.public
+    }
+
+    /// Builds the scheme to verify with, which must match the spec
+    pub fn verifier<S>(&self) -> Result<S, SpecError>
+        where S: SignatureScheme<Public = U256> + for<'a> TryFrom<&'a SchemeSpec, Error = SpecError> {
+        S::try_from(&self.spec)
+    }
+}
+
+impl fmt::Display for Bundle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&bech32::encode_with_hrp(&self.spec.to_string(), &self.public))
+    }
+}
+
+impl FromStr for Bundle {
+    type Err = DecodeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (spec, public) = bech32::decode_with_hrp(s)?;
+        let spec = spec.parse()
+            .map_err(|err: SpecError| DecodeError(err.to_string()))?;
+
+        Ok(Self { spec, public })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::horst::Horst;
+    use crate::merkle::Merkle;
+    use crate::sphincs::Sphincs;
+    use crate::winternitz::Winternitz;
+
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let msg = b"My OS update";
+
+        let spec: SchemeSpec = "sphincs-d2-h2-wots16-horst-t256-k64".parse().unwrap();
+        let sphincs: Sphincs<Winternitz, Horst> = Sphincs::try_from(&spec).unwrap();
+        let (private, public) = sphincs.gen_keys(None);
+        let sig = sphincs.sign(msg, &private);
+
+        let printed = Bundle::new(spec, public).to_string();
+        assert!(printed.starts_with("sphincs-d2-h2-wots16-horst-t256-k641"));
+
+        let bundle: Bundle = printed.parse().unwrap();
+        let verifier: Sphincs<Winternitz, Horst> = bundle.verifier().unwrap();
+        assert!(verifier.verify(msg, bundle.public(), &sig));
+        assert!(bundle.verifier::<Merkle<Winternitz>>().is_err());
+
+        // Typos in the parameters are caught by the checksum too
+        assert!(printed.replacen("d2", "d3", 1).parse::<Bundle>().is_err());
+    }
+
+    #[test]
+    fn presets_fit_in_qr_code() {
+        // The default parameters and Sphincs::preset_small_sig and preset_fast_verify
+        for spec in &["sphincs-d12-h5-wots16-horst-t65536-k32", "sphincs-d3-h10-wots256-horst-t65536-k32",
+                      "sphincs-d6-h10-wots4-horst-t65536-k32"] {
+            let bundle = Bundle::new(spec.parse().unwrap(), [0xff; 32]);
+            assert!(bundle.to_string().len() < 2048);
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use crate::{SignatureScheme, U256};
+use crate::framing::{Encode, Reader};
+use crate::util::{hash, hash_pair};
+
+/// A public key of scheme `N` signed by a key of scheme `O`, e.g. to migrate
+/// trust from a Merkle key to a SPHINCS key. Encoded as the new key followed
+/// by the signature, each as their schemes encode them.
+pub struct Certificate<O: SignatureScheme, N: SignatureScheme> {
+    public: N::Public,
+    sig: O::Signature,
+}
+
+fn cert_msg(public: &[u8]) -> U256 {
+    hash_pair(b"certified public key", hash(public))
+}
+
+impl<O: SignatureScheme, N: SignatureScheme> Certificate<O, N>
+    where <N as SignatureScheme>::Public: AsRef<[u8]> {
+    pub fn public(&self) -> &N::Public {
+        &self.public
+    }
+
+    pub fn verify(&self, old_scheme: &O, old_public: &O::Public) -> bool {
+        old_scheme.verify(&cert_msg(self.public.as_ref()), old_public, &self.sig)
+    }
+}
+
+impl<O: Encode, N: Encode> Certificate<O, N> {
+    pub fn to_bytes(&self, old_scheme: &O, new_scheme: &N) -> Vec<u8> {
+        let mut bytes = new_scheme.encode_public(&self.public);
+        old_scheme.write_signature(&self.sig, &mut bytes);
+        bytes
+    }
+
+    /// Parses a certificate of a key of `new_scheme` by one of `old_scheme`,
+    /// or returns `None` if it is malformed. The signature is only checked
+    /// by `verify`.
+    pub fn from_bytes(old_scheme: &O, new_scheme: &N, bytes: &[u8]) -> Option<Self> {
+        let mut input = Reader::new(bytes);
+        let public = new_scheme.read_public(&mut input).ok()?;
+        let sig = old_scheme.decode_signature(input.rest()).ok()?;
+
+        Some(Self { public, sig })
+    }
+}
+
+/// Certifies `new_public` with the old key. For stateful schemes, the old
+/// private key must be advanced afterwards like after any other signature.
+pub fn cross_sign<O, N>(old_scheme: &O, old_private: &O::Private, new_public: N::Public) -> Certificate<O, N>
+    where O: SignatureScheme, N: SignatureScheme, <N as SignatureScheme>::Public: AsRef<[u8]> {
+    let sig = old_scheme.sign(&cert_msg(new_public.as_ref()), old_private);
+    Certificate { public: new_public, sig }
+}
+
+/// Verifies a signature made by a new key, trusting only the old public key
+/// and following a single cross-signature.
+pub fn verify_cross_signed<O, N>(old_scheme: &O, old_public: &O::Public, cert: &Certificate<O, N>,
+                                 new_scheme: &N, msg: &[u8], sig: &N::Signature) -> bool
+    where O: SignatureScheme, N: SignatureScheme, <N as SignatureScheme>::Public: AsRef<[u8]> {
+    cert.verify(old_scheme, old_public) && new_scheme.verify(msg, &cert.public, sig)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::horst::Horst;
+    use crate::lamport::Lamport;
+    use crate::merkle::Merkle;
+    use crate::sphincs::Sphincs;
+    use crate::winternitz::Winternitz;
+
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let msg = b"My OS update";
+
+        let merkle = Merkle::new(3, Winternitz::new(16));
+        let (old_private, old_public) = merkle.gen_keys(None);
+
+        let sphincs = Sphincs::new(3, 3, Winternitz::new(16), Horst::new(8, 64));
+        let (new_private, new_public) = sphincs.gen_keys(None);
+
+        let cert = cross_sign(&merkle, &old_private, new_public);
+        assert!(cert.verify(&merkle, &old_public));
+
+        let sig = sphincs.sign(msg, &new_private);
+        assert!(verify_cross_signed(&merkle, &old_public, &cert, &sphincs, msg, &sig));
+        assert!(!verify_cross_signed(&merkle, &old_public, &cert, &sphincs, b"Another update", &sig));
+
+        let (_, other_public) = merkle.gen_keys(None);
+        assert!(!verify_cross_signed(&merkle, &other_public, &cert, &sphincs, msg, &sig));
+    }
+
+    #[test]
+    fn encoding() {
+        let merkle = Merkle::new(3, Winternitz::new(16));
+        let (old_private, old_public) = merkle.gen_keys(None);
+        let lamport = Lamport::new(32);
+        let (_, new_public) = lamport.gen_keys(None);
+
+        let bytes = cross_sign(&merkle, &old_private, new_public).to_bytes(&merkle, &lamport);
+        let cert = Certificate::from_bytes(&merkle, &lamport, &bytes).unwrap();
+        assert_eq!(cert.to_bytes(&merkle, &lamport), bytes);
+        assert!(cert.verify(&merkle, &old_public));
+
+        assert!(Certificate::from_bytes(&merkle, &lamport, &bytes[..bytes.len() - 1]).is_none());
+        assert!(Certificate::from_bytes(&merkle, &Lamport::new(16), &bytes).is_none());
+    }
+}
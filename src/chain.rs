@@ -0,0 +1,370 @@
+use std::any::Any;
+use std::error::Error;
+use std::fmt;
+
+use crate::U256;
+use crate::framing::{write_len, Encode, Reader};
+use crate::ota::ManifestCache;
+use crate::registry::Registry;
+use crate::spec::{SchemeKind, SchemeSpec, SpecError};
+use crate::util::{hash, hash_pair};
+
+/// A key in a chain of trust, certified by the key of the link before it, or
+/// by the root for the first link. Its scheme can differ from the issuer's.
+/// The key and certificate are kept encoded, and decoded with the schemes
+/// the `ChainVerifier` builds from the registry.
+///
+/// On the wire, the spec string, the key and the certificate, each preceded
+/// by its length as a little-endian `u64`.
+pub struct Link {
+    spec: SchemeSpec,
+    public: Box<[u8]>,
+    cert: Box<[u8]>,
+}
+
+impl Link {
+    pub fn spec(&self) -> &SchemeSpec {
+        &self.spec
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let spec = self.spec.to_string();
+        let mut bytes = Vec::new();
+        for part in [spec.as_bytes(), &self.public, &self.cert] {
+            write_len(&mut bytes, part.len());
+            bytes.extend_from_slice(part);
+        }
+        bytes
+    }
+
+    /// Parses a link, or returns `None` if it is malformed or its spec
+    /// doesn't parse. The key and certificate are only decoded on
+    /// verification.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut input = Reader::new(bytes);
+        let mut part = || {
+            let len = input.len(input.rest().len()).ok()?;
+            input.take(len).ok()
+        };
+
+        let spec = std::str::from_utf8(part()?).ok()?.parse().ok()?;
+        let public = part()?.into();
+        let cert = part()?.into();
+
+        input.rest().is_empty().then_some(Self { spec, public, cert })
+    }
+}
+
+// The spec is certified along with the key, so a link can't be passed off as
+// one of another scheme
+fn link_msg(spec: &SchemeSpec, public: &[u8]) -> U256 {
+    hash_pair(b"certified chain link", [&spec.parameter_digest()[..], &hash(public)].concat())
+}
+
+// What a cache remembers a chain by: the root key it is certified under and
+// the bytes of its links
+fn chain_digest(root_spec: &SchemeSpec, root_public: &[u8], links: &[Link]) -> U256 {
+    let spec = root_spec.to_string();
+    let mut bytes = Vec::new();
+    for part in [spec.as_bytes(), root_public] {
+        write_len(&mut bytes, part.len());
+        bytes.extend_from_slice(part);
+    }
+    for link in links {
+        let link = link.to_bytes();
+        write_len(&mut bytes, link.len());
+        bytes.extend_from_slice(&link);
+    }
+    hash_pair(b"cached chain", bytes)
+}
+
+/// Certifies `public`, a key of the scheme described by `spec`, with the
+/// issuer's key. For stateful issuers, the private key must be advanced
+/// afterwards like after any other signature.
+pub fn certify<I: Encode>(issuer: &I, issuer_private: &I::Private, spec: SchemeSpec, public: impl AsRef<[u8]>) -> Link {
+    let public = public.as_ref();
+    let cert = issuer.sign(&link_msg(&spec, public), issuer_private);
+    Link { spec, public: public.into(), cert: issuer.encode_signature(&cert).into() }
+}
+
+
+/// Requirements on the links of a chain
+#[derive(Clone, Copy, Debug)]
+pub struct ChainPolicy {
+    max_links: usize,
+    intermediate_kind: Option<SchemeKind>,
+    leaf_kind: Option<SchemeKind>,
+}
+
+impl Default for ChainPolicy {
+    fn default() -> Self {
+        Self { max_links: 4, intermediate_kind: None, leaf_kind: None }
+    }
+}
+
+impl ChainPolicy {
+    pub fn with_max_links(mut self, max_links: usize) -> Self {
+        self.max_links = max_links;
+        self
+    }
+
+    /// Requires every link but the last to be of this kind
+    pub fn with_intermediate_kind(mut self, kind: SchemeKind) -> Self {
+        self.intermediate_kind = Some(kind);
+        self
+    }
+
+    /// Requires the last link, whose key signs the message, to be of this
+    /// kind, e.g. one-time for keys minted per message
+    pub fn with_leaf_kind(mut self, kind: SchemeKind) -> Self {
+        self.leaf_kind = Some(kind);
+        self
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ChainError {
+    Empty,
+    TooLong { len: usize, max: usize },
+    UnknownScheme(SpecError),
+    /// The scheme of the link at this index isn't allowed there
+    Policy { link: usize, kind: SchemeKind },
+    BadCertificate { link: usize },
+    BadSignature,
+}
+
+impl fmt::Display for ChainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "chain has no links"),
+            Self::TooLong { len, max } => write!(f, "chain has {} links, more than the {} allowed", len, max),
+            Self::UnknownScheme(err) => write!(f, "{}", err),
+            Self::Policy { link, kind } => write!(f, "link {} is a {:?} key, which the policy forbids there", link, kind),
+            Self::BadCertificate { link } => write!(f, "certificate of link {} is invalid", link),
+            Self::BadSignature => write!(f, "invalid signature"),
+        }
+    }
+}
+
+impl Error for ChainError {}
+
+/// Verifies signatures made at the end of chains whose links may each use a
+/// different scheme, building the scheme of every link from the registry.
+pub struct ChainVerifier {
+    registry: Registry,
+    policy: ChainPolicy,
+}
+
+impl ChainVerifier {
+    pub fn new(registry: Registry, policy: ChainPolicy) -> Self {
+        Self { registry, policy }
+    }
+
+    /// Verifies `sig` on `msg` by the key of the last link, trusting only the
+    /// root key
+    pub fn verify(&self, root_spec: &SchemeSpec, root_public: &dyn Any, links: &[Link], msg: &[u8],
+                  sig: &dyn Any) -> Result<(), ChainError> {
+        self.check(root_spec, root_public, links, msg, sig, None)
+    }
+
+    /// Like `verify`, but skips checking the certificates of chains found in
+    /// `cache`, and adds the chain to it once they check out. Chains are
+    /// cached by their root key and link bytes; the policy and the signature
+    /// are checked every time.
+    #[allow(clippy::too_many_arguments)]
+    pub fn verify_cached(&self, root_spec: &SchemeSpec, root_public: &dyn Any, links: &[Link], msg: &[u8],
+                         sig: &dyn Any, cache: &mut ManifestCache, now: u64) -> Result<(), ChainError> {
+        self.check(root_spec, root_public, links, msg, sig, Some((cache, now)))
+    }
+
+    fn check(&self, root_spec: &SchemeSpec, root_public: &dyn Any, links: &[Link], msg: &[u8], sig: &dyn Any,
+             cache: Option<(&mut ManifestCache, u64)>) -> Result<(), ChainError> {
+        if links.is_empty() {
+            return Err(ChainError::Empty);
+        }
+        if links.len() > self.policy.max_links {
+            return Err(ChainError::TooLong { len: links.len(), max: self.policy.max_links });
+        }
+
+        let mut issuer = self.registry.build(root_spec).map_err(ChainError::UnknownScheme)?;
+        // A root key of the wrong type can't be cached, and fails below anyway
+        let cache = cache.and_then(|(cache, now)| {
+            let digest = chain_digest(root_spec, &issuer.public_bytes(root_public)?, links);
+            Some((cache.contains(&digest, now), cache, digest, now))
+        });
+        let cached = cache.as_ref().is_some_and(|&(cached, ..)| cached);
+
+        let mut issuer_public = None;
+        for (i, link) in links.iter().enumerate() {
+            let required = if i + 1 == links.len() { self.policy.leaf_kind } else { self.policy.intermediate_kind };
+            let kind = link.spec.kind();
+            if required.is_some_and(|required| required != kind) {
+                return Err(ChainError::Policy { link: i, kind });
+            }
+
+            let scheme = self.registry.build(&link.spec).map_err(ChainError::UnknownScheme)?;
+            let public = scheme.decode_public(&link.public).ok_or(ChainError::BadCertificate { link: i })?;
+            if !cached {
+                let cert = issuer.decode_signature(&link.cert).ok_or(ChainError::BadCertificate { link: i })?;
+                let certified_by = issuer_public.as_deref().unwrap_or(root_public);
+                if !issuer.verify(&link_msg(&link.spec, &link.public), certified_by, &*cert) {
+                    return Err(ChainError::BadCertificate { link: i });
+                }
+            }
+
+            issuer = scheme;
+            issuer_public = Some(public);
+        }
+        if let Some((false, cache, digest, now)) = cache {
+            cache.insert(digest, now);
+        }
+
+        if issuer.verify(msg, issuer_public.as_deref().unwrap_or(root_public), sig) {
+            Ok(())
+        } else {
+            Err(ChainError::BadSignature)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use crate::SignatureScheme;
+    use crate::horst::Horst;
+    use crate::merkle::Merkle;
+    use crate::sphincs::Sphincs;
+    use crate::util::record_hashes;
+    use crate::winternitz::Winternitz;
+
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let msg = b"My OS update";
+
+        let root_spec: SchemeSpec = "merkle-wots16-h3".parse().unwrap();
+        let merkle = Merkle::<Winternitz>::try_from(&root_spec).unwrap();
+        let (root_private, root_public) = merkle.gen_keys(None);
+
+        let sphincs_spec: SchemeSpec = "sphincs-d2-h2-wots16-horst-t256-k64".parse().unwrap();
+        let sphincs = Sphincs::<Winternitz, Horst>::try_from(&sphincs_spec).unwrap();
+        let (sphincs_private, sphincs_public) = sphincs.gen_keys(None);
+
+        let winternitz = Winternitz::new(16);
+        let (leaf_private, leaf_public) = winternitz.gen_keys(None);
+        let sig = winternitz.sign(msg, &leaf_private);
+
+        let links = [
+            certify(&merkle, &root_private, sphincs_spec, sphincs_public),
+            certify(&sphincs, &sphincs_private, "wots16".parse().unwrap(), leaf_public),
+        ];
+
+        let policy = ChainPolicy::default()
+            .with_intermediate_kind(SchemeKind::Stateless)
+            .with_leaf_kind(SchemeKind::OneTime);
+        let verifier = ChainVerifier::new(Registry::default(), policy);
+        assert_eq!(verifier.verify(&root_spec, &root_public, &links, msg, &sig), Ok(()));
+        assert_eq!(verifier.verify(&root_spec, &root_public, &links, b"Another update", &sig),
+                   Err(ChainError::BadSignature));
+        assert_eq!(verifier.verify(&root_spec, &root_public, &links[..1], msg, &sig),
+                   Err(ChainError::Policy { link: 0, kind: SchemeKind::Stateless }));
+
+        let (_, other_public) = merkle.gen_keys(None);
+        assert_eq!(verifier.verify(&root_spec, &other_public, &links, msg, &sig),
+                   Err(ChainError::BadCertificate { link: 0 }));
+
+        let verifier = ChainVerifier::new(Registry::default(), policy.with_max_links(1));
+        assert_eq!(verifier.verify(&root_spec, &root_public, &links, msg, &sig),
+                   Err(ChainError::TooLong { len: 2, max: 1 }));
+    }
+
+    #[test]
+    fn substituted_spec() {
+        let msg = b"My OS update";
+
+        let root_spec: SchemeSpec = "merkle-wots16-h3".parse().unwrap();
+        let merkle = Merkle::new(3, Winternitz::new(16));
+        let (root_private, root_public) = merkle.gen_keys(None);
+
+        let winternitz = Winternitz::new(16);
+        let (leaf_private, leaf_public) = winternitz.gen_keys(None);
+        let sig = winternitz.sign(msg, &leaf_private);
+
+        let mut link = certify(&merkle, &root_private, "wots16".parse().unwrap(), leaf_public);
+        let verifier = ChainVerifier::new(Registry::default(), ChainPolicy::default());
+        assert_eq!(verifier.verify(&root_spec, &root_public, std::slice::from_ref(&link), msg, &sig), Ok(()));
+
+        link.spec = "wots4".parse().unwrap();
+        assert_eq!(verifier.verify(&root_spec, &root_public, &[link], msg, &sig),
+                   Err(ChainError::BadCertificate { link: 0 }));
+    }
+
+    #[test]
+    fn encoding() {
+        let msg = b"My OS update";
+
+        let root_spec: SchemeSpec = "merkle-wots16-h3".parse().unwrap();
+        let merkle = Merkle::new(3, Winternitz::new(16));
+        let (root_private, root_public) = merkle.gen_keys(None);
+
+        let winternitz = Winternitz::new(16);
+        let (leaf_private, leaf_public) = winternitz.gen_keys(None);
+        let sig = winternitz.sign(msg, &leaf_private);
+
+        let bytes = certify(&merkle, &root_private, "wots16".parse().unwrap(), leaf_public).to_bytes();
+        let link = Link::from_bytes(&bytes).unwrap();
+        assert_eq!(link.to_bytes(), bytes);
+        let verifier = ChainVerifier::new(Registry::default(), ChainPolicy::default());
+        assert_eq!(verifier.verify(&root_spec, &root_public, &[link], msg, &sig), Ok(()));
+
+        assert!(Link::from_bytes(&bytes[..bytes.len() - 1]).is_none());
+        assert!(Link::from_bytes(&[bytes.as_slice(), &[0]].concat()).is_none());
+
+        // A certificate the issuer's scheme can't decode
+        let mut link = Link::from_bytes(&bytes).unwrap();
+        link.cert = link.cert[1..].into();
+        assert_eq!(verifier.verify(&root_spec, &root_public, &[link], msg, &sig),
+                   Err(ChainError::BadCertificate { link: 0 }));
+    }
+
+    #[test]
+    fn cached() {
+        let msg = b"My OS update";
+
+        let root_spec: SchemeSpec = "merkle-wots16-h3".parse().unwrap();
+        let merkle = Merkle::new(3, Winternitz::new(16));
+        let (root_private, root_public) = merkle.gen_keys(None);
+
+        let winternitz = Winternitz::new(16);
+        let (leaf_private, leaf_public) = winternitz.gen_keys(None);
+        let sig = winternitz.sign(msg, &leaf_private);
+        let links = [certify(&merkle, &root_private, "wots16".parse().unwrap(), leaf_public)];
+
+        let verifier = ChainVerifier::new(Registry::default(), ChainPolicy::default());
+        let mut cache = ManifestCache::new(4, 100);
+        let verify = |cache: &mut ManifestCache, now, msg: &[u8]| {
+            record_hashes(|| verifier.verify_cached(&root_spec, &root_public, &links, msg, &sig, cache, now))
+        };
+        let (result, uncached) = verify(&mut cache, 1000, msg);
+        assert_eq!(result, Ok(()));
+
+        // Only the signature is verified again, and always
+        let (result, cached) = verify(&mut cache, 1050, msg);
+        assert_eq!(result, Ok(()));
+        assert!(cached.len() < uncached.len());
+        assert_eq!(verify(&mut cache, 1050, b"Another update").0, Err(ChainError::BadSignature));
+        assert_eq!(verify(&mut cache, 1100, msg).1.len(), uncached.len());
+
+        // Another root or altered links miss the cache
+        let (_, other_public) = merkle.gen_keys(None);
+        assert_eq!(verifier.verify_cached(&root_spec, &other_public, &links, msg, &sig, &mut cache, 1100),
+                   Err(ChainError::BadCertificate { link: 0 }));
+        let mut link = Link::from_bytes(&links[0].to_bytes()).unwrap();
+        link.cert = link.cert[1..].into();
+        assert_eq!(verifier.verify_cached(&root_spec, &root_public, &[link], msg, &sig, &mut cache, 1100),
+                   Err(ChainError::BadCertificate { link: 0 }));
+    }
+}
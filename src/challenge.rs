@@ -0,0 +1,55 @@
+use rand::prelude::{Rng, SeedableRng, StdRng};
+
+use crate::{SignatureScheme, U256};
+use crate::util::hash_pair;
+
+/// A fresh random challenge, picked by the verifier for each authentication
+pub fn new_challenge() -> U256 {
+    StdRng::from_entropy().gen()
+}
+
+// Labelled, so a response can't be passed off as a signature on a message
+// someone chose to look like a challenge, or vice versa
+fn challenge_msg(challenge: &U256, context: &[u8]) -> U256 {
+    hash_pair(b"challenge response", [&challenge[..], context].concat())
+}
+
+/// Proves possession of `private` by signing the verifier's challenge.
+/// `context`, e.g. the verifier's name, keeps a response from being relayed
+/// to a different verifier that happens to send the same challenge.
+pub fn sign_challenge<S: SignatureScheme>(scheme: &S, private: &S::Private, challenge: &U256,
+                                          context: &[u8]) -> S::Signature {
+    scheme.sign(&challenge_msg(challenge, context), private)
+}
+
+pub fn verify_challenge<S: SignatureScheme>(scheme: &S, public: &S::Public, challenge: &U256, context: &[u8],
+                                            sig: &S::Signature) -> bool {
+    scheme.verify(&challenge_msg(challenge, context), public, sig)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::horst::Horst;
+    use crate::sphincs::Sphincs;
+    use crate::winternitz::Winternitz;
+
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let sphincs = Sphincs::new(2, 2, Winternitz::new(16), Horst::new(8, 64));
+        let (private, public) = sphincs.gen_keys(None);
+
+        let challenge = new_challenge();
+        let response = sign_challenge(&sphincs, &private, &challenge, b"door 3");
+        assert!(verify_challenge(&sphincs, &public, &challenge, b"door 3", &response));
+
+        assert!(!verify_challenge(&sphincs, &public, &new_challenge(), b"door 3", &response));
+        assert!(!verify_challenge(&sphincs, &public, &challenge, b"door 4", &response));
+
+        // Nor does a plain signature on the challenge count as a response
+        let plain = sphincs.sign(&challenge, &private);
+        assert!(!verify_challenge(&sphincs, &public, &challenge, b"", &plain));
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::registry::{self, Registry};
+use crate::spec::{SchemeSpec, SpecError};
+
+/// One named scheme of a setup, e.g.
+///
+/// ```toml
+/// [schemes.firmware]
+/// spec = "merkle-wots16-h10"
+/// public_key = "keys/firmware.pub"
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SchemeConfig {
+    spec: SchemeSpec,
+    public_key: Option<PathBuf>,
+    private_key: Option<PathBuf>,
+}
+
+impl SchemeConfig {
+    pub fn spec(&self) -> &SchemeSpec {
+        &self.spec
+    }
+
+    pub fn public_key(&self) -> Option<&Path> {
+        self.public_key.as_deref()
+    }
+
+    pub fn private_key(&self) -> Option<&Path> {
+        self.private_key.as_deref()
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigError(String);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid config: {}", self.0)
+    }
+}
+
+impl Error for ConfigError {}
+
+
+/// The schemes a signer or verifier is set up with, by name
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    schemes: BTreeMap<String, SchemeConfig>,
+}
+
+impl Config {
+    pub fn from_toml(s: &str) -> Result<Self, ConfigError> {
+        let config: Self = toml::from_str(s).map_err(|err| ConfigError(err.to_string()))?;
+        for (name, scheme) in &config.schemes {
+            if scheme.public_key.is_none() && scheme.private_key.is_none() {
+                return Err(ConfigError(format!("scheme '{}' has neither a public nor a private key", name)));
+            }
+        }
+
+        Ok(config)
+    }
+
+    pub fn scheme(&self, name: &str) -> Option<&SchemeConfig> {
+        self.schemes.get(name)
+    }
+
+    fn spec_of(&self, name: &str) -> Result<&SchemeSpec, ConfigError> {
+        self.scheme(name)
+            .map(|scheme| &scheme.spec)
+            .ok_or_else(|| ConfigError(format!("no scheme named '{}'", name)))
+    }
+
+    /// Builds the scheme configured as `name`, which must match its spec
+    pub fn build<S>(&self, name: &str) -> Result<S, ConfigError>
+        where S: for<'a> TryFrom<&'a SchemeSpec, Error = SpecError> {
+        S::try_from(self.spec_of(name)?).map_err(|err| ConfigError(format!("scheme '{}': {}", name, err)))
+    }
+
+    /// Builds the scheme configured as `name` with whichever type of
+    /// `registry` its spec matches, for when the type isn't known up front
+    pub fn build_erased(&self, registry: &Registry, name: &str) -> Result<Box<dyn registry::ErasedScheme>, ConfigError> {
+        registry.build(self.spec_of(name)?).map_err(|err| ConfigError(format!("scheme '{}': {}", name, err)))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::merkle::Merkle;
+    use crate::winternitz::Winternitz;
+    use crate::SignatureScheme;
+
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let config = Config::from_toml(r#"
+            [schemes.firmware]
+            spec = "merkle-wots16-h3"
+            public_key = "keys/firmware.pub"
+            private_key = "keys/firmware.key"
+
+            [schemes.ephemeral]
+            spec = "wots16"
+            public_key = "keys/ephemeral.pub"
+        "#).unwrap();
+
+        let firmware = config.scheme("firmware").unwrap();
+        assert_eq!(firmware.private_key(), Some(Path::new("keys/firmware.key")));
+
+        let merkle: Merkle<Winternitz> = config.build("firmware").unwrap();
+        let (private, public) = merkle.gen_keys(None);
+        assert!(merkle.verify(b"My OS update", &public, &merkle.sign(b"My OS update", &private)));
+
+        assert!(config.build::<Merkle<Winternitz>>("ephemeral").is_err());
+        assert!(config.build::<Winternitz>("missing").is_err());
+    }
+
+    #[test]
+    fn build_erased() {
+        let config = Config::from_toml(r#"
+            [schemes.firmware]
+            spec = "merkle-wots16-h3"
+            public_key = "keys/firmware.pub"
+
+            [schemes.legacy]
+            spec = "goldreich-lamport-n64-h8"
+            public_key = "keys/legacy.pub"
+        "#).unwrap();
+
+        let merkle = Merkle::new(3, Winternitz::new(16));
+        let (private, public) = merkle.gen_keys(None);
+        let sig = merkle.sign(b"My OS update", &private);
+        let erased = config.build_erased(&Registry::default(), "firmware").unwrap();
+        assert!(erased.verify(b"My OS update", &public, &sig));
+
+        // Only the registered types are built
+        let err = config.build_erased(&Registry::default(), "legacy").err().unwrap();
+        assert!(err.to_string().contains("no registered scheme"), "{}", err);
+        assert!(config.build_erased(&Registry::empty(), "firmware").is_err());
+        assert!(config.build_erased(&Registry::default(), "missing").is_err());
+    }
+
+    #[test]
+    fn invalid() {
+        let err = Config::from_toml("[schemes.a]\nspec = \"wots15\"\npublic_key = \"a.pub\"").unwrap_err();
+        assert!(err.to_string().contains("Winternitz w must be a power of two"), "{}", err);
+
+        assert!(Config::from_toml("[schemes.a]\nspec = \"wots16\"").is_err());
+        assert!(Config::from_toml("[schemes.a]\nspec = \"wots16\"\npublic_key = \"a.pub\"\nkey = 1").is_err());
+    }
+}
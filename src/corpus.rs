@@ -0,0 +1,128 @@
+//! Known-answer corpus for external audits, letting alternative
+//! implementations check themselves against this crate without reading its
+//! internals. Everything is generated from a fixed seed, with the randomized
+//! schemes in deterministic mode, so the corpus is the same on every run.
+//!
+//! The corpus is a JSON object:
+//!
+//! * `format`: `"crypto-audit-corpus-v1"`
+//! * `hash`: the hash function of every scheme, `"sha256"`
+//! * `schemes`: one object per scheme, with
+//!   * `spec`: the `SchemeSpec` string giving the scheme and parameters
+//!   * `parameter_digest`: `SchemeSpec::parameter_digest` of the spec
+//!   * `seed`: the seed passed to `gen_keys`
+//!   * `message`: the signed message
+//!   * `private`, `public`, `signature`: the keys and signature in their
+//!     serde encoding, with byte strings as arrays of numbers
+//!   * `verification`: every hash computed while verifying, in order, as
+//!     objects with an `input` and an `output`. These are the message
+//!     digests, chain steps and tree levels an implementation has to match.
+//!
+//! Byte strings outside of the serde encoding are lowercase hex.
+
+use std::convert::TryFrom;
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::{SignatureScheme, SigningMode, U256};
+use crate::goldreich::Goldreich;
+use crate::horst::Horst;
+use crate::lamport::Lamport;
+use crate::merkle::Merkle;
+use crate::spec::{SchemeSpec, SpecError};
+use crate::sphincs::Sphincs;
+use crate::util::{record_hashes, to_hex};
+use crate::winternitz::Winternitz;
+
+pub const FORMAT: &str = "crypto-audit-corpus-v1";
+
+const SEED: U256 = [0x42; 32];
+const MSG: &[u8] = b"Audit corpus";
+
+pub struct Corpus {
+    schemes: Vec<Value>,
+}
+
+impl Corpus {
+    /// The entries of the `schemes` array
+    pub fn schemes(&self) -> &[Value] {
+        &self.schemes
+    }
+
+    pub fn to_json(&self) -> String {
+        let corpus = json!({
+            "format": FORMAT,
+            "hash": "sha256",
+            "schemes": self.schemes,
+        });
+        serde_json::to_string(&corpus).expect("corpus is valid JSON")
+    }
+}
+
+fn entry<S>(spec: &str, configure: impl FnOnce(S) -> S) -> Value
+    where S: SignatureScheme + for<'a> TryFrom<&'a SchemeSpec, Error = SpecError>,
+          S::Private: Serialize, S::Public: Serialize, S::Signature: Serialize {
+    let spec: SchemeSpec = spec.parse().expect("corpus specs are valid");
+    let scheme = configure(S::try_from(&spec).expect("corpus specs match their schemes"));
+
+    let (private, public) = scheme.gen_keys(Some(SEED));
+    let sig = scheme.sign(MSG, &private);
+    let (valid, calls) = record_hashes(|| scheme.verify(MSG, &public, &sig));
+    assert!(valid, "corpus signature for '{}' doesn't verify", spec);
+
+    let verification: Vec<_> = calls.iter()
+        .map(|call| json!({ "input": to_hex(&call.input), "output": to_hex(&call.output) }))
+        .collect();
+
+    json!({
+        "spec": spec.to_string(),
+        "parameter_digest": to_hex(&spec.parameter_digest()),
+        "seed": to_hex(&SEED),
+        "message": to_hex(MSG),
+        "private": private,
+        "public": public,
+        "signature": sig,
+        "verification": verification,
+    })
+}
+
+/// Generates the corpus for every scheme, with small parameters to keep it
+/// readable
+pub fn generate() -> Corpus {
+    let deterministic = SigningMode::Deterministic;
+    let schemes = vec![
+        entry::<Lamport>("lamport-n32", |lamport| lamport),
+        entry::<Winternitz>("wots16", |winternitz| winternitz),
+        entry::<Horst>("horst-t256-k32", |horst| horst),
+        entry::<Merkle<Winternitz>>("merkle-wots16-h3", |merkle| merkle),
+        entry::<Goldreich<Lamport>>("goldreich-lamport-n64-h4", |goldreich| goldreich.with_mode(deterministic)),
+        entry::<Sphincs<Winternitz, Horst>>("sphincs-d2-h2-wots16-horst-t256-k64",
+                                            |sphincs| sphincs.with_mode(deterministic)),
+    ];
+
+    Corpus { schemes }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::util::{hash_in, Domain};
+
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let corpus = generate();
+        assert_eq!(corpus.to_json(), generate().to_json());
+
+        let json: Value = serde_json::from_str(&corpus.to_json()).unwrap();
+        assert_eq!(json["format"], FORMAT);
+        assert_eq!(json["schemes"].as_array().unwrap().len(), 6);
+
+        // Winternitz verification starts with the message digest
+        let winternitz = &corpus.schemes()[1];
+        assert_eq!(winternitz["spec"], "wots16");
+        assert_eq!(winternitz["verification"][0]["output"], to_hex(&hash_in(Domain::Message, MSG)));
+    }
+}
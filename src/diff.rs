@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+
+use crate::U256;
+use crate::tree::{MerkleTree, Proof};
+
+/// A record along with its inclusion proof in a manifest tree
+pub struct Entry<T> {
+    record: T,
+    proof: Proof,
+}
+
+impl<T> Entry<T> {
+    pub fn record(&self) -> &T {
+        &self.record
+    }
+
+    fn verify(&self, leaf_hash: &impl Fn(&T) -> U256, root: &U256) -> bool {
+        self.proof.verify_record(&self.record, leaf_hash, root)
+    }
+}
+
+/// The entries added, removed and changed between two manifests, whose
+/// records are committed to in trees built with `MerkleTree::from_records`.
+/// After checking the signatures on both manifest roots, a client can check
+/// every entry of the diff against them and fetch only what changed.
+///
+/// Inclusion proofs show that each entry is genuine, not that the diff is
+/// complete.
+pub struct ManifestDiff<T> {
+    added: Vec<Entry<T>>,
+    removed: Vec<Entry<T>>,
+    changed: Vec<(Entry<T>, Entry<T>)>,
+}
+
+impl<T: Clone> ManifestDiff<T> {
+    /// Matches records of the two manifests up by `key`, e.g. their path
+    pub fn new<K: Ord>(old: &[T], new: &[T], key: impl Fn(&T) -> K, leaf_hash: impl Fn(&T) -> U256) -> Self {
+        let old_tree = MerkleTree::from_records(old, &leaf_hash);
+        let new_tree = MerkleTree::from_records(new, &leaf_hash);
+        let entry = |tree: &MerkleTree, records: &[T], idx: usize| Entry {
+            record: records[idx].clone(),
+            proof: tree.prove(idx),
+        };
+
+        let mut old_by_key: BTreeMap<_, _> = old.iter().enumerate().map(|(idx, record)| (key(record), idx)).collect();
+        let mut diff = Self { added: Vec::new(), removed: Vec::new(), changed: Vec::new() };
+        for (new_idx, record) in new.iter().enumerate() {
+            match old_by_key.remove(&key(record)) {
+                None => diff.added.push(entry(&new_tree, new, new_idx)),
+                Some(old_idx) if old_tree.leaf(old_idx) != new_tree.leaf(new_idx) =>
+                    diff.changed.push((entry(&old_tree, old, old_idx), entry(&new_tree, new, new_idx))),
+                Some(_) => {}
+            }
+        }
+        for old_idx in old_by_key.into_values() {
+            diff.removed.push(entry(&old_tree, old, old_idx));
+        }
+
+        diff
+    }
+}
+
+impl<T> ManifestDiff<T> {
+    pub fn added(&self) -> impl Iterator<Item = &T> {
+        self.added.iter().map(Entry::record)
+    }
+
+    pub fn removed(&self) -> impl Iterator<Item = &T> {
+        self.removed.iter().map(Entry::record)
+    }
+
+    /// Pairs of old and new records
+    pub fn changed(&self) -> impl Iterator<Item = (&T, &T)> {
+        self.changed.iter().map(|(old, new)| (old.record(), new.record()))
+    }
+
+    pub fn verify(&self, old_root: &U256, new_root: &U256, leaf_hash: impl Fn(&T) -> U256) -> bool {
+        self.added.iter().all(|entry| entry.verify(&leaf_hash, new_root))
+            && self.removed.iter().all(|entry| entry.verify(&leaf_hash, old_root))
+            && self.changed.iter().all(|(old, new)| old.verify(&leaf_hash, old_root) && new.verify(&leaf_hash, new_root))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::tree::leaf_hash;
+
+    use super::*;
+
+    fn record_hash(record: &(&str, &str)) -> U256 {
+        leaf_hash([record.0, "\0", record.1].concat())
+    }
+
+    #[test]
+    fn it_works() {
+        let old = [("bin/app", "v1"), ("etc/app.conf", "v1"), ("README", "v1")];
+        let new = [("bin/app", "v2"), ("README", "v1"), ("lib/plugin.so", "v1")];
+
+        let diff = ManifestDiff::new(&old, &new, |record| record.0, record_hash);
+        assert_eq!(diff.added().collect::<Vec<_>>(), [&("lib/plugin.so", "v1")]);
+        assert_eq!(diff.removed().collect::<Vec<_>>(), [&("etc/app.conf", "v1")]);
+        assert_eq!(diff.changed().collect::<Vec<_>>(), [(&("bin/app", "v1"), &("bin/app", "v2"))]);
+
+        let old_root = MerkleTree::from_records(&old, record_hash).root();
+        let new_root = MerkleTree::from_records(&new, record_hash).root();
+        assert!(diff.verify(&old_root, &new_root, record_hash));
+        assert!(!diff.verify(&new_root, &old_root, record_hash));
+    }
+}
@@ -0,0 +1,132 @@
+use std::convert::TryInto;
+
+use crate::util::{hash, hash_pair};
+
+// Message id, index and count before the payload, and a tag after it
+const HEADER_LEN: usize = 8;
+const TAG_LEN: usize = 4;
+
+/// A piece of a payload too large for a constrained transport. On the wire,
+/// it is a 4-byte id of the whole payload, its index and the number of
+/// fragments as big-endian `u16`s, the piece itself and a 4-byte tag catching
+/// corruption of the fragment.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fragment {
+    id: [u8; 4],
+    index: u16,
+    count: u16,
+    payload: Box<[u8]>,
+}
+
+impl Fragment {
+    pub fn index(&self) -> usize {
+        self.index as usize
+    }
+
+    pub fn count(&self) -> usize {
+        self.count as usize
+    }
+
+    fn header(&self) -> [u8; HEADER_LEN] {
+        let mut header = [0; HEADER_LEN];
+        header[..4].copy_from_slice(&self.id);
+        header[4..6].copy_from_slice(&self.index.to_be_bytes());
+        header[6..].copy_from_slice(&self.count.to_be_bytes());
+        header
+    }
+
+    fn tag(&self) -> [u8; TAG_LEN] {
+        hash_pair(self.header(), &self.payload)[..TAG_LEN].try_into().unwrap()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [&self.header()[..], &self.payload, &self.tag()].concat()
+    }
+
+    /// Parses a fragment, or returns `None` if it was corrupted
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < HEADER_LEN + TAG_LEN {
+            return None;
+        }
+
+        let (payload, tag) = bytes[HEADER_LEN..].split_at(bytes.len() - HEADER_LEN - TAG_LEN);
+        let fragment = Self {
+            id: bytes[..4].try_into().unwrap(),
+            index: u16::from_be_bytes(bytes[4..6].try_into().unwrap()),
+            count: u16::from_be_bytes(bytes[6..8].try_into().unwrap()),
+            payload: payload.into(),
+        };
+
+        (fragment.index < fragment.count && fragment.tag() == tag).then_some(fragment)
+    }
+}
+
+/// Splits `data`, e.g. an encoded signature, into fragments of at most `mtu`
+/// bytes on the wire
+pub fn fragment(data: &[u8], mtu: usize) -> Vec<Fragment> {
+    assert!(mtu > HEADER_LEN + TAG_LEN);
+
+    let chunks: Vec<_> = data.chunks(mtu - HEADER_LEN - TAG_LEN).collect();
+    let count: u16 = chunks.len().max(1).try_into().expect("too many fragments");
+    let id = hash(data)[..4].try_into().unwrap();
+    if chunks.is_empty() {
+        return vec![Fragment { id, index: 0, count, payload: Box::new([]) }];
+    }
+
+    chunks.into_iter()
+        .enumerate()
+        .map(|(index, chunk)| Fragment { id, index: index as u16, count, payload: chunk.into() })
+        .collect()
+}
+
+/// Puts the fragments of a payload back together, in any order. Returns
+/// `None` if some are missing or belong to another payload.
+pub fn reassemble(mut fragments: Vec<Fragment>) -> Option<Vec<u8>> {
+    fragments.sort_by_key(|fragment| fragment.index);
+    fragments.dedup_by_key(|fragment| fragment.index);
+
+    let first = fragments.first()?;
+    let (id, count) = (first.id, first.count);
+    let complete = fragments.len() == count as usize
+        && fragments.iter().all(|fragment| fragment.id == id && fragment.count == count);
+    if !complete {
+        return None;
+    }
+
+    let data: Vec<u8> = fragments.iter().flat_map(|fragment| fragment.payload.iter().copied()).collect();
+    (hash(&data)[..4] == id).then_some(data)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let data: Vec<u8> = (0..5000).map(|i| i as u8).collect();
+
+        let mut fragments = fragment(&data, 240);
+        assert_eq!(fragments.len(), 22);
+        assert!(fragments.iter().all(|fragment| fragment.to_bytes().len() <= 240));
+
+        fragments.reverse();
+        let received = fragments.iter()
+            .map(|fragment| Fragment::from_bytes(&fragment.to_bytes()).unwrap())
+            .collect();
+        assert_eq!(reassemble(received), Some(data.clone()));
+
+        let mut corrupted = fragments[3].to_bytes();
+        corrupted[20] ^= 1;
+        assert!(Fragment::from_bytes(&corrupted).is_none());
+
+        fragments.pop();
+        assert!(reassemble(fragments.clone()).is_none());
+
+        let other = fragment(&data[1..], 240);
+        fragments.push(other[0].clone());
+        assert!(reassemble(fragments).is_none());
+
+        assert_eq!(reassemble(fragment(&[], 240)), Some(Vec::new()));
+    }
+}
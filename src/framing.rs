@@ -0,0 +1,272 @@
+//! Length framing for encoded signatures, so a transport delivering a partial
+//! payload is caught as such before anything is decoded, and the encodings
+//! themselves, whose decoders know from the parameters how many bytes to
+//! expect.
+
+use std::convert::TryInto;
+
+use crate::{Error, SignatureScheme};
+use crate::util::Node;
+
+/// The total length of the frame, header included, as a little-endian `u64`
+pub const HEADER_LEN: usize = 8;
+
+/// Prefixes an encoded signature with the total length of the frame
+pub fn frame(sig: &[u8]) -> Vec<u8> {
+    let len = (HEADER_LEN + sig.len()) as u64;
+    [&len.to_le_bytes()[..], sig].concat()
+}
+
+/// Splits the encoded signature of the frame at the start of `bytes` from
+/// whatever follows it
+pub fn unframe(bytes: &[u8]) -> Result<(&[u8], &[u8]), Error> {
+    if bytes.len() < HEADER_LEN {
+        return Err(Error::Truncated { len: bytes.len(), expected: HEADER_LEN });
+    }
+
+    let expected = u64::from_le_bytes(bytes[..HEADER_LEN].try_into().unwrap());
+    if expected < HEADER_LEN as u64 {
+        return Err(Error::InvalidSignature);
+    }
+    if expected > bytes.len() as u64 {
+        let expected = expected.try_into().unwrap_or(usize::MAX);
+        return Err(Error::Truncated { len: bytes.len(), expected });
+    }
+
+    let (frame, rest) = bytes.split_at(expected as usize);
+    Ok((&frame[HEADER_LEN..], rest))
+}
+
+/// Verifies the framed signature in `bytes`, which must hold exactly one
+/// frame. `decode` is only given complete signatures, and returns `None` for
+/// malformed ones.
+pub fn verify_framed<S: SignatureScheme>(scheme: &S, msg: &[u8], public: &S::Public, bytes: &[u8],
+                                         decode: impl FnOnce(&[u8]) -> Option<S::Signature>) -> Result<(), Error> {
+    let (sig, rest) = unframe(bytes)?;
+    if !rest.is_empty() {
+        return Err(Error::InvalidSignature);
+    }
+
+    let sig = decode(sig).ok_or(Error::InvalidSignature)?;
+    scheme.try_verify(msg, public, &sig)
+}
+
+/// Like `verify_framed`, decoding with the scheme's own `Encode` impl, so a
+/// frame holding fewer bytes than the parameters call for is `Truncated` too
+pub fn verify_encoded<S: Encode>(scheme: &S, msg: &[u8], public: &S::Public, bytes: &[u8]) -> Result<(), Error> {
+    let (sig, rest) = unframe(bytes)?;
+    if !rest.is_empty() {
+        return Err(Error::InvalidSignature);
+    }
+
+    let sig = scheme.decode_signature(sig)?;
+    scheme.try_verify(msg, public, &sig)
+}
+
+
+/// Reads encoded keys and signatures front to back
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+
+    /// Whatever hasn't been read yet
+    pub fn rest(&self) -> &'a [u8] {
+        &self.bytes[self.pos..]
+    }
+
+    pub fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        let end = self.pos.saturating_add(len);
+        if end > self.bytes.len() {
+            return Err(Error::Truncated { len: self.bytes.len(), expected: end });
+        }
+
+        let taken = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(taken)
+    }
+
+    pub fn u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// A length written by `write_len`, which must be at most `max`
+    pub fn len(&mut self, max: usize) -> Result<usize, Error> {
+        match self.u64()?.try_into() {
+            Ok(len) if len <= max => Ok(len),
+            _ => Err(Error::InvalidSignature),
+        }
+    }
+
+    pub fn node<N: Node>(&mut self) -> Result<N, Error> {
+        let mut node = N::default();
+        node.as_mut().copy_from_slice(self.take(N::LEN)?);
+        Ok(node)
+    }
+
+    pub fn nodes<N: Node>(&mut self, count: usize) -> Result<Box<[N]>, Error> {
+        let len = count.saturating_mul(N::LEN);
+        Ok(self.take(len)?.chunks_exact(N::LEN)
+            .map(|bytes| {
+                let mut node = N::default();
+                node.as_mut().copy_from_slice(bytes);
+                node
+            })
+            .collect())
+    }
+}
+
+pub fn write_len(out: &mut Vec<u8>, len: usize) {
+    out.extend_from_slice(&(len as u64).to_le_bytes());
+}
+
+pub fn write_nodes<N: Node>(out: &mut Vec<u8>, nodes: &[N]) {
+    nodes.iter().for_each(|node| out.extend_from_slice(node.as_ref()));
+}
+
+
+/// A byte encoding of a scheme's signatures and public keys. Only parts
+/// whose size varies between signatures carry a length, bounded by the
+/// parameters; the rest follows from the parameters, so the decoders fail
+/// with `Truncated` when the input ends before they do. Public keys that are
+/// `AsRef<[u8]>` encode as exactly those bytes.
+pub trait Encode: SignatureScheme {
+    fn write_signature(&self, sig: &Self::Signature, out: &mut Vec<u8>);
+
+    fn read_signature(&self, input: &mut Reader) -> Result<Self::Signature, Error>;
+
+    fn write_public(&self, public: &Self::Public, out: &mut Vec<u8>);
+
+    fn read_public(&self, input: &mut Reader) -> Result<Self::Public, Error>;
+
+    fn encode_signature(&self, sig: &Self::Signature) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.write_signature(sig, &mut out);
+        out
+    }
+
+    /// The inverse of `encode_signature`, rejecting trailing bytes
+    fn decode_signature(&self, bytes: &[u8]) -> Result<Self::Signature, Error> {
+        let mut input = Reader::new(bytes);
+        let sig = self.read_signature(&mut input)?;
+        if !input.rest().is_empty() {
+            return Err(Error::InvalidSignature);
+        }
+
+        Ok(sig)
+    }
+
+    fn encode_public(&self, public: &Self::Public) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.write_public(public, &mut out);
+        out
+    }
+
+    fn decode_public(&self, bytes: &[u8]) -> Result<Self::Public, Error> {
+        let mut input = Reader::new(bytes);
+        let public = self.read_public(&mut input)?;
+        if !input.rest().is_empty() {
+            return Err(Error::InvalidSignature);
+        }
+
+        Ok(public)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::goldreich::Goldreich;
+    use crate::horst::Horst;
+    use crate::lamport::Lamport;
+    use crate::merkle::Merkle;
+    use crate::sphincs::Sphincs;
+    use crate::util::Sha512;
+    use crate::winternitz::{CompressedWinternitz, Key, Winternitz, WotsPlus};
+
+    use super::*;
+
+    fn round_trip<S: Encode>(scheme: &S, msg: &[u8]) {
+        let (private, public) = scheme.gen_keys(Some([1; 32]));
+        let public = scheme.decode_public(&scheme.encode_public(&public)).unwrap();
+        let encoded = scheme.encode_signature(&scheme.sign(msg, &private));
+        assert_eq!(verify_encoded(scheme, msg, &public, &frame(&encoded)), Ok(()));
+
+        // Correctly framed, but shorter than the parameters call for
+        for len in (0..encoded.len()).step_by(encoded.len() / 50 + 1) {
+            let result = verify_encoded(scheme, msg, &public, &frame(&encoded[..len]));
+            assert!(matches!(result, Err(Error::Truncated { len: l, expected }) if l == len && expected > len));
+        }
+        let extended = [encoded.as_slice(), &[0]].concat();
+        assert_eq!(verify_encoded(scheme, msg, &public, &frame(&extended)), Err(Error::InvalidSignature));
+    }
+
+    #[test]
+    fn it_works() {
+        let msg = b"My OS update";
+        let winternitz = Winternitz::new(16);
+        let (private, public) = winternitz.gen_keys(None);
+        let framed = frame(winternitz.sign(msg, &private).as_ref());
+        assert_eq!(framed.len(), HEADER_LEN + 67 * 32);
+
+        let verify = |bytes: &[u8]| verify_framed(&winternitz, msg, &public, bytes, Key::from_bytes);
+        assert_eq!(verify(&framed), Ok(()));
+        assert_eq!(verify(&framed[..1000]), Err(Error::Truncated { len: 1000, expected: framed.len() }));
+        assert_eq!(verify(&framed[..3]), Err(Error::Truncated { len: 3, expected: HEADER_LEN }));
+        assert_eq!(verify(&[framed.as_slice(), &[0]].concat()), Err(Error::InvalidSignature));
+        assert_eq!(verify(&frame(&[0; 67 * 32])), Err(Error::InvalidSignature));
+    }
+
+    #[test]
+    fn encodings() {
+        let msg = b"My OS update";
+        round_trip(&Lamport::new(16), msg);
+        round_trip(&Winternitz::new(16), msg);
+        round_trip(&CompressedWinternitz::new(16), msg);
+        round_trip(&WotsPlus::new(16), msg);
+        round_trip(&Horst::new(8, 64), msg);
+        round_trip(&Horst::new(8, 64).with_hasher::<Sha512>(), msg);
+        round_trip(&Merkle::new(3, Winternitz::new(16)), msg);
+        round_trip(&Merkle::new(3, Lamport::new(16)).with_hasher::<Sha512>(), msg);
+        round_trip(&Goldreich::new(4, Lamport::new(64)), msg);
+        round_trip(&Sphincs::new(2, 2, Winternitz::new(16), Horst::new(8, 64)), msg);
+    }
+
+    #[test]
+    fn bounded_lengths() {
+        // A Lamport signature of more bytes than the scheme signs
+        let lamport = Lamport::new(16);
+        let mut encoded = vec![0; 8 + 17 * 8 * 32];
+        encoded[0] = 17;
+        assert!(matches!(lamport.decode_signature(&encoded), Err(Error::InvalidSignature)));
+        encoded[0] = 16;
+        assert!(lamport.decode_signature(&encoded[..8 + 16 * 8 * 32]).is_ok());
+
+        // More authentication nodes than a HORST signature can have
+        let horst = Horst::new(8, 64);
+        let mut encoded = vec![0; 64 * 32 + 8];
+        encoded[64 * 32..].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(horst.decode_signature(&encoded), Err(Error::InvalidSignature)));
+    }
+
+    #[test]
+    fn stream() {
+        let bytes = [frame(b"first"), frame(b""), frame(b"third")].concat();
+
+        let (first, rest) = unframe(&bytes).unwrap();
+        let (second, rest) = unframe(rest).unwrap();
+        assert_eq!((first, second), (&b"first"[..], &b""[..]));
+        assert_eq!(unframe(&rest[..10]), Err(Error::Truncated { len: 10, expected: 13 }));
+
+        let mut bogus = frame(b"");
+        bogus[0] = 7;
+        assert_eq!(unframe(&bogus), Err(Error::InvalidSignature));
+        bogus[..HEADER_LEN].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(unframe(&bogus), Err(Error::Truncated { len: 8, .. })));
+    }
+}
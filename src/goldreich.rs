@@ -1,41 +1,144 @@
-use rand::prelude::{Rng, SeedableRng, StdRng};
-use rug::Integer;
-use rug::integer::Order;
-use rug::rand::RandState;
+use std::marker::PhantomData;
 
-use crate::{SignatureScheme, U256};
-use crate::util::hash_pair;
+use rand::CryptoRng;
+use rand::prelude::{Rng, RngCore, SeedableRng, StdRng};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
+use crate::{Error, Estimate, SignatureScheme, SigningMode, U256};
+use crate::framing::{Encode, Reader};
+use crate::selftest::Composition;
+use crate::util::{accepts_len, Domain, Hasher, Node, Sha256};
+
+type PathNode<O> = (<O as SignatureScheme>::Public, <O as SignatureScheme>::Public, <O as SignatureScheme>::Signature);
+
+/// Leaf indices are little-endian, so trees can be up to 256 levels high
+pub const MAX_TREE_HEIGHT: usize = 256;
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound(serialize = "O::Public: Serialize, O::Signature: Serialize",
+                                              deserialize = "O::Public: Deserialize<'de>, O::Signature: Deserialize<'de>")))]
 pub struct Signature<O: SignatureScheme> {
-    leaf_idx: Integer,
-    path: Box<[(O::Public, O::Public, O::Signature)]>,
+    leaf_idx: U256,
+    path: Box<[PathNode<O>]>,
+}
+
+impl<O: SignatureScheme> Signature<O> {
+    /// The little-endian index of the leaf among the bottom level nodes
+    pub fn leaf_idx(&self) -> &U256 {
+        &self.leaf_idx
+    }
+}
+
+// Clears all but the lowest `bits` bits of a little-endian index
+fn truncate(idx: &mut U256, bits: usize) {
+    for (i, byte) in idx.iter_mut().enumerate() {
+        let kept = bits.saturating_sub(8 * i);
+        if kept < 8 {
+            *byte &= (1u8 << kept).wrapping_sub(1);
+        }
+    }
+}
+
+fn parent(idx: &mut U256) {
+    let mut carry = 0;
+    for byte in idx.iter_mut().rev() {
+        let low = *byte & 1;
+        *byte = (*byte >> 1) | (carry << 7);
+        carry = low;
+    }
 }
 
 
-pub struct Goldreich<O> {
+pub struct Goldreich<O, H = Sha256> {
     tree_height: usize,
     ots_scheme: O,
+    mode: SigningMode,
+    hasher: PhantomData<H>,
 }
 
-impl<O: SignatureScheme> Goldreich<O>
+impl<O: SignatureScheme, H: Hasher> Goldreich<O, H>
     where <O as SignatureScheme>::Public: AsRef<[u8]> + Clone + PartialEq {
-    fn get_node(&self, private: <Self as SignatureScheme>::Private, idx: &Integer) -> (O::Private, O::Public) {
-        let node_seed = hash_pair(&private, &idx.to_digits(Order::Lsf));
+    // `idx` is the index of the node among those at `level`, with the root
+    // at level 0
+    fn get_node(&self, private: <Self as SignatureScheme>::Private, level: usize, idx: &U256) -> (O::Private, O::Public) {
+        let node_seed = H::hash_pair(private, [&(level as u16).to_le_bytes()[..], idx].concat()).truncate();
         self.ots_scheme.gen_keys(Some(node_seed))
     }
+
+    /// Signs with the leaf picked by `rng`, e.g. a DRBG, whatever the mode
+    pub fn sign_with_rng(&self, msg: &[u8], private: &U256, rng: &mut (impl RngCore + CryptoRng)) -> Signature<O> {
+        self.sign_at(msg, private, rng.gen())
+    }
+
+    // Signs with the leaf indexed by the lowest bits of `leaf_idx`, taken
+    // straight from the PRF or RNG output so they alone pick the leaf
+    fn sign_at(&self, msg: &[u8], private: &U256, mut leaf_idx: U256) -> Signature<O> {
+        truncate(&mut leaf_idx, self.tree_height);
+
+        let mut path = Vec::with_capacity(self.tree_height);
+        let mut idx = leaf_idx;
+        let mut hash: Box<[u8]> = msg.into();
+        for level in (1..=self.tree_height).rev() {
+            let is_right = idx[0] & 1 == 1;
+            let (mut left, mut right) = (idx, idx);
+            left[0] &= !1;
+            right[0] |= 1;
+            let (left_private, left_public) = self.get_node(*private, level, &left);
+            let (right_private, right_public) = self.get_node(*private, level, &right);
+
+            // The node is one of the siblings, so its keys needn't be derived again
+            let node_private = if is_right { right_private } else { left_private };
+            let sig = self.ots_scheme.sign(&hash, &node_private);
+
+            parent(&mut idx);
+            hash = H::hash_pair_in(Domain::Node, &left_public, &right_public).as_ref().into();
+            path.push((left_public, right_public, sig));
+        }
+
+        Signature {
+            leaf_idx,
+            path: path.into_boxed_slice(),
+        }
+    }
 }
 
 impl<O: SignatureScheme> Goldreich<O> {
     pub fn new(tree_height: usize, ots_scheme: O) -> Self {
-        assert!(tree_height >= 1);
+        assert!((1..=MAX_TREE_HEIGHT).contains(&tree_height));
+        // Inner nodes sign the hash of their children's public keys
+        assert!(accepts_len(&ots_scheme, 32), "OTS can't sign 32-byte hashes");
 
         Self {
-            tree_height, ots_scheme
+            tree_height, ots_scheme, mode: SigningMode::Randomized, hasher: PhantomData
         }
     }
 }
 
-impl<'a, O: SignatureScheme> SignatureScheme for Goldreich<O>
+impl<O: SignatureScheme, H: Hasher> Goldreich<O, H> {
+    /// Hashes with `H2` outside of the OTS scheme, which keeps its own hasher
+    /// and must be able to sign `H2`'s node hashes
+    pub fn with_hasher<H2: Hasher>(self) -> Goldreich<O, H2> {
+        let Self { tree_height, ots_scheme, mode, .. } = self;
+        assert!(accepts_len(&ots_scheme, H2::Node::LEN), "OTS can't sign {}-byte hashes", H2::Node::LEN);
+        Goldreich { tree_height, ots_scheme, mode, hasher: PhantomData }
+    }
+
+    pub fn with_mode(mut self, mode: SigningMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Upper bound on the probability that signing `num_messages` distinct
+    /// messages uses some leaf twice. In deterministic mode, the leaf is a PRF
+    /// of the message, so signing the same message again is always safe.
+    pub fn leaf_collision_probability(&self, num_messages: u64) -> f64 {
+        let pairs = num_messages as f64 * num_messages.saturating_sub(1) as f64 / 2.0;
+        (pairs * 2f64.powi(-(self.tree_height as i32))).min(1.0)
+    }
+}
+
+impl<O: SignatureScheme, H: Hasher> SignatureScheme for Goldreich<O, H>
     where <O as SignatureScheme>::Public: AsRef<[u8]> + Clone + PartialEq {
     type Private = U256;
     type Public = (O::Public, O::Signature);
@@ -47,52 +150,37 @@ impl<'a, O: SignatureScheme> SignatureScheme for Goldreich<O>
             Some(seed) => StdRng::from_seed(seed).gen(),
         };
 
-        let root = self.get_node(private, &Integer::from(0));
-        let left_public = self.get_node(private, &Integer::from(1)).1;
-        let right_public = self.get_node(private, &Integer::from(2)).1;
+        let (left, mut right) = ([0; 32], [0; 32]);
+        right[0] = 1;
+        let root = self.get_node(private, 0, &left);
+        let left_public = self.get_node(private, 1, &left).1;
+        let right_public = self.get_node(private, 1, &right).1;
 
-        let hash = hash_pair(left_public, right_public);
-        let sig = self.ots_scheme.sign(&hash, &root.0);
+        let hash = H::hash_pair_in(Domain::Node, left_public, right_public);
+        let sig = self.ots_scheme.sign(hash.as_ref(), &root.0);
         let public = (root.1, sig);
 
         (private, public)
     }
 
     fn sign(&self, msg: &[u8], private: &Self::Private) -> Self::Signature {
-        let num_leaves = Integer::from(1) << self.tree_height as u32;
-        let mut rand = RandState::new(); // Is this safe?
-        let mut leaf_idx = Integer::random_below(num_leaves.clone(), &mut rand);
-        leaf_idx = leaf_idx + num_leaves - 1;
-
-        let mut path = Vec::new();
-        let mut idx = leaf_idx.clone();
-        let mut hash: Box<[u8]> = msg.into();
-        while idx != 0 {
-            let node = self.get_node(*private, &idx);
-
-            let parent_idx = (idx - 1) / 2;
-            let tmp = Integer::from(&parent_idx * 2);
-            let left_sibling = self.get_node(*private, &Integer::from(&tmp + 1));
-            let right_sibling = self.get_node(*private, &(tmp + 2));
-
-            let sig = self.ots_scheme.sign(&hash, &node.0);
-            path.push((left_sibling.1.clone(), right_sibling.1.clone(), sig));
-
-            idx = parent_idx;
-            hash = hash_pair(left_sibling.1, right_sibling.1).into();
-        }
-
-        Signature {
-            leaf_idx,
-            path: path.into_boxed_slice(),
-        }
+        let leaf_idx = match self.mode {
+            SigningMode::Randomized => StdRng::from_entropy().gen(),
+            SigningMode::Deterministic => H::hash_pair(H::hash_pair(private, b"leaf index"), msg).truncate(),
+        };
+        self.sign_at(msg, private, leaf_idx)
     }
 
     fn verify(&self, msg: &[u8], public: &Self::Public, sig: &Self::Signature) -> bool {
-        let mut idx = sig.leaf_idx.clone();
+        let mut idx = sig.leaf_idx;
+        truncate(&mut idx, self.tree_height);
+        if sig.path.len() != self.tree_height || idx != sig.leaf_idx {
+            return false;
+        }
+
         let mut hash: Box<[u8]> = msg.into();
         for (left_sibling, right_sibling, sig) in sig.path.iter() {
-            let node = if idx.is_even() {
+            let node = if idx[0] & 1 == 1 {
                 // node is a right child
                 right_sibling
             } else {
@@ -104,18 +192,87 @@ impl<'a, O: SignatureScheme> SignatureScheme for Goldreich<O>
                 return false;
             }
 
-            hash = hash_pair(left_sibling, right_sibling).into();
-            idx = (idx - 1) / 2;
+            hash = H::hash_pair_in(Domain::Node, left_sibling, right_sibling).as_ref().into();
+            parent(&mut idx);
         }
 
         self.ots_scheme.verify(&hash, &public.0, &public.1)
     }
+
+    fn max_message_len(&self) -> Option<usize> {
+        self.ots_scheme.max_message_len()
+    }
+}
+
+impl<O: Encode, H: Hasher> Encode for Goldreich<O, H>
+    where <O as SignatureScheme>::Public: AsRef<[u8]> + Clone + PartialEq {
+    /// The leaf index in 32 bytes, then both siblings and the signature at
+    /// each level from the leaf up
+    fn write_signature(&self, sig: &Self::Signature, out: &mut Vec<u8>) {
+        out.extend_from_slice(&sig.leaf_idx);
+        for (left, right, sig) in sig.path.iter() {
+            self.ots_scheme.write_public(left, out);
+            self.ots_scheme.write_public(right, out);
+            self.ots_scheme.write_signature(sig, out);
+        }
+    }
+
+    fn read_signature(&self, input: &mut Reader) -> Result<Self::Signature, Error> {
+        let leaf_idx = input.node()?;
+        let path = (0..self.tree_height)
+            .map(|_| Ok((self.ots_scheme.read_public(input)?, self.ots_scheme.read_public(input)?,
+                         self.ots_scheme.read_signature(input)?)))
+            .collect::<Result<_, Error>>()?;
+
+        Ok(Signature { leaf_idx, path })
+    }
+
+    fn write_public(&self, public: &Self::Public, out: &mut Vec<u8>) {
+        self.ots_scheme.write_public(&public.0, out);
+        self.ots_scheme.write_signature(&public.1, out);
+    }
+
+    fn read_public(&self, input: &mut Reader) -> Result<Self::Public, Error> {
+        Ok((self.ots_scheme.read_public(input)?, self.ots_scheme.read_signature(input)?))
+    }
+}
+
+
+impl<O: Estimate, H: Hasher> Estimate for Goldreich<O, H>
+    where <O as SignatureScheme>::Public: AsRef<[u8]> + Clone + PartialEq {
+    fn public_size(&self) -> usize {
+        self.ots_scheme.public_size() + self.ots_scheme.signature_size()
+    }
+
+    fn signature_size(&self) -> usize {
+        // The leaf index is encoded in full, however short the tree
+        let node_size = 2 * self.ots_scheme.public_size() + self.ots_scheme.signature_size();
+        32 + self.tree_height * node_size
+    }
+
+    fn verify_hashes(&self) -> usize {
+        (self.tree_height + 1) * self.ots_scheme.verify_hashes() + self.tree_height
+    }
 }
 
+impl<O: Estimate, H: Hasher> Composition for Goldreich<O, H>
+    where <O as SignatureScheme>::Public: AsRef<[u8]> + Clone + PartialEq {
+    type Leaf = O;
+
+    fn leaf_scheme(&self) -> &O {
+        &self.ots_scheme
+    }
+
+    fn first_leaf(&self, seed: U256) -> (O::Private, O::Public) {
+        self.get_node(StdRng::from_seed(seed).gen(), self.tree_height, &[0; 32])
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use crate::lamport::Lamport;
+    use crate::util::{hash, to_hex, Sha512, Sha512_256};
+    use crate::winternitz::CompressedWinternitz;
 
     use super::*;
 
@@ -137,4 +294,135 @@ mod tests {
 
         assert!(!goldreich.verify(msg1, &public, &sig));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn hashers() {
+        let msg = b"My OS update";
+
+        let lamport = Lamport::new(64).with_hasher::<Sha512_256>();
+        let goldreich = Goldreich::new(8, lamport).with_hasher::<Sha512_256>();
+        let (private, public) = goldreich.gen_keys(None);
+        let sig = goldreich.sign(msg, &private);
+        assert!(goldreich.verify(msg, &public, &sig));
+
+        // Only the OTS hashes with SHA-512/256 here
+        let mixed = Goldreich::new(8, lamport);
+        assert!(!mixed.verify(msg, &public, &sig));
+    }
+
+    #[test]
+    fn estimate() {
+        let short = Goldreich::new(4, CompressedWinternitz::new(16));
+        let (private, public) = short.gen_keys(None);
+        let sig = short.sign(b"My OS update", &private);
+        assert_eq!(short.encode_signature(&sig).len(), short.signature_size());
+        assert_eq!(short.encode_public(&public).len(), short.public_size());
+
+        let tall = Goldreich::new(12, CompressedWinternitz::new(16)).with_hasher::<Sha512_256>();
+        let (private, _) = tall.gen_keys(None);
+        let sig = tall.sign(b"My OS update", &private);
+        assert_eq!(tall.encode_signature(&sig).len(), tall.signature_size());
+    }
+
+    #[test]
+    fn leaf_collision_probability() {
+        let goldreich = Goldreich::new(20, Lamport::new(64));
+        assert_eq!(goldreich.leaf_collision_probability(1), 0.0);
+        assert_eq!(goldreich.leaf_collision_probability(2), 1.0 / (1 << 20) as f64);
+        assert_eq!(goldreich.leaf_collision_probability(1 << 20), 1.0);
+
+        let goldreich = Goldreich::new(256, Lamport::new(64));
+        assert!(goldreich.leaf_collision_probability(1 << 40) < 1e-50);
+    }
+
+    #[test]
+    fn malformed() {
+        let msg = b"My OS update";
+
+        let goldreich = Goldreich::new(4, Lamport::new(64));
+        let (private, public) = goldreich.gen_keys(None);
+
+        let mut sig = goldreich.sign(msg, &private);
+        sig.leaf_idx[0] |= 1 << 4;
+        assert!(!goldreich.verify(msg, &public, &sig));
+
+        let sig = Signature::<Lamport> { leaf_idx: [0; 32], path: Box::new([]) };
+        assert!(!goldreich.verify(msg, &public, &sig));
+        assert!(!goldreich.verify(&[0; 100], &public, &goldreich.sign(msg, &private)));
+    }
+
+    #[test]
+    fn deterministic() {
+        let msg = b"My OS update";
+
+        let goldreich = Goldreich::new(64, Lamport::new(64))
+            .with_mode(SigningMode::Deterministic);
+        let (private, public) = goldreich.gen_keys(None);
+
+        let sig1 = goldreich.sign(msg, &private);
+        let sig2 = goldreich.sign(msg, &private);
+        assert!(goldreich.verify(msg, &public, &sig1));
+        assert_eq!(sig1.leaf_idx, sig2.leaf_idx);
+
+        // The index is the low bits of the PRF output, without an RNG between
+        let prf = Sha256::hash_pair(Sha256::hash_pair(private, b"leaf index"), msg);
+        assert_eq!(sig1.leaf_idx[..8], prf[..8]);
+        assert_eq!(sig1.leaf_idx[8..], [0; 24]);
+
+        let sig3 = goldreich.sign(b"My important message", &private);
+        assert_ne!(sig1.leaf_idx, sig3.leaf_idx);
+    }
+
+    #[test]
+    fn sign_with_rng() {
+        let msg = b"My OS update";
+
+        let goldreich = Goldreich::new(64, Lamport::new(64));
+        let (private, public) = goldreich.gen_keys(None);
+
+        let sig1 = goldreich.sign_with_rng(msg, &private, &mut StdRng::seed_from_u64(7));
+        let sig2 = goldreich.sign_with_rng(msg, &private, &mut StdRng::seed_from_u64(7));
+        assert!(goldreich.verify(msg, &public, &sig1));
+        assert_eq!(sig1.leaf_idx, sig2.leaf_idx);
+
+        let sig3 = goldreich.sign_with_rng(msg, &private, &mut StdRng::seed_from_u64(8));
+        assert_ne!(sig1.leaf_idx, sig3.leaf_idx);
+    }
+
+    #[test]
+    fn index_helpers() {
+        let mut idx = [0xff; 32];
+        truncate(&mut idx, 12);
+        assert_eq!(idx[..3], [0xff, 0x0f, 0]);
+
+        let mut idx = [0; 32];
+        idx[1] = 1;
+        parent(&mut idx);
+        assert_eq!(idx[..2], [0x80, 0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "OTS can't sign 32-byte hashes")]
+    fn incompatible_ots() {
+        Goldreich::new(8, Lamport::new(16));
+    }
+
+    #[test]
+    fn sha512() {
+        let msg = b"My OS update";
+
+        let goldreich = Goldreich::new(8, Lamport::new(64))
+            .with_mode(SigningMode::Deterministic)
+            .with_hasher::<Sha512>();
+        let (private, public) = goldreich.gen_keys(Some([7; 32]));
+        let sig = goldreich.sign(msg, &private);
+        assert!(goldreich.verify(msg, &public, &sig));
+        assert_eq!(to_hex(&hash(&public.0)), "a86f4b69e74feac86f3433a34e7d7e359c82a0928c02a10e9e841200a7587d4d");
+    }
+
+    #[test]
+    #[should_panic]
+    fn sha512_needs_wide_ots() {
+        Goldreich::new(8, Lamport::new(32)).with_hasher::<Sha512>();
+    }
+}
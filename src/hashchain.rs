@@ -0,0 +1,114 @@
+use crate::U256;
+use crate::util::{hash, hash_n};
+
+/// S/Key style hash chain. The one-time passwords are the chain elements
+/// `hash^(length - 1)(seed), hash^(length - 2)(seed), ..., seed`, each of
+/// which hashes to the one handed out before it.
+pub struct Chain {
+    seed: U256,
+    length: usize,
+    remaining: usize,
+}
+
+impl Chain {
+    pub fn new(seed: U256, length: usize) -> Self {
+        Self {
+            seed,
+            length,
+            remaining: length,
+        }
+    }
+
+    /// The end of the chain, which the server is enrolled with
+    pub fn anchor(&self) -> U256 {
+        hash_n(self.seed, self.length)
+    }
+
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    /// The `idx`th element after the anchor, independent of how many
+    /// passwords have been handed out
+    pub fn element(&self, idx: usize) -> U256 {
+        assert!(idx <= self.length);
+        hash_n(self.seed, self.length - idx)
+    }
+
+    pub fn next_otp(&mut self) -> Option<U256> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        self.remaining -= 1;
+        Some(hash_n(self.seed, self.remaining))
+    }
+}
+
+
+/// Server side of a hash chain, remembering the last accepted password.
+pub struct Verifier {
+    last: U256,
+    remaining: usize,
+}
+
+impl Verifier {
+    pub fn new(anchor: U256, length: usize) -> Self {
+        Self {
+            last: anchor,
+            remaining: length,
+        }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    /// Checks `otp` against the last accepted password and, if it is the
+    /// next one in the chain, accepts it so it can't be replayed.
+    pub fn verify(&mut self, otp: &U256) -> bool {
+        if self.remaining == 0 || hash(otp) != self.last {
+            return false;
+        }
+
+        self.last = *otp;
+        self.remaining -= 1;
+        true
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let mut chain = Chain::new(hash(b"My secret"), 10);
+        let mut verifier = Verifier::new(chain.anchor(), 10);
+
+        let otp1 = chain.next_otp().unwrap();
+        assert!(verifier.verify(&otp1));
+        assert!(!verifier.verify(&otp1));
+
+        let otp2 = chain.next_otp().unwrap();
+        let otp3 = chain.next_otp().unwrap();
+        assert!(!verifier.verify(&otp3));
+        assert!(verifier.verify(&otp2));
+        assert!(verifier.verify(&otp3));
+
+        while let Some(otp) = chain.next_otp() {
+            assert!(verifier.verify(&otp));
+        }
+        assert_eq!(verifier.remaining(), 0);
+        assert!(!verifier.verify(&hash(b"My secret")));
+    }
+}
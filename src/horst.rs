@@ -1,106 +1,246 @@
-use crate::{SignatureScheme, U256};
-use rand::prelude::{StdRng, SeedableRng, RngCore};
-use crate::util::{hash, hash_pair, floored_log};
-use rug::Integer;
-use rug::integer::Order;
+use std::collections::HashMap;
+use std::iter;
+use std::marker::PhantomData;
+
+use crate::{Error, Estimate, SignatureScheme, U256};
+use crate::framing::{write_len, write_nodes, Encode, Reader};
+use rand::prelude::{Rng, StdRng, SeedableRng};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+use crate::util::{base_w, floored_log, Domain, Hasher, Node, Sha256, Sha512};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// The revealed leaves with their merged ("octopus") authentication paths:
+/// nodes shared by several paths, or computable from other revealed leaves,
+/// are left out
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Signature<N = U256> {
+    /// One per message chunk, in message order
+    sks: Box<[N]>,
+    /// The missing siblings, level by level from the leaves up, left to
+    /// right within a level
+    auth: Box<[N]>,
+}
+
+
+/// Options for `Verifier`
+#[derive(Clone, Copy, Debug)]
+pub struct VerifyOptions {
+    cached_keys: usize,
+}
+
+impl Default for VerifyOptions {
+    fn default() -> Self {
+        Self { cached_keys: 16 }
+    }
+}
+
+impl VerifyOptions {
+    /// How many public keys to remember the top nodes of, each taking
+    /// `2^x` nodes. Zero disables the cache.
+    pub fn with_cached_keys(mut self, cached_keys: usize) -> Self {
+        self.cached_keys = cached_keys;
+        self
+    }
+}
+
+
+/// Verifies many signatures, remembering the top nodes already checked
+/// against each public key, so later signatures under the same key skip
+/// hashing them up to the root.
+pub struct Verifier<'a, H: Hasher = Sha256> {
+    horst: &'a Horst<H>,
+    options: VerifyOptions,
+    cache: HashMap<H::Node, Box<[H::Node]>>,
+}
+
+impl<'a, H: Hasher> Verifier<'a, H> {
+    pub fn new(horst: &'a Horst<H>, options: VerifyOptions) -> Self {
+        Self { horst, options, cache: HashMap::new() }
+    }
 
-pub struct Signature {
-    sk: U256,
-    path: Box<[U256]>,
+    pub fn verify(&mut self, msg: &[u8], public: &H::Node, sig: &<Horst<H> as SignatureScheme>::Signature) -> bool {
+        let (_, top_nodes) = sig;
+        if !self.horst.verify_paths(msg, sig) {
+            return false;
+        }
+        if self.cache.get(public) == Some(top_nodes) {
+            return true;
+        }
+        if self.horst.get_root_from_top_nodes(top_nodes) != *public {
+            return false;
+        }
+
+        if self.cache.len() < self.options.cached_keys {
+            self.cache.insert(*public, top_nodes.clone());
+        }
+        true
+    }
 }
 
 
-pub struct Horst {
+/// Key generation and every signature hash all of the 2^height leaves, so
+/// this bounds both to about two million leaf hashes
+pub const MAX_HEIGHT: usize = 20;
+
+pub struct Horst<H = Sha256> {
     height: usize,      // tau
     num_leaves: usize,  // t
     x: usize,           // x
     k: usize,           // k
+    hasher: PhantomData<H>,
+}
+
+/// How many levels the top nodes sent along with a signature span, enough
+/// for one node per revealed leaf. The tree must be at least this high.
+pub fn top_levels(k: usize) -> usize {
+    floored_log(k) + 1 // close enough
 }
 
 impl Horst {
     pub fn new(height: usize, k: usize) -> Self {
+        assert!((1..=MAX_HEIGHT).contains(&height));
+        assert!(top_levels(k) <= height, "HORST with k = {} needs a tree at least {} high", k, top_levels(k));
+
         let num_leaves = 1 << height;
-        let x = floored_log(k) + 1; // close enough
+        let x = top_levels(k);
         Self {
-            height, num_leaves, k, x
+            height, num_leaves, k, x, hasher: PhantomData
         }
     }
+}
+
+impl Horst<Sha512> {
+    /// t = 2^16 and k = 32 as in SPHINCS-256, with 64-byte nodes throughout.
+    /// Public keys are 64 bytes and signatures at most 26,624 bytes, twice
+    /// those of `Horst::new(16, 32)`.
+    pub fn preset_sha512() -> Self {
+        Horst::new(16, 32).with_hasher()
+    }
+}
+
+impl<H: Hasher> Horst<H> {
+    pub fn with_hasher<H2: Hasher>(self) -> Horst<H2> {
+        let Self { height, num_leaves, x, k, .. } = self;
+        Horst { height, num_leaves, x, k, hasher: PhantomData }
+    }
+
+    // The leaf secrets are derived from the private seed on demand, so the
+    // private key stays 32 bytes whatever t is
+    fn get_leaf_secret(private: &U256, idx: usize) -> H::Node {
+        H::hash_pair_in(Domain::Secret, private, (idx as u64).to_le_bytes())
+    }
 
-    fn get_node(private: &<Self as SignatureScheme>::Private, height: usize, idx: usize) -> U256 {
-        if height == 0 {
-            return hash(private[idx]);
+    // Treehash: hashes the leaves under the node left to right, merging
+    // nodes as soon as their sibling is done, so only one node per level is
+    // kept around
+    fn get_node(private: &<Self as SignatureScheme>::Private, height: usize, idx: usize) -> H::Node {
+        let mut stack: Vec<(usize, H::Node)> = Vec::with_capacity(height + 1);
+        for leaf_idx in idx << height..(idx + 1) << height {
+            let mut node = (0, H::hash_in(Domain::Leaf, Self::get_leaf_secret(private, leaf_idx)));
+            while let Some(&(left_height, left)) = stack.last() {
+                if left_height != node.0 {
+                    break;
+                }
+                stack.pop();
+                node = (left_height + 1, H::hash_pair_in(Domain::Node, left, node.1));
+            }
+            stack.push(node);
         }
 
-        let left = Self::get_node(private, height - 1, idx * 2);
-        let right = Self::get_node(private, height - 1, idx * 2 + 1);
+        stack[0].1
+    }
+
+    // The subtrees below the top nodes are independent, so with the rayon
+    // feature they are hashed in parallel
+    fn get_top_nodes(&self, private: &<Self as SignatureScheme>::Private) -> Box<[H::Node]> {
+        let top_nodes_height = self.height - self.x;
+        #[cfg(feature = "rayon")]
+        let indices = (0..1 << self.x).into_par_iter();
+        #[cfg(not(feature = "rayon"))]
+        let indices = 0..1 << self.x;
 
-        hash_pair(left, right)
+        indices
+            .map(|i| Self::get_node(private, top_nodes_height, i))
+            .collect::<Vec<_>>()
+            .into_boxed_slice()
     }
 
-    fn get_path(&self, private: &<Self as SignatureScheme>::Private, leaf_idx: usize) -> Box<[U256]> {
-        let path_len = self.height - self.x;
+    // The siblings on the paths from the leaves to the top nodes that can't
+    // be computed from the leaves themselves, in the order `verify_paths`
+    // consumes them
+    fn get_auth(&self, private: &<Self as SignatureScheme>::Private, leaves: &[usize]) -> Box<[H::Node]> {
+        let mut indices = leaves.to_vec();
+        indices.sort_unstable();
+        indices.dedup();
+
+        let mut missing = Vec::new();
+        for height in 0..self.height - self.x {
+            for (i, &idx) in indices.iter().enumerate() {
+                let known = if idx % 2 == 0 {
+                    indices.get(i + 1) == Some(&(idx + 1))
+                } else {
+                    i > 0 && indices[i - 1] == idx - 1
+                };
+                if !known {
+                    missing.push((height, idx ^ 1));
+                }
+            }
 
-        let mut path = Vec::with_capacity(path_len);
-        let mut idx = leaf_idx;
-        for height in 0..path_len {
-            let sibling_idx = if idx % 2 == 0 {
-                idx + 1
-            } else {
-                idx - 1
-            };
-            path.push(Self::get_node(private, height, sibling_idx));
-
-            idx /= 2;
+            indices.iter_mut().for_each(|idx| *idx /= 2);
+            indices.dedup();
         }
 
-        path.into_boxed_slice()
+        #[cfg(feature = "rayon")]
+        let missing = missing.into_par_iter();
+        #[cfg(not(feature = "rayon"))]
+        let missing = missing.into_iter();
+
+        missing
+            .map(|(height, idx)| Self::get_node(private, height, idx))
+            .collect::<Vec<_>>()
+            .into_boxed_slice()
     }
 
+    // Splits the message into k tau-bit leaf indices, least significant
+    // first, as in the SPHINCS paper
     // TODO: Is it OK to just return zeros, if msg too short?
     fn transform_msg(&self, msg: &[u8]) -> Box<[usize]> {
-        let mut transformed = vec![0; self.k].into_boxed_slice();
-        let mut msg = Integer::from_digits(msg, Order::Lsf);
-        for m in transformed.iter_mut() {
-            *m = msg.mod_u(self.height as u32) as usize;
-            msg /= self.height as u32;
-        }
-
-        transformed
+        base_w(msg, self.height)
+            .chain(iter::repeat(0))
+            .take(self.k)
+            .collect()
     }
 
-    fn get_root_from_top_nodes(&self, top_nodes: &[U256]) -> U256 {
-        fn inner(top_nodes_height: usize, top_nodes: &[U256], height: usize, idx: usize) -> U256 {
+    fn get_root_from_top_nodes(&self, top_nodes: &[H::Node]) -> H::Node {
+        fn inner<H: Hasher>(top_nodes_height: usize, top_nodes: &[H::Node], height: usize, idx: usize) -> H::Node {
             if height == top_nodes_height {
                 return top_nodes[idx];
             }
 
-            let left = inner(top_nodes_height, top_nodes, height - 1, idx * 2);
-            let right = inner(top_nodes_height, top_nodes, height - 1, idx * 2 + 1);
+            let left = inner::<H>(top_nodes_height, top_nodes, height - 1, idx * 2);
+            let right = inner::<H>(top_nodes_height, top_nodes, height - 1, idx * 2 + 1);
 
-            hash_pair(left, right)
+            H::hash_pair_in(Domain::Node, left, right)
         }
 
-        inner(self.height - self.x, top_nodes, self.height, 0)
+        inner::<H>(self.height - self.x, top_nodes, self.height, 0)
     }
 }
 
-impl SignatureScheme for Horst {
-    type Private = Box<[U256]>;
-    type Public = U256;
-    type Signature = (Box<[Signature]>, Box<[U256]>);
+impl<H: Hasher> SignatureScheme for Horst<H> {
+    type Private = U256;
+    type Public = H::Node;
+    type Signature = (Signature<H::Node>, Box<[H::Node]>);
 
     fn gen_keys(&self, seed: Option<U256>) -> (Self::Private, Self::Public) {
-        let mut rng = match seed {
-            None => StdRng::from_entropy(),
-            Some(seed) => StdRng::from_seed(seed),
+        let private = match seed {
+            None => StdRng::from_entropy().gen(),
+            Some(seed) => seed,
         };
 
-        let mut private = vec![[0; 32]; self.num_leaves].into_boxed_slice();
-        for sk in private.iter_mut() {
-            rng.fill_bytes(sk);
-        }
-
-        let public = Self::get_node(&private, self.height, 0);
+        let public = self.get_root_from_top_nodes(&self.get_top_nodes(&private));
 
         (private, public)
     }
@@ -109,56 +249,129 @@ impl SignatureScheme for Horst {
         assert!(msg.len() * 8 <= self.k * self.height);
 
         let msg = self.transform_msg(msg);
+        let signature = Signature {
+            sks: msg.iter().map(|&m| Self::get_leaf_secret(private, m)).collect(),
+            auth: self.get_auth(private, &msg),
+        };
 
-        let mut signature = Vec::with_capacity(self.k);
-        for &m in msg.iter() {
-            let sk = private[m];
-            let path = self.get_path(private, m);
-            let sig = Signature {
-                sk,
-                path
-            };
-            signature.push(sig);
-        }
+        (signature, self.get_top_nodes(private))
+    }
 
-        let top_nodes_len = 1 << self.x;
-        let top_nodes_height = self.height - self.x;
-        let top_nodes = (0..top_nodes_len)
-            .map(|i| Self::get_node(private, top_nodes_height, i))
-            .collect();
+    fn verify(&self, msg: &[u8], public: &Self::Public, sig: &Self::Signature) -> bool {
+        self.verify_paths(msg, sig) && self.get_root_from_top_nodes(&sig.1) == *public
+    }
 
-        (signature.into_boxed_slice(), top_nodes)
+    fn max_message_len(&self) -> Option<usize> {
+        Some(self.k * self.height / 8)
     }
+}
 
-    fn verify(&self, msg: &[u8], public: &Self::Public, sig: &Self::Signature) -> bool {
-        let msg = self.transform_msg(msg);
+impl<H: Hasher> Horst<H> {
+    // Checks the revealed leaves against the top nodes, but not the top nodes
+    // against the public key
+    fn verify_paths(&self, msg: &[u8], sig: &<Self as SignatureScheme>::Signature) -> bool {
         let (signature, top_nodes) = sig;
+        if signature.sks.len() != self.k || top_nodes.len() != 1 << self.x {
+            return false;
+        }
 
-        for (&m, sig) in msg.iter().zip(signature.iter()) {
-            let mut idx = m;
-            let mut node = hash(sig.sk);
-            for &sibling in sig.path.iter() {
-                node = if idx % 2 == 0 {
-                    hash_pair(node, sibling)
-                } else {
-                    hash_pair(sibling, node)
+        let mut leaves: Vec<_> = self.transform_msg(msg).iter().copied().zip(signature.sks.iter().copied()).collect();
+        leaves.sort_unstable();
+        leaves.dedup();
+        // A leaf picked twice must be revealed the same both times
+        if leaves.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+            return false;
+        }
+
+        let mut nodes: Vec<_> = leaves.into_iter()
+            .map(|(idx, sk)| (idx, H::hash_in(Domain::Leaf, sk)))
+            .collect();
+        let mut auth = signature.auth.iter();
+        for _ in 0..self.height - self.x {
+            let mut parents = Vec::with_capacity(nodes.len());
+            let mut i = 0;
+            while i < nodes.len() {
+                let (idx, node) = nodes[i];
+                let sibling = match nodes.get(i + 1) {
+                    Some(&(next, sibling)) if idx % 2 == 0 && next == idx + 1 => {
+                        i += 1;
+                        sibling
+                    }
+                    _ => match auth.next() {
+                        Some(&sibling) => sibling,
+                        None => return false,
+                    },
                 };
 
-                idx /= 2;
+                let parent = if idx % 2 == 0 {
+                    H::hash_pair_in(Domain::Node, node, sibling)
+                } else {
+                    H::hash_pair_in(Domain::Node, sibling, node)
+                };
+                parents.push((idx / 2, parent));
+                i += 1;
             }
 
-            if node != top_nodes[idx] {
-                return false;
-            }
+            nodes = parents;
         }
 
-        self.get_root_from_top_nodes(top_nodes) == *public
+        auth.next().is_none() && nodes.iter().all(|&(idx, node)| node == top_nodes[idx])
+    }
+}
+
+impl<H: Hasher> Encode for Horst<H> {
+    /// The revealed leaves, the number of authentication nodes and the nodes
+    /// themselves, then the top nodes
+    fn write_signature(&self, sig: &Self::Signature, out: &mut Vec<u8>) {
+        let (signature, top_nodes) = sig;
+        write_nodes(out, &signature.sks);
+        write_len(out, signature.auth.len());
+        write_nodes(out, &signature.auth);
+        write_nodes(out, top_nodes);
+    }
+
+    fn read_signature(&self, input: &mut Reader) -> Result<Self::Signature, Error> {
+        let sks = input.nodes(self.k)?;
+        let auth_len = input.len(self.k * (self.height - self.x))?;
+        let auth = input.nodes(auth_len)?;
+        let top_nodes = input.nodes(1 << self.x)?;
+
+        Ok((Signature { sks, auth }, top_nodes))
+    }
+
+    fn write_public(&self, public: &H::Node, out: &mut Vec<u8>) {
+        out.extend_from_slice(public.as_ref());
+    }
+
+    fn read_public(&self, input: &mut Reader) -> Result<H::Node, Error> {
+        input.node()
     }
 }
 
 
+impl<H: Hasher> Estimate for Horst<H> {
+    fn public_size(&self) -> usize {
+        H::Node::LEN
+    }
+
+    /// At most, when no authentication nodes are shared
+    fn signature_size(&self) -> usize {
+        let path_len = self.height - self.x;
+        (self.k * (1 + path_len) + (1 << self.x)) * H::Node::LEN
+    }
+
+    /// At most, as with `signature_size`
+    fn verify_hashes(&self) -> usize {
+        let path_len = self.height - self.x;
+        self.k * (1 + path_len) + (1 << self.x) - 1
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use crate::Error;
+    use crate::util::{hash, record_hashes, to_hex};
+
     use super::*;
 
     #[test]
@@ -178,4 +391,128 @@ mod tests {
 
         assert!(!horst.verify(msg1, &public, &sig));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn malformed() {
+        let msg = b"My OS update";
+
+        let horst = Horst::new(12, 48);
+        let (private, public) = horst.gen_keys(None);
+        let (sig, top_nodes) = horst.sign(msg, &private);
+        let with = |sks: &[U256], auth: &[U256], top_nodes: &[U256]| {
+            (Signature { sks: sks.into(), auth: auth.into() }, top_nodes.into())
+        };
+        assert!(horst.verify(msg, &public, &with(&sig.sks, &sig.auth, &top_nodes)));
+
+        // Dropping a revealed leaf must not shrink what is checked
+        let (sks, auth) = (&*sig.sks, &*sig.auth);
+        assert!(!horst.verify(msg, &public, &with(&sks[1..], auth, &top_nodes)));
+        assert!(!horst.verify(msg, &public, &with(sks, &auth[1..], &top_nodes)));
+        assert!(!horst.verify(msg, &public, &with(sks, &[auth, &auth[..1]].concat(), &top_nodes)));
+        assert!(!horst.verify(msg, &public, &with(sks, auth, &top_nodes[1..])));
+
+        assert!(matches!(horst.try_sign(&[0; 73], &private), Err(Error::MessageTooLong { len: 73, max: 72 })));
+    }
+
+    #[test]
+    fn leaf_indices() {
+        // tau-bit chunks of the message, least significant first
+        assert_eq!(&*Horst::new(4, 5).transform_msg(&[0x21, 0x43]), [1, 2, 3, 4, 0]);
+        assert_eq!(&*Horst::new(12, 2).transform_msg(&[0x21, 0x43, 0x65]), [0x321, 0x654]);
+        assert_eq!(&*Horst::new(16, 32).transform_msg(&[0xff; 64]), [(1 << 16) - 1; 32]);
+
+        // Every leaf can be picked, not just the first tau
+        let horst = Horst::new(8, 32);
+        let msg = hash(b"My OS update");
+        assert_eq!(&*horst.transform_msg(&msg), msg.map(usize::from));
+
+        let (private, _) = horst.gen_keys(Some([0x42; 32]));
+        let (sig, top_nodes) = horst.sign(&msg, &private);
+        assert_eq!(sig.sks[0], Horst::<Sha256>::get_leaf_secret(&private, msg[0].into()));
+        let sig_bytes: Vec<u8> = sig.sks.iter().flatten()
+            .chain(sig.auth.iter().flatten())
+            .chain(top_nodes.iter().flatten())
+            .copied()
+            .collect();
+        assert_eq!(to_hex(&hash(sig_bytes)), "b2aab64f7df74b5eacdec8bb0d52453f4be7c36e0a8b99c7a7e78dd29bedbe78");
+    }
+
+    #[test]
+    fn cached_verifier() {
+        let msg1 = b"My OS update";
+        let msg2 = b"My important message";
+
+        let horst = Horst::new(12, 48);
+        let (private, public) = horst.gen_keys(None);
+        let sig1 = horst.sign(msg1, &private);
+        let sig2 = horst.sign(msg2, &private);
+
+        let mut verifier = Verifier::new(&horst, VerifyOptions::default());
+        let (valid, calls) = record_hashes(|| verifier.verify(msg1, &public, &sig1));
+        assert!(valid);
+        assert!(calls.len() <= horst.verify_hashes());
+
+        // Shared authentication nodes make the count depend on the message
+        let (_, uncached) = record_hashes(|| horst.verify(msg2, &public, &sig2));
+        let (valid, calls) = record_hashes(|| verifier.verify(msg2, &public, &sig2));
+        assert!(valid);
+        assert_eq!(calls.len(), uncached.len() - ((1 << horst.x) - 1));
+        assert!(!verifier.verify(msg1, &public, &sig2));
+
+        let (_, other_public) = horst.gen_keys(None);
+        assert!(!verifier.verify(msg1, &other_public, &sig1));
+
+        let mut verifier = Verifier::new(&horst, VerifyOptions::default().with_cached_keys(0));
+        assert!(verifier.verify(msg1, &public, &sig1));
+        let (_, calls) = record_hashes(|| verifier.verify(msg2, &public, &sig2));
+        assert_eq!(calls.len(), uncached.len());
+    }
+
+    #[test]
+    #[cfg(feature = "blake3")]
+    fn blake3() {
+        let msg = b"My OS update";
+
+        let horst = Horst::new(12, 48).with_hasher::<crate::util::Blake3>();
+        let (private, public) = horst.gen_keys(Some([7; 32]));
+        let sig = horst.sign(msg, &private);
+        assert!(horst.verify(msg, &public, &sig));
+        assert_ne!(Horst::new(12, 48).gen_keys(Some([7; 32])).1, public);
+    }
+
+    #[test]
+    fn signature_size() {
+        let horst = Horst::new(12, 48);
+        let (private, _) = horst.gen_keys(None);
+        let (sig, top_nodes) = horst.sign(b"My OS update", &private);
+
+        // 48 paths of 6 nodes below the top nodes, so some must merge
+        let size = (sig.sks.len() + sig.auth.len() + top_nodes.len()) * 32;
+        assert!(size < horst.signature_size());
+        assert_eq!(horst.signature_size(), (48 * 7 + 64) * 32);
+    }
+
+    #[test]
+    #[should_panic]
+    fn too_low() {
+        Horst::new(4, 32);
+    }
+
+    #[test]
+    fn sha512() {
+        let msg = b"My OS update";
+
+        let horst = Horst::preset_sha512();
+        let (private, public) = horst.gen_keys(Some([7; 32]));
+        let sig = horst.sign(msg, &private);
+        assert!(horst.verify(msg, &public, &sig));
+        assert_eq!(to_hex(&hash(public)), "a732867cbae9da773c4feefae71eb266b480d2415f12d701b99b5ffd29a18370");
+        assert_eq!(horst.signature_size(), 26624);
+
+        #[cfg(feature = "serde")]
+        {
+            let json = serde_json::to_string(&sig).unwrap();
+            assert!(horst.verify(msg, &public, &serde_json::from_str(&json).unwrap()));
+        }
+    }
+}
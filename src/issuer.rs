@@ -0,0 +1,92 @@
+use std::convert::TryFrom;
+
+use crate::{SignatureScheme, U256};
+use crate::chain::{certify, Link};
+use crate::framing::Encode;
+use crate::merkle::Merkle;
+use crate::spec::{SchemeKind, SchemeSpec, SpecError};
+
+/// A freshly minted one-time key, certified by the issuer's long-term key.
+/// Sign a single message with `private`, and send `link` along with the
+/// signature for verification with a `ChainVerifier`.
+pub struct EphemeralKey<E: SignatureScheme> {
+    pub link: Link,
+    pub private: E::Private,
+}
+
+/// Mints certified one-time keys on demand, so latency-critical signing only
+/// costs a Winternitz or Lamport signature, while the long-term Merkle key
+/// only signs certifications.
+pub struct Issuer<O, E> {
+    merkle: Merkle<O>,
+    private: Option<(U256, usize)>,
+    ephemeral: E,
+    spec: SchemeSpec,
+}
+
+impl<O, E> Issuer<O, E>
+    where O: Encode + 'static, O::Public: AsRef<[u8]>,
+          E: SignatureScheme + for<'a> TryFrom<&'a SchemeSpec, Error = SpecError>, E::Public: AsRef<[u8]> + 'static {
+    /// `spec` describes the one-time scheme of the minted keys
+    pub fn new(merkle: Merkle<O>, private: (U256, usize), spec: SchemeSpec) -> Result<Self, SpecError> {
+        if spec.kind() != SchemeKind::OneTime {
+            return Err(SpecError::new(format!("'{}' isn't a one-time scheme", spec)));
+        }
+        let ephemeral = E::try_from(&spec)?;
+
+        Ok(Self { merkle, private: Some(private), ephemeral, spec })
+    }
+
+    pub fn ephemeral_scheme(&self) -> &E {
+        &self.ephemeral
+    }
+
+    /// Mints a key, using up one leaf of the long-term key. Returns `None`
+    /// once the long-term key is used up.
+    pub fn issue(&mut self) -> Option<EphemeralKey<E>> {
+        let private = self.private.take()?;
+        let (ephemeral_private, ephemeral_public) = self.ephemeral.gen_keys(None);
+        let link = certify(&self.merkle, &private, self.spec.clone(), ephemeral_public);
+        self.private = self.merkle.next_key(private);
+
+        Some(EphemeralKey { link, private: ephemeral_private })
+    }
+
+    /// The long-term key state to persist, or `None` if it is used up
+    pub fn state(&self) -> Option<(U256, usize)> {
+        self.private
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::chain::{ChainPolicy, ChainVerifier};
+    use crate::lamport::Lamport;
+    use crate::registry::Registry;
+    use crate::winternitz::Winternitz;
+
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let root_spec: SchemeSpec = "merkle-wots16-h1".parse().unwrap();
+        let merkle = Merkle::<Winternitz>::try_from(&root_spec).unwrap();
+        let (private, public) = merkle.gen_keys(None);
+
+        let mut issuer = Issuer::<_, Lamport>::new(merkle, private, "lamport-n32".parse().unwrap()).unwrap();
+        let verifier = ChainVerifier::new(Registry::default(),
+                                          ChainPolicy::default().with_leaf_kind(SchemeKind::OneTime));
+
+        for (i, msg) in [b"My OS update", b"My OS patch!"].iter().enumerate() {
+            let key = issuer.issue().unwrap();
+            let sig = issuer.ephemeral_scheme().sign(&msg[..], &key.private);
+            assert_eq!(verifier.verify(&root_spec, &public, &[key.link], &msg[..], &sig), Ok(()));
+            assert_eq!(issuer.state().map(|(_, leaf)| leaf), (i == 0).then_some(1));
+        }
+        assert!(issuer.issue().is_none());
+
+        let merkle = Merkle::new(1, Winternitz::new(16));
+        assert!(Issuer::<_, Winternitz>::new(merkle, private, "horst-t256-k64".parse().unwrap()).is_err());
+    }
+}
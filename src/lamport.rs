@@ -1,15 +1,20 @@
+use std::marker::PhantomData;
 use std::ops::Index;
 
 use bitvec::prelude::{BitView, Lsb0};
 use bytemuck::{cast_slice, cast_slice_mut};
 use rand::{RngCore, SeedableRng};
 use rand_hc::Hc128Rng;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
-use crate::util::hash;
-use crate::SignatureScheme;
+use crate::framing::{write_len, Encode, Reader};
+use crate::util::{Domain, Hasher, Sha256};
+use crate::{check_message_len, Error, Estimate, SignatureScheme};
 use crate::U256;
 
 #[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Key(Box<[[U256; 2]]>);
 
 impl Key {
@@ -28,12 +33,12 @@ impl Key {
         Self(result.into_boxed_slice())
     }
 
-    fn gen_public(private: &Self) -> Self {
+    fn gen_public<H: Hasher<Node = U256>>(private: &Self) -> Self {
         let mut result = private.clone();
 
         for keys in result.0.iter_mut() {
-            keys[0] = hash(keys[0]);
-            keys[1] = hash(keys[1]);
+            keys[0] = H::hash_in(Domain::Chain, keys[0]);
+            keys[1] = H::hash_in(Domain::Chain, keys[1]);
         }
 
         result
@@ -47,7 +52,7 @@ impl Key {
 
 impl AsRef<[u8]> for Key {
     fn as_ref(&self) -> &[u8] {
-        cast_slice(&*self.0)
+        cast_slice(&self.0)
     }
 }
 
@@ -60,6 +65,7 @@ impl Index<usize> for Key {
 }
 
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Signature(Box<[U256]>);
 
 impl Signature {
@@ -78,25 +84,67 @@ impl Index<usize> for Signature {
 }
 
 
+/// A public key laid out as all the hashes for 0 bits followed by all the
+/// hashes for 1 bits, for repeatedly verifying under the same key, e.g. when
+/// Lamport is the OTS of a Merkle or Goldreich tree in a hot verify path.
+pub struct PreparedLamportVerifier<H = Sha256> {
+    msg_len: usize,
+    keys: Box<[U256]>,
+    hasher: PhantomData<H>,
+}
+
+impl<H: Hasher<Node = U256>> PreparedLamportVerifier<H> {
+    pub fn new(lamport: &Lamport<H>, public: &Key) -> Option<Self> {
+        if public.len() != lamport.msg_len || public.0.len() != lamport.msg_len * 8 {
+            return None;
+        }
+
+        let keys = public.0.iter().map(|keys| keys[0])
+            .chain(public.0.iter().map(|keys| keys[1]))
+            .collect();
+        Some(Self { msg_len: lamport.msg_len, keys, hasher: PhantomData })
+    }
+
+    pub fn verify(&self, msg: &[u8], sig: &Signature) -> bool {
+        if msg.len() > self.msg_len || msg.len() != sig.len() {
+            return false;
+        }
+
+        let (keys0, keys1) = self.keys.split_at(self.msg_len * 8);
+        msg.view_bits::<Lsb0>().iter().by_val()
+            .zip(keys0.iter().zip(keys1))
+            .zip(sig.0.iter())
+            .all(|((bit, (k0, k1)), s)| H::hash_in(Domain::Chain, s) == if bit { *k1 } else { *k0 })
+    }
+}
+
+
 #[derive(Copy, Clone)]
-pub struct Lamport {
+pub struct Lamport<H = Sha256> {
     msg_len: usize,
+    hasher: PhantomData<H>,
 }
 
 impl Lamport {
     pub fn new(msg_len: usize) -> Self {
-        Self { msg_len }
+        Self { msg_len, hasher: PhantomData }
     }
 }
 
-impl SignatureScheme for Lamport {
+impl<H: Hasher<Node = U256>> Lamport<H> {
+    pub fn with_hasher<H2: Hasher<Node = U256>>(self) -> Lamport<H2> {
+        Lamport { msg_len: self.msg_len, hasher: PhantomData }
+    }
+}
+
+impl<H: Hasher<Node = U256>> SignatureScheme for Lamport<H> {
     type Private = Key;
     type Public = Key;
     type Signature = Signature;
 
     fn gen_keys(&self, seed: Option<U256>) -> (Key, Key) {
         let private = Key::gen_private(self.msg_len, seed);
-        let public = Key::gen_public(&private);
+        let public = Key::gen_public::<H>(&private);
 
         (private, public)
     }
@@ -115,11 +163,21 @@ impl SignatureScheme for Lamport {
         Signature(sig)
     }
 
-    fn verify(&self, msg: &[u8], public: &Self::Public, sig: &Self::Signature) -> bool {
-        assert_eq!(self.msg_len, public.len());
-        assert!(msg.len() <= self.msg_len);
+    fn max_message_len(&self) -> Option<usize> {
+        Some(self.msg_len)
+    }
+
+    fn try_sign(&self, msg: &[u8], private: &Self::Private) -> Result<Self::Signature, Error> {
+        check_message_len(self, msg)?;
+        if private.len() != self.msg_len || private.0.len() != self.msg_len * 8 {
+            return Err(Error::MalformedKey);
+        }
 
-        if msg.len() != sig.len() {
+        Ok(self.sign(msg, private))
+    }
+
+    fn verify(&self, msg: &[u8], public: &Self::Public, sig: &Self::Signature) -> bool {
+        if self.msg_len != public.len() || msg.len() > self.msg_len || msg.len() != sig.len() {
             return false;
         }
 
@@ -128,11 +186,47 @@ impl SignatureScheme for Lamport {
         msg_bits.iter().by_val()
             .enumerate()
             .map(|(i, bit)| (sig[i], public[i][bit as usize]))
-            .all(|(s, k)| hash(s) == k)
+            .all(|(s, k)| H::hash_in(Domain::Chain, s) == k)
+    }
+}
+
+impl<H: Hasher<Node = U256>> Encode for Lamport<H> {
+    /// The number of signed bytes, followed by a secret per bit
+    fn write_signature(&self, sig: &Signature, out: &mut Vec<u8>) {
+        write_len(out, sig.len());
+        out.extend_from_slice(cast_slice(&sig.0));
+    }
+
+    fn read_signature(&self, input: &mut Reader) -> Result<Signature, Error> {
+        let len = input.len(self.msg_len)?;
+        Ok(Signature(input.nodes(len * 8)?))
+    }
+
+    fn write_public(&self, public: &Key, out: &mut Vec<u8>) {
+        out.extend_from_slice(public.as_ref());
+    }
+
+    fn read_public(&self, input: &mut Reader) -> Result<Key, Error> {
+        let hashes = input.nodes(self.msg_len * 8 * 2)?;
+        Ok(Key(hashes.chunks_exact(2).map(|pair| [pair[0], pair[1]]).collect()))
     }
 }
 
 
+impl<H: Hasher<Node = U256>> Estimate for Lamport<H> {
+    fn public_size(&self) -> usize {
+        self.msg_len * 8 * 2 * 32
+    }
+
+    fn signature_size(&self) -> usize {
+        self.msg_len * 8 * 32
+    }
+
+    fn verify_hashes(&self) -> usize {
+        self.msg_len * 8
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -149,4 +243,46 @@ mod tests {
         assert!(lamport.verify(msg, &public, &sig));
         assert!(!lamport.verify(b"My OS apdate", &public, &sig));
     }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde() {
+        let lamport = Lamport::new(16);
+        let (private, public) = lamport.gen_keys(None);
+
+        let private: Key = serde_json::from_str(&serde_json::to_string(&private).unwrap()).unwrap();
+        let sig = lamport.sign(b"My OS update", &private);
+        assert!(lamport.verify(b"My OS update", &public, &sig));
+    }
+
+    #[test]
+    fn prepared_verifier() {
+        let lamport = Lamport::new(16);
+        let (private, public) = lamport.gen_keys(None);
+        let verifier = PreparedLamportVerifier::new(&lamport, &public).unwrap();
+
+        let sig = lamport.sign(b"My OS update", &private);
+        assert!(verifier.verify(b"My OS update", &sig));
+        assert!(!verifier.verify(b"My OS apdate", &sig));
+        assert!(!verifier.verify(b"My OS", &sig));
+
+        assert!(PreparedLamportVerifier::new(&Lamport::new(32), &public).is_none());
+    }
+
+    #[test]
+    fn malformed() {
+        let msg = b"My OS update";
+
+        let lamport = Lamport::new(16);
+        let (private, public) = lamport.gen_keys(None);
+        let sig = lamport.sign(msg, &private);
+
+        assert!(!lamport.verify(&[0; 17], &public, &sig));
+        assert!(!Lamport::new(64).verify(msg, &public, &sig));
+        assert!(!lamport.verify(msg, &public, &Signature(sig.0[..8].into())));
+
+        assert_eq!(lamport.try_sign(&[0; 17], &private).err(), Some(Error::MessageTooLong { len: 17, max: 16 }));
+        assert_eq!(Lamport::new(8).try_sign(b"", &private).err(), Some(Error::MalformedKey));
+        assert_eq!(lamport.try_verify(b"My OS apdate", &public, &sig), Err(Error::InvalidSignature));
+    }
 }
\ No newline at end of file
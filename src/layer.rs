@@ -0,0 +1,235 @@
+//! Middleware for signers and verifiers. Features such as verify-after-sign,
+//! audit logging or budgets are written once as layers and stacked around
+//! any `SignatureScheme`, instead of each coming with its own wrapper type.
+//! E.g. `Layered::new(scheme, budget).layer(VerifyAfterSign::new(public))`
+//! checks signatures made within the budget.
+
+use crate::budget::{Budget, Clock, ClockedBudget, SystemClock};
+use crate::util::hash;
+use crate::{Error, Estimate, SignatureScheme, U256};
+
+/// Runs around the `sign` of the scheme below it. Layers call `inner` to
+/// continue down the stack, or return early to short-circuit it.
+pub trait SignLayer<S: SignatureScheme> {
+    fn sign(&self, inner: &S, msg: &[u8], private: &S::Private) -> Result<S::Signature, Error> {
+        inner.try_sign(msg, private)
+    }
+}
+
+/// Runs around the `verify` of the scheme below it, like `SignLayer`
+pub trait VerifyLayer<S: SignatureScheme> {
+    fn verify(&self, inner: &S, msg: &[u8], public: &S::Public, sig: &S::Signature) -> Result<(), Error> {
+        inner.try_verify(msg, public, sig)
+    }
+}
+
+/// A scheme with a layer around it. Layers added later with `layer` run
+/// first, so the stack reads inside out.
+pub struct Layered<S, L> {
+    inner: S,
+    layer: L,
+}
+
+impl<S, L> Layered<S, L> where S: SignatureScheme, L: SignLayer<S> + VerifyLayer<S> {
+    pub fn new(inner: S, layer: L) -> Self {
+        Self { inner, layer }
+    }
+
+    pub fn layer<L2>(self, outer: L2) -> Layered<Self, L2> where L2: SignLayer<Self> + VerifyLayer<Self> {
+        Layered::new(self, outer)
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S, L> SignatureScheme for Layered<S, L> where S: SignatureScheme, L: SignLayer<S> + VerifyLayer<S> {
+    type Private = S::Private;
+    type Public = S::Public;
+    type Signature = S::Signature;
+
+    fn gen_keys(&self, seed: Option<U256>) -> (Self::Private, Self::Public) {
+        self.inner.gen_keys(seed)
+    }
+
+    /// Panics if a layer fails, see `try_sign`
+    fn sign(&self, msg: &[u8], private: &Self::Private) -> Self::Signature {
+        self.try_sign(msg, private).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    fn verify(&self, msg: &[u8], public: &Self::Public, sig: &Self::Signature) -> bool {
+        self.try_verify(msg, public, sig).is_ok()
+    }
+
+    fn max_message_len(&self) -> Option<usize> {
+        self.inner.max_message_len()
+    }
+
+    fn try_sign(&self, msg: &[u8], private: &Self::Private) -> Result<Self::Signature, Error> {
+        self.layer.sign(&self.inner, msg, private)
+    }
+
+    fn try_verify(&self, msg: &[u8], public: &Self::Public, sig: &Self::Signature) -> Result<(), Error> {
+        self.layer.verify(&self.inner, msg, public, sig)
+    }
+}
+
+impl<S, L> Estimate for Layered<S, L> where S: Estimate, L: SignLayer<S> + VerifyLayer<S> {
+    fn public_size(&self) -> usize {
+        self.inner.public_size()
+    }
+
+    fn signature_size(&self) -> usize {
+        self.inner.signature_size()
+    }
+
+    fn verify_hashes(&self) -> usize {
+        self.inner.verify_hashes()
+    }
+}
+
+
+/// Checks every signature against the signer's public key before returning
+/// it, catching faults that would otherwise leak key material
+pub struct VerifyAfterSign<P> {
+    public: P,
+}
+
+impl<P> VerifyAfterSign<P> {
+    pub fn new(public: P) -> Self {
+        Self { public }
+    }
+}
+
+impl<S: SignatureScheme> SignLayer<S> for VerifyAfterSign<S::Public> {
+    fn sign(&self, inner: &S, msg: &[u8], private: &S::Private) -> Result<S::Signature, Error> {
+        let sig = inner.try_sign(msg, private)?;
+        inner.try_verify(msg, &self.public, &sig)?;
+        Ok(sig)
+    }
+}
+
+impl<S: SignatureScheme> VerifyLayer<S> for VerifyAfterSign<S::Public> {}
+
+impl<S: SignatureScheme> SignLayer<S> for Budget {
+    fn sign(&self, inner: &S, msg: &[u8], private: &S::Private) -> Result<S::Signature, Error> {
+        self.with_clock(SystemClock).sign(inner, msg, private)
+    }
+}
+
+impl<S: SignatureScheme> VerifyLayer<S> for Budget {
+    fn verify(&self, inner: &S, msg: &[u8], public: &S::Public, sig: &S::Signature) -> Result<(), Error> {
+        self.with_clock(SystemClock).verify(inner, msg, public, sig)
+    }
+}
+
+impl<S: SignatureScheme, C: Clock + Clone + 'static> SignLayer<S> for ClockedBudget<C> {
+    fn sign(&self, inner: &S, msg: &[u8], private: &S::Private) -> Result<S::Signature, Error> {
+        self.run(|| inner.try_sign(msg, private)).map_err(Error::OverBudget)?
+    }
+}
+
+impl<S: SignatureScheme, C: Clock + Clone + 'static> VerifyLayer<S> for ClockedBudget<C> {
+    fn verify(&self, inner: &S, msg: &[u8], public: &S::Public, sig: &S::Signature) -> Result<(), Error> {
+        self.run(|| inner.try_verify(msg, public, sig)).map_err(Error::OverBudget)?
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    Sign,
+    Verify,
+}
+
+/// What `AuditLog` records of an operation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Event {
+    pub operation: Operation,
+    /// The hash of the message, so logs don't hold the messages themselves
+    pub msg_digest: U256,
+    pub outcome: Result<(), Error>,
+}
+
+/// Hands an `Event` for every operation to a callback, e.g. one writing to
+/// an audit log
+pub struct AuditLog<F> {
+    log: F,
+}
+
+impl<F: Fn(Event)> AuditLog<F> {
+    pub fn new(log: F) -> Self {
+        Self { log }
+    }
+}
+
+impl<S: SignatureScheme, F: Fn(Event)> SignLayer<S> for AuditLog<F> {
+    fn sign(&self, inner: &S, msg: &[u8], private: &S::Private) -> Result<S::Signature, Error> {
+        let result = inner.try_sign(msg, private);
+        let outcome = result.as_ref().map(|_| ()).map_err(|&err| err);
+        (self.log)(Event { operation: Operation::Sign, msg_digest: hash(msg), outcome });
+        result
+    }
+}
+
+impl<S: SignatureScheme, F: Fn(Event)> VerifyLayer<S> for AuditLog<F> {
+    fn verify(&self, inner: &S, msg: &[u8], public: &S::Public, sig: &S::Signature) -> Result<(), Error> {
+        let outcome = inner.try_verify(msg, public, sig);
+        (self.log)(Event { operation: Operation::Verify, msg_digest: hash(msg), outcome });
+        outcome
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use crate::budget::BudgetError;
+    use crate::merkle::Merkle;
+    use crate::winternitz::Winternitz;
+
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let msg = b"My OS update";
+        let winternitz = Winternitz::new(16);
+        let (private, public) = winternitz.gen_keys(None);
+
+        let events = RefCell::new(Vec::new());
+        let scheme = Layered::new(winternitz, Budget::default().with_max_hash_calls(winternitz.verify_hashes()))
+            .layer(AuditLog::new(|event| events.borrow_mut().push(event)));
+        let sig = scheme.sign(msg, &private);
+        assert!(scheme.verify(msg, &public, &sig));
+        assert!(!scheme.verify(b"Another update", &public, &sig));
+
+        let tight = Layered::new(winternitz, Budget::default().with_max_hash_calls(10))
+            .layer(AuditLog::new(|event| events.borrow_mut().push(event)));
+        assert!(!tight.verify(msg, &public, &sig));
+
+        // The outer layer sees what the inner one made of the operation
+        let outcomes: Vec<_> = events.borrow().iter().map(|event| (event.operation, event.outcome)).collect();
+        assert_eq!(outcomes, [
+            (Operation::Sign, Ok(())),
+            (Operation::Verify, Ok(())),
+            (Operation::Verify, Err(Error::InvalidSignature)),
+            (Operation::Verify, Err(Error::OverBudget(BudgetError::HashCalls { max: 10 }))),
+        ]);
+        assert_eq!(events.borrow()[0].msg_digest, hash(msg));
+    }
+
+    #[test]
+    fn verify_after_sign() {
+        let msg = b"My OS update";
+        let merkle = Merkle::new(3, Winternitz::new(16));
+        let (private, public) = merkle.gen_keys(None);
+
+        let checked = Layered::new(merkle, VerifyAfterSign::new(public));
+        assert!(checked.inner().verify(msg, &public, &checked.sign(msg, &private)));
+
+        // Signatures under keys that don't match the public key never get out
+        let (other, _) = checked.gen_keys(None);
+        assert_eq!(checked.try_sign(msg, &other).err(), Some(Error::InvalidSignature));
+    }
+}
@@ -5,9 +5,153 @@ pub mod merkle;
 pub mod sphincs;
 pub mod winternitz;
 pub mod horst;
+pub mod tree;
+pub mod hashchain;
+pub mod tesla;
+pub mod session;
+pub mod selftest;
+pub mod spec;
+pub mod cert;
+pub mod stats;
+pub mod message_recovery;
+pub mod bech32;
+pub mod bundle;
+pub mod fragment;
+pub mod ota;
+pub mod diff;
+pub mod service;
+pub mod challenge;
+pub mod registry;
+pub mod chain;
+pub mod issuer;
+pub mod sequence;
+pub mod pinning;
+pub mod framing;
+pub mod budget;
+pub mod layer;
+#[cfg(feature = "unstable")]
+pub mod witness;
+#[cfg(feature = "config")]
+pub mod config;
+#[cfg(feature = "profiling")]
+pub mod profiling;
+#[cfg(feature = "corpus")]
+pub mod corpus;
+#[cfg(feature = "archive")]
+pub mod archive;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+use rand::{CryptoRng, Rng, RngCore};
+
+pub use selftest::{self_test, validate_composition};
 
 pub type U256 = [u8; 32];
 
+/// 512-bit nodes, as hashed by `util::Sha512`. Serialized like `U256`, as a
+/// tuple of bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct U512(pub [u8; 64]);
+
+impl Default for U512 {
+    fn default() -> Self {
+        Self([0; 64])
+    }
+}
+
+impl AsRef<[u8]> for U512 {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl AsMut<[u8]> for U512 {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for U512 {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeTuple;
+
+        let mut tuple = serializer.serialize_tuple(64)?;
+        for byte in &self.0 {
+            tuple.serialize_element(byte)?;
+        }
+        tuple.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for U512 {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Bytes;
+
+        impl<'de> serde::de::Visitor<'de> for Bytes {
+            type Value = U512;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "64 bytes")
+            }
+
+            fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<U512, A::Error> {
+                let mut out = U512::default();
+                for (i, byte) in out.0.iter_mut().enumerate() {
+                    *byte = seq.next_element()?.ok_or_else(|| serde::de::Error::invalid_length(i, &self))?;
+                }
+                Ok(out)
+            }
+        }
+
+        deserializer.deserialize_tuple(64, Bytes)
+    }
+}
+
+/// Where the schemes picking a random leaf per signature (`Goldreich` and
+/// `Sphincs`) get their randomness from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SigningMode {
+    /// Fresh randomness from the OS for every signature.
+    Randomized,
+    /// Randomness derived from the private key and the message, so identical
+    /// messages get byte-identical signatures and a broken RNG can't cause
+    /// leaf reuse. On the other hand, observers can tell when the same
+    /// message was signed twice, and fault attacks have repeatable signing
+    /// runs to work with.
+    Deterministic,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The message is longer than the scheme can sign
+    MessageTooLong { len: usize, max: usize },
+    /// The private key doesn't fit the scheme's parameters, or is used up
+    MalformedKey,
+    /// The signature doesn't verify
+    InvalidSignature,
+    /// Fewer bytes of the signature arrived than its frame declares, or than
+    /// the scheme's parameters call for
+    Truncated { len: usize, expected: usize },
+    /// Signing or verifying took more work than allowed
+    OverBudget(budget::BudgetError),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MessageTooLong { len, max } => write!(f, "message of {} bytes is longer than {}", len, max),
+            Self::MalformedKey => write!(f, "private key doesn't fit the scheme"),
+            Self::InvalidSignature => write!(f, "signature doesn't verify"),
+            Self::Truncated { len, expected } => write!(f, "signature cut off after {} of {} bytes", len, expected),
+            Self::OverBudget(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
 pub trait SignatureScheme {
     type Private;
     type Public;
@@ -15,7 +159,241 @@ pub trait SignatureScheme {
 
     fn gen_keys(&self, seed: Option<U256>) -> (Self::Private, Self::Public);
 
+    /// Generates keys from a seed drawn from `rng`, e.g. `OsRng`, a DRBG or a
+    /// test RNG, instead of the entropy `gen_keys` picks itself
+    fn gen_keys_with_rng(&self, rng: &mut (impl RngCore + CryptoRng)) -> (Self::Private, Self::Public) {
+        self.gen_keys(Some(rng.gen()))
+    }
+
     fn sign(&self, msg: &[u8], private: &Self::Private) -> Self::Signature;
 
+    /// Never panics, whatever the input, but returns `false` if the public key
+    /// or signature is malformed.
     fn verify(&self, msg: &[u8], public: &Self::Public, sig: &Self::Signature) -> bool;
-}
\ No newline at end of file
+
+    /// The longest message `sign` accepts, or `None` if any length is fine
+    fn max_message_len(&self) -> Option<usize> {
+        None
+    }
+
+    /// Like `sign`, but returns an error instead of panicking on input it
+    /// can't sign
+    fn try_sign(&self, msg: &[u8], private: &Self::Private) -> Result<Self::Signature, Error> {
+        check_message_len(self, msg)?;
+        Ok(self.sign(msg, private))
+    }
+
+    fn try_verify(&self, msg: &[u8], public: &Self::Public, sig: &Self::Signature) -> Result<(), Error> {
+        if self.verify(msg, public, sig) {
+            Ok(())
+        } else {
+            Err(Error::InvalidSignature)
+        }
+    }
+}
+
+pub(crate) fn check_message_len<S: SignatureScheme + ?Sized>(scheme: &S, msg: &[u8]) -> Result<(), Error> {
+    match scheme.max_message_len() {
+        Some(max) if msg.len() > max => Err(Error::MessageTooLong { len: msg.len(), max }),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stability {
+    Stable,
+    /// Only available with the `unstable` feature and exempt from semver
+    Unstable,
+}
+
+/// Lists the modules compiled into this build along with their stability,
+/// so downstream packagers can check they don't depend on unstable APIs.
+pub fn stability() -> Vec<(&'static str, Stability)> {
+    let mut report = vec![
+        ("lamport", Stability::Stable),
+        ("winternitz", Stability::Stable),
+        ("horst", Stability::Stable),
+        ("merkle", Stability::Stable),
+        ("goldreich", Stability::Stable),
+        ("sphincs", Stability::Stable),
+        ("tree", Stability::Stable),
+        ("hashchain", Stability::Stable),
+        ("tesla", Stability::Stable),
+        ("session", Stability::Stable),
+        ("selftest", Stability::Stable),
+        ("spec", Stability::Stable),
+        ("cert", Stability::Stable),
+        ("stats", Stability::Stable),
+        ("message_recovery", Stability::Stable),
+        ("bech32", Stability::Stable),
+        ("bundle", Stability::Stable),
+        ("fragment", Stability::Stable),
+        ("ota", Stability::Stable),
+        ("diff", Stability::Stable),
+        ("service", Stability::Stable),
+        ("challenge", Stability::Stable),
+        ("registry", Stability::Stable),
+        ("chain", Stability::Stable),
+        ("issuer", Stability::Stable),
+        ("sequence", Stability::Stable),
+        ("pinning", Stability::Stable),
+        ("framing", Stability::Stable),
+        ("budget", Stability::Stable),
+        ("layer", Stability::Stable),
+    ];
+    if cfg!(feature = "unstable") {
+        report.push(("witness", Stability::Unstable));
+    }
+    if cfg!(feature = "config") {
+        report.push(("config", Stability::Stable));
+    }
+    if cfg!(feature = "profiling") {
+        report.push(("profiling", Stability::Stable));
+    }
+    if cfg!(feature = "corpus") {
+        report.push(("corpus", Stability::Stable));
+    }
+    if cfg!(feature = "archive") {
+        report.push(("archive", Stability::Stable));
+    }
+    if cfg!(feature = "testing") {
+        report.push(("testing", Stability::Stable));
+    }
+
+    report
+}
+
+/// What this build can do on the current machine, for diagnosing why a
+/// scheme or acceleration path is unavailable.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+    pub schemes: Vec<&'static str>,
+    /// The `util::Hasher` implementations compiled in
+    pub hash_backends: Vec<&'static str>,
+    /// Whether the CPU has SHA instructions, which the hash backend picks up
+    /// at runtime
+    pub hash_acceleration: bool,
+    /// Crate features enabled in this build
+    pub features: Vec<&'static str>,
+}
+
+pub fn capabilities() -> Capabilities {
+    let mut features = Vec::new();
+    if cfg!(feature = "unstable") {
+        features.push("unstable");
+    }
+    if cfg!(feature = "serde") {
+        features.push("serde");
+    }
+    if cfg!(feature = "config") {
+        features.push("config");
+    }
+    if cfg!(feature = "stress") {
+        features.push("stress");
+    }
+    if cfg!(feature = "shake") {
+        features.push("shake");
+    }
+    if cfg!(feature = "blake3") {
+        features.push("blake3");
+    }
+    if cfg!(feature = "profiling") {
+        features.push("profiling");
+    }
+    if cfg!(feature = "corpus") {
+        features.push("corpus");
+    }
+    if cfg!(feature = "archive") {
+        features.push("archive");
+    }
+    if cfg!(feature = "rayon") {
+        features.push("rayon");
+    }
+    if cfg!(feature = "testing") {
+        features.push("testing");
+    }
+
+    let mut hash_backends = vec!["sha256", "sha512/256", "sha512"];
+    if cfg!(feature = "shake") {
+        hash_backends.push("shake256");
+    }
+    if cfg!(feature = "blake3") {
+        hash_backends.push("blake3");
+    }
+
+    Capabilities {
+        schemes: vec!["lamport", "winternitz", "wots+", "compressed-winternitz", "horst", "merkle", "goldreich", "sphincs"],
+        hash_backends,
+        hash_acceleration: hash_acceleration(),
+        features,
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn hash_acceleration() -> bool {
+    is_x86_feature_detected!("sha")
+}
+
+#[cfg(target_arch = "aarch64")]
+fn hash_acceleration() -> bool {
+    std::arch::is_aarch64_feature_detected!("sha2")
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
+fn hash_acceleration() -> bool {
+    false
+}
+
+/// Size estimates for a configured scheme, in bytes of key and signature
+/// material (hashes, secrets and leaf indices, without any framing).
+pub trait Estimate: SignatureScheme {
+    fn public_size(&self) -> usize;
+
+    fn signature_size(&self) -> usize;
+
+    /// Upper bound on the number of hash computations done by `verify`
+    fn verify_hashes(&self) -> usize;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stability_report() {
+        let report = stability();
+        assert!(report.contains(&("sphincs", Stability::Stable)));
+        assert_eq!(report.contains(&("witness", Stability::Unstable)), cfg!(feature = "unstable"));
+    }
+
+    #[test]
+    fn gen_keys_with_rng() {
+        use rand::rngs::{OsRng, StdRng};
+        use rand::SeedableRng;
+
+        use crate::horst::Horst;
+        use crate::sphincs::Sphincs;
+        use crate::winternitz::Winternitz;
+
+        let sphincs = Sphincs::new(2, 2, Winternitz::new(16), Horst::new(8, 64));
+        let mut rng = StdRng::seed_from_u64(7);
+        let (_, public) = sphincs.gen_keys_with_rng(&mut rng);
+        let seed = StdRng::seed_from_u64(7).gen();
+        assert_eq!(public, sphincs.gen_keys(Some(seed)).1);
+        assert_ne!(public, sphincs.gen_keys_with_rng(&mut rng).1);
+
+        let (private, public) = sphincs.gen_keys_with_rng(&mut OsRng);
+        assert!(sphincs.verify(b"My OS update", &public, &sphincs.sign(b"My OS update", &private)));
+    }
+
+    #[test]
+    fn capabilities_report() {
+        let caps = capabilities();
+        assert!(caps.schemes.contains(&"sphincs"));
+        assert!(caps.schemes.contains(&"wots+") && caps.schemes.contains(&"compressed-winternitz"));
+        assert_eq!(caps.hash_backends[..3], ["sha256", "sha512/256", "sha512"]);
+        assert_eq!(caps.hash_backends.contains(&"shake256"), cfg!(feature = "shake"));
+        assert_eq!(caps.hash_backends.contains(&"blake3"), cfg!(feature = "blake3"));
+        assert_eq!(caps.features.contains(&"unstable"), cfg!(feature = "unstable"));
+    }
+}
@@ -1,57 +1,518 @@
+use std::convert::{TryFrom, TryInto};
+use std::marker::PhantomData;
+use std::ops::{Deref, Range};
+
 use bytemuck::bytes_of;
 use rand::prelude::{Rng, SeedableRng, StdRng};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{check_message_len, Error, Estimate, SignatureScheme, U256};
+use crate::framing::{write_nodes, Encode, Reader};
+use crate::selftest::Composition;
+use crate::tree::MerkleTree;
+use crate::util::{Domain, Hasher, Node, Sha256};
+#[cfg(feature = "unstable")]
+use crate::witness::{self, Witness};
+
+/// Key generation derives every leaf, so this takes a few hours already;
+/// taller trees belong in a hypertree like SPHINCS
+pub const MAX_TREE_HEIGHT: usize = 24;
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound(serialize = "O::Public: Serialize, O::Signature: Serialize, N: Serialize",
+                                              deserialize = "O::Public: Deserialize<'de>, O::Signature: Deserialize<'de>, \
+                                                             N: Deserialize<'de>")))]
+pub struct Signature<O: SignatureScheme, N = U256> {
+    leaf_idx: usize,
+    leaf_public: O::Public,
+    leaf_sig: O::Signature,
+    path: Box<[N]>,
+}
+
+impl<O: SignatureScheme, N> Signature<O, N> {
+    pub fn leaf_idx(&self) -> usize {
+        self.leaf_idx
+    }
+}
 
-use crate::{SignatureScheme, U256};
-use crate::util::{hash, hash_pair};
 
-pub struct Signature<O: SignatureScheme> {
+/// A signature by a single leaf, without the authentication path.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound(serialize = "O::Public: Serialize, O::Signature: Serialize",
+                                              deserialize = "O::Public: Deserialize<'de>, O::Signature: Deserialize<'de>")))]
+pub struct LeafSignature<O: SignatureScheme> {
     leaf_idx: usize,
     leaf_public: O::Public,
     leaf_sig: O::Signature,
-    path: Box<[U256]>,
 }
 
 
-pub struct Merkle<O> {
+/// The public part of a Merkle tree whose leaf keys may be held by different
+/// parties. Anyone holding it can complete leaf signatures into full ones.
+pub struct PublicTree<H: Hasher = Sha256> {
+    tree: MerkleTree<H>,
+}
+
+impl<H: Hasher> PublicTree<H> {
+    pub fn root(&self) -> H::Node {
+        self.tree.root()
+    }
+
+    /// Adds the authentication path to `leaf_sig`, or returns `None` if its
+    /// leaf key isn't the one in the tree.
+    pub fn complete<O: SignatureScheme>(&self, leaf_sig: LeafSignature<O>) -> Option<Signature<O, H::Node>>
+        where <O as SignatureScheme>::Public: AsRef<[u8]> {
+        let LeafSignature { leaf_idx, leaf_public, leaf_sig } = leaf_sig;
+        if leaf_idx >= self.tree.len() || H::hash_in(Domain::Leaf, &leaf_public) != self.tree.leaf(leaf_idx) {
+            return None;
+        }
+
+        Some(Signature {
+            leaf_idx,
+            leaf_public,
+            leaf_sig,
+            path: self.tree.prove(leaf_idx).path().into(),
+        })
+    }
+}
+
+fn get_root<H: Hasher>(leaf_idx: usize, leaf_public: impl AsRef<[u8]>, path: &[H::Node]) -> H::Node {
+    path.iter()
+        .enumerate()
+        .fold(H::hash_in(Domain::Leaf, leaf_public), |acc, (h, sibling)| {
+            let idx = leaf_idx / (1 << h);
+            if idx & 1 == 0 {
+                H::hash_pair_in(Domain::Node, acc, sibling)
+            } else {
+                H::hash_pair_in(Domain::Node, sibling, acc)
+            }
+        })
+}
+
+
+/// Identifies how leaf keys are derived from the private seed, so auditors
+/// can tell which derivation a key claims to use
+const KEYGEN_PRG: &str = "merkle-v1: leaf seed = H(secret || idx), OTS gen_keys(leaf seed)";
+
+type Opening<O, N> = (usize, <O as SignatureScheme>::Public, Box<[N]>);
+
+/// Spot-check openings of the leaves of a Merkle key, at indices picked from
+/// the public key itself, showing the key is a tree of the claimed height
+/// over OTS keys of the claimed parameters.
+pub struct KeygenAudit<O: SignatureScheme, N = U256> {
+    prg: &'static str,
+    openings: Box<[Opening<O, N>]>,
+}
+
+
+/// Division of time into epochs of `epoch_len` starting at `start`, with
+/// `leaves_per_epoch` consecutive leaves reserved for each. A signature's leaf
+/// index then tells which epoch it was made in.
+#[derive(Clone, Copy)]
+pub struct Epochs {
+    start: u64,
+    epoch_len: u64,
+    leaves_per_epoch: usize,
+}
+
+impl Epochs {
+    pub fn new(start: u64, epoch_len: u64, leaves_per_epoch: usize) -> Self {
+        assert!(epoch_len > 0);
+        assert!(leaves_per_epoch > 0);
+
+        Self { start, epoch_len, leaves_per_epoch }
+    }
+
+    /// The epoch `time` falls in, or `None` if it is before the start or
+    /// past any epoch a `usize` can number
+    pub fn epoch(&self, time: u64) -> Option<usize> {
+        let elapsed = time.checked_sub(self.start)?;
+        (elapsed / self.epoch_len).try_into().ok()
+    }
+
+    pub fn leaf_epoch(&self, leaf_idx: usize) -> usize {
+        leaf_idx / self.leaves_per_epoch
+    }
+
+    /// The leaves reserved for `epoch`, or `None` if the epoch is so late
+    /// that their indices overflow, and so past the end of any tree
+    pub fn leaves(&self, epoch: usize) -> Option<Range<usize>> {
+        let start = epoch.checked_mul(self.leaves_per_epoch)?;
+        Some(start..start.checked_add(self.leaves_per_epoch)?)
+    }
+}
+
+
+/// Signing rights for the leaves of one aligned subtree of a Merkle key. It
+/// holds only the seeds of those leaves, along with the authentication path
+/// from the subtree root to the root of the full tree, so its signatures
+/// verify under the full public key.
+pub struct DelegatedKey<N = U256> {
+    start: usize,
+    leaf_seeds: Box<[U256]>,
+    upper_path: Box<[N]>,
+    leaf_idx: usize,
+}
+
+impl<N> DelegatedKey<N> {
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.start + self.leaf_seeds.len()
+    }
+
+    pub fn leaf_idx(&self) -> usize {
+        self.leaf_idx
+    }
+}
+
+
+/// The level hashes of an aligned subtree of a Merkle key, so that giant
+/// trees can be built across several machines and merged with
+/// `Merkle::merge_partials`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PartialTree<N = U256> {
+    start: usize,
+    levels: Vec<Box<[N]>>,
+}
+
+impl<N: Node> PartialTree<N> {
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn height(&self) -> usize {
+        self.levels.len() - 1
+    }
+
+    pub fn root(&self) -> N {
+        self.levels[self.height()][0]
+    }
+
+    /// Start and height as little-endian `u64`s, followed by the nodes of
+    /// each level from the leaves up
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&(self.start as u64).to_le_bytes());
+        bytes.extend_from_slice(&(self.height() as u64).to_le_bytes());
+        for node in self.levels.iter().flat_map(|level| level.iter()) {
+            bytes.extend_from_slice(node.as_ref());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 16 {
+            return None;
+        }
+        let start = usize::try_from(u64::from_le_bytes(bytes[..8].try_into().unwrap())).ok()?;
+        let height = usize::try_from(u64::from_le_bytes(bytes[8..16].try_into().unwrap())).ok()?;
+        // No Merkle key has leaves beyond these
+        if height > MAX_TREE_HEIGHT || start >= 1 << MAX_TREE_HEIGHT || start & ((1 << height) - 1) != 0 {
+            return None;
+        }
+
+        let num_nodes = (1usize << (height + 1)) - 1;
+        if Some(bytes.len() - 16) != num_nodes.checked_mul(N::LEN) {
+            return None;
+        }
+
+        let mut nodes = bytes[16..].chunks_exact(N::LEN);
+        let levels = (0..=height)
+            .map(|h| {
+                nodes.by_ref()
+                    .take(1 << (height - h))
+                    .map(|bytes| {
+                        let mut node = N::default();
+                        node.as_mut().copy_from_slice(bytes);
+                        node
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Some(Self { start, levels })
+    }
+}
+
+
+pub struct Merkle<O, H = Sha256> {
     tree_height: usize,
     ots_scheme: O,
+    hasher: PhantomData<H>,
 }
 
 impl<O: SignatureScheme> Merkle<O>
     where <O as SignatureScheme>::Public: AsRef<[u8]> {
     pub fn new(tree_height: usize, ots_scheme: O) -> Self {
+        assert!((1..=MAX_TREE_HEIGHT).contains(&tree_height));
+
         Self {
             tree_height,
             ots_scheme,
+            hasher: PhantomData,
+        }
+    }
+}
+
+impl<O: SignatureScheme, H: Hasher> Merkle<O, H>
+    where <O as SignatureScheme>::Public: AsRef<[u8]> {
+    /// Hashes the tree with `H2`. The OTS scheme keeps its own hasher.
+    pub fn with_hasher<H2: Hasher>(self) -> Merkle<O, H2> {
+        Merkle {
+            tree_height: self.tree_height,
+            ots_scheme: self.ots_scheme,
+            hasher: PhantomData,
         }
     }
 
+    fn get_leaf_seed(private: U256, idx: usize) -> U256 {
+        H::hash_pair(private, bytes_of(&idx)).truncate()
+    }
+
     fn get_ots_pair(&self, private: U256, idx: usize) -> (O::Private, O::Public) {
-        let node_seed = hash_pair(&private, bytes_of(&idx));
-        self.ots_scheme.gen_keys(Some(node_seed))
+        self.ots_scheme.gen_keys(Some(Self::get_leaf_seed(private, idx)))
     }
 
-    fn get_node(&self, private: U256, height: usize, idx: usize) -> U256 {
+    fn get_node(&self, private: U256, height: usize, idx: usize) -> H::Node {
         if height == self.tree_height {
-            return hash(self.get_ots_pair(private, idx).1);
+            return H::hash_in(Domain::Leaf, self.get_ots_pair(private, idx).1);
         }
 
         let left = self.get_node(private, height + 1, idx * 2);
         let right = self.get_node(private, height + 1, idx * 2 + 1);
-        hash_pair(left, right)
+        H::hash_pair_in(Domain::Node, left, right)
+    }
+
+    /// Leaf public keys for the indices in `range`, derived from a party's own
+    /// secret. A party holding the secret for a range of leaves can sign with
+    /// those leaves using `sign_leaf`.
+    pub fn leaf_publics(&self, party_secret: U256, range: Range<usize>) -> Vec<O::Public> {
+        assert!(range.end <= 1 << self.tree_height);
+        range.map(|idx| self.get_ots_pair(party_secret, idx).1).collect()
+    }
+
+    /// Builds the tree from the leaf public keys of all parties, in order.
+    pub fn from_leaf_publics(&self, leaf_publics: &[O::Public]) -> PublicTree<H> {
+        assert_eq!(leaf_publics.len(), 1 << self.tree_height);
+
+        let leaves: Vec<_> = leaf_publics.iter().map(|public| H::hash_in(Domain::Leaf, public)).collect();
+        PublicTree { tree: MerkleTree::<H>::with_hasher(&leaves) }
+    }
+
+    /// Computes the subtree of the given height whose leftmost leaf is `start`
+    pub fn build_partial(&self, private: &<Self as SignatureScheme>::Private, start: usize,
+                         height: usize) -> PartialTree<H::Node> {
+        assert!(height <= self.tree_height);
+        assert!(start & ((1 << height) - 1) == 0 && start + (1 << height) <= 1 << self.tree_height);
+
+        let leaves: Vec<_> = (start..start + (1 << height))
+            .map(|idx| H::hash_in(Domain::Leaf, self.get_ots_pair(private.0, idx).1))
+            .collect();
+
+        let mut levels = vec![Box::<[H::Node]>::from(leaves)];
+        for _ in 0..height {
+            let next = levels.last().unwrap()
+                .chunks(2)
+                .map(|pair| H::hash_pair_in(Domain::Node, pair[0], pair[1]))
+                .collect();
+            levels.push(next);
+        }
+
+        PartialTree { start, levels }
+    }
+
+    /// Merges subtrees into the public key. Overlapping subtrees must agree on
+    /// every node they share, and together they must cover the whole tree.
+    pub fn merge_partials(&self, parts: &[PartialTree<H::Node>]) -> Option<H::Node> {
+        let mut levels: Vec<Vec<Option<H::Node>>> = (0..=self.tree_height)
+            .map(|h| vec![None; 1 << (self.tree_height - h)])
+            .collect();
+
+        for part in parts {
+            let end = part.start.checked_add(1 << part.height());
+            if part.height() > self.tree_height || end.is_none_or(|end| end > 1 << self.tree_height) {
+                return None;
+            }
+
+            for (h, level) in part.levels.iter().enumerate() {
+                for (i, node) in level.iter().enumerate() {
+                    let slot = &mut levels[h][(part.start >> h) + i];
+                    match slot {
+                        Some(existing) if existing != node => return None,
+                        _ => *slot = Some(*node),
+                    }
+                }
+            }
+        }
+
+        for h in 0..self.tree_height {
+            let (lower, upper) = levels.split_at_mut(h + 1);
+            for (i, slot) in upper[0].iter_mut().enumerate() {
+                let computed = match (lower[h][2 * i], lower[h][2 * i + 1]) {
+                    (Some(left), Some(right)) => Some(H::hash_pair_in(Domain::Node, left, right)),
+                    _ => None,
+                };
+                match (*slot, computed) {
+                    (Some(given), Some(computed)) if given != computed => return None,
+                    (None, computed) => *slot = computed,
+                    _ => {}
+                }
+            }
+        }
+
+        levels[self.tree_height][0]
+    }
+
+    /// Hands out the leaves in `range`, which must be an aligned subtree, e.g.
+    /// to give a build server a bounded slice of the key.
+    pub fn delegate(&self, private: &<Self as SignatureScheme>::Private, range: Range<usize>) -> DelegatedKey<H::Node> {
+        let size = range.len();
+        assert!(size.is_power_of_two() && range.start & (size - 1) == 0 && range.end <= 1 << self.tree_height);
+
+        let height = size.trailing_zeros() as usize;
+        let upper_path = (height..self.tree_height)
+            .map(|h| {
+                let idx = range.start >> h;
+                self.get_node(private.0, self.tree_height - h, idx ^ 1)
+            })
+            .collect();
+
+        DelegatedKey {
+            start: range.start,
+            leaf_seeds: range.clone().map(|idx| Self::get_leaf_seed(private.0, idx)).collect(),
+            upper_path,
+            leaf_idx: range.start,
+        }
+    }
+
+    pub fn sign_delegated(&self, msg: &[u8], key: &DelegatedKey<H::Node>) -> Signature<O, H::Node> {
+        let ots_pairs: Vec<_> = key.leaf_seeds.iter()
+            .map(|&seed| self.ots_scheme.gen_keys(Some(seed)))
+            .collect();
+        let leaves: Vec<_> = ots_pairs.iter().map(|(_, public)| H::hash_in(Domain::Leaf, public)).collect();
+        let sub_tree = MerkleTree::<H>::with_hasher(&leaves);
+
+        let offset = key.leaf_idx - key.start;
+        let (ots_private, leaf_public) = ots_pairs.into_iter().nth(offset).unwrap();
+        let path = sub_tree.prove(offset).path().iter()
+            .chain(key.upper_path.iter())
+            .copied()
+            .collect();
+
+        Signature {
+            leaf_idx: key.leaf_idx,
+            leaf_sig: self.ots_scheme.sign(msg, &ots_private),
+            leaf_public,
+            path,
+        }
+    }
+
+    pub fn next_delegated(&self, mut key: DelegatedKey<H::Node>) -> Option<DelegatedKey<H::Node>> {
+        key.leaf_idx += 1;
+        (key.leaf_idx < key.range().end).then_some(key)
+    }
+
+    pub fn sign_leaf(&self, msg: &[u8], party_secret: U256, leaf_idx: usize) -> LeafSignature<O> {
+        let (ots_private, leaf_public) = self.get_ots_pair(party_secret, leaf_idx);
+        LeafSignature {
+            leaf_idx,
+            leaf_public,
+            leaf_sig: self.ots_scheme.sign(msg, &ots_private),
+        }
+    }
+
+    /// Every hash computed while verifying, for building verification circuits
+    #[cfg(feature = "unstable")]
+    pub fn export_witness(&self, msg: &[u8], public: &H::Node, sig: &Signature<O, H::Node>) -> Witness {
+        witness::export(self, msg, public, sig)
     }
 
     pub fn next_key(&self, mut private: <Self as SignatureScheme>::Private) -> Option<<Self as SignatureScheme>::Private> {
         private.1 += 1;
-        (private.1 < 1 << self.tree_height).then(|| private)
+        (private.1 < 1 << self.tree_height).then_some(private)
+    }
+
+    /// Skips ahead to the leaves of the epoch `now` falls in. Returns `None` if
+    /// the leaves of that epoch are used up, or it is outside the tree.
+    pub fn key_for_time(&self, mut private: <Self as SignatureScheme>::Private, epochs: &Epochs,
+                        now: u64) -> Option<<Self as SignatureScheme>::Private> {
+        let leaves = epochs.leaves(epochs.epoch(now)?)?;
+        private.1 = private.1.max(leaves.start);
+        (private.1 < leaves.end && private.1 < 1 << self.tree_height).then_some(private)
+    }
+
+    /// Verifies `sig` and that it was made at most `max_age` epochs before the
+    /// one `now` falls in.
+    pub fn verify_fresh(&self, msg: &[u8], public: &H::Node, sig: &Signature<O, H::Node>, epochs: &Epochs,
+                        now: u64, max_age: usize) -> bool {
+        let sig_epoch = epochs.leaf_epoch(sig.leaf_idx);
+        let fresh = match epochs.epoch(now) {
+            Some(epoch) => sig_epoch <= epoch && epoch - sig_epoch <= max_age,
+            None => false,
+        };
+
+        fresh && self.verify(msg, public, sig)
     }
 }
 
-impl<O: SignatureScheme> SignatureScheme for Merkle<O>
+impl<O: Estimate, H: Hasher> Merkle<O, H>
+    where <O as SignatureScheme>::Public: AsRef<[u8]> {
+    // Fiat-Shamir style public coin, so the key holder can't pick the leaves
+    fn get_audit_indices(&self, public: &H::Node, num_checks: usize) -> Vec<usize> {
+        (0..num_checks)
+            .map(|i| {
+                // Hashed and reduced as u64s, so the leaves are the same on
+                // 32-bit targets
+                let coin = H::hash_pair(public, (i as u64).to_le_bytes());
+                let idx = u64::from_le_bytes(coin.as_ref()[..8].try_into().unwrap()) % (1u64 << self.tree_height);
+                idx as usize
+            })
+            .collect()
+    }
+
+    pub fn audit_keygen(&self, private: &<Self as SignatureScheme>::Private,
+                        num_checks: usize) -> KeygenAudit<O, H::Node> {
+        let leaf_publics = self.leaf_publics(private.0, 0..1 << self.tree_height);
+        let leaves: Vec<_> = leaf_publics.iter().map(|public| H::hash_in(Domain::Leaf, public)).collect();
+        let tree = MerkleTree::<H>::with_hasher(&leaves);
+
+        let mut leaf_publics: Vec<_> = leaf_publics.into_iter().map(Some).collect();
+        let openings = self.get_audit_indices(&tree.root(), num_checks).into_iter()
+            .filter_map(|idx| {
+                let leaf_public = leaf_publics[idx].take()?;
+                Some((idx, leaf_public, tree.prove(idx).path().into()))
+            })
+            .collect();
+
+        KeygenAudit {
+            prg: KEYGEN_PRG,
+            openings,
+        }
+    }
+
+    pub fn verify_audit(&self, public: &H::Node, audit: &KeygenAudit<O, H::Node>, num_checks: usize) -> bool {
+        let mut indices = self.get_audit_indices(public, num_checks);
+        indices.sort_unstable();
+        indices.dedup();
+
+        let mut opened: Vec<_> = audit.openings.iter().map(|(idx, _, _)| *idx).collect();
+        opened.sort_unstable();
+
+        audit.prg == KEYGEN_PRG
+            && opened == indices
+            && audit.openings.iter().all(|(idx, leaf_public, path)| {
+                leaf_public.as_ref().len() == self.ots_scheme.public_size()
+                    && path.len() == self.tree_height
+                    && get_root::<H>(*idx, leaf_public, path) == *public
+            })
+    }
+}
+
+impl<O: SignatureScheme, H: Hasher> SignatureScheme for Merkle<O, H>
     where <O as SignatureScheme>::Public: AsRef<[u8]> {
     type Private = (U256, usize);
-    type Public = U256;
-    type Signature = Signature<O>;
+    type Public = H::Node;
+    type Signature = Signature<O, H::Node>;
 
     fn gen_keys(&self, seed: Option<U256>) -> (Self::Private, Self::Public) {
         let private = match seed {
@@ -87,29 +548,210 @@ impl<O: SignatureScheme> SignatureScheme for Merkle<O>
     }
 
     fn verify(&self, msg: &[u8], public: &Self::Public, sig: &Self::Signature) -> bool {
+        if sig.path.len() != self.tree_height || sig.leaf_idx >= 1 << self.tree_height {
+            return false;
+        }
         if !self.ots_scheme.verify(msg, &sig.leaf_public, &sig.leaf_sig) {
             return false;
         }
 
-        let root = sig.path.iter()
-            .enumerate()
-            .fold(hash(&sig.leaf_public), |acc, (h, sibling)| {
-                let idx = sig.leaf_idx / (1 << h);
-                if idx % 2 == 0 {
-                    hash_pair(&acc, sibling)
-                } else {
-                    hash_pair(sibling, &acc)
-                }
-            });
+        get_root::<H>(sig.leaf_idx, &sig.leaf_public, &sig.path) == *public
+    }
+
+    fn max_message_len(&self) -> Option<usize> {
+        self.ots_scheme.max_message_len()
+    }
+
+    fn try_sign(&self, msg: &[u8], private: &Self::Private) -> Result<Self::Signature, Error> {
+        check_message_len(self, msg)?;
+        if private.1 >= 1 << self.tree_height {
+            return Err(Error::MalformedKey);
+        }
+
+        Ok(self.sign(msg, private))
+    }
+}
+
+impl<O: Encode, H: Hasher> Encode for Merkle<O, H>
+    where <O as SignatureScheme>::Public: AsRef<[u8]> {
+    /// The leaf index as 8 bytes, the leaf's public key and signature, then
+    /// the authentication path
+    fn write_signature(&self, sig: &Self::Signature, out: &mut Vec<u8>) {
+        out.extend_from_slice(&(sig.leaf_idx as u64).to_le_bytes());
+        self.ots_scheme.write_public(&sig.leaf_public, out);
+        self.ots_scheme.write_signature(&sig.leaf_sig, out);
+        write_nodes(out, &sig.path);
+    }
+
+    fn read_signature(&self, input: &mut Reader) -> Result<Self::Signature, Error> {
+        let leaf_idx = usize::try_from(input.u64()?).map_err(|_| Error::InvalidSignature)?;
+        let leaf_public = self.ots_scheme.read_public(input)?;
+        let leaf_sig = self.ots_scheme.read_signature(input)?;
+        let path = input.nodes(self.tree_height)?;
+
+        Ok(Signature { leaf_idx, leaf_public, leaf_sig, path })
+    }
+
+    fn write_public(&self, public: &H::Node, out: &mut Vec<u8>) {
+        out.extend_from_slice(public.as_ref());
+    }
+
+    fn read_public(&self, input: &mut Reader) -> Result<H::Node, Error> {
+        input.node()
+    }
+}
+
+
+impl<O: Estimate, H: Hasher> Estimate for Merkle<O, H>
+    where <O as SignatureScheme>::Public: AsRef<[u8]> {
+    fn public_size(&self) -> usize {
+        H::Node::LEN
+    }
+
+    fn signature_size(&self) -> usize {
+        8 + self.ots_scheme.public_size() + self.ots_scheme.signature_size() + self.tree_height * H::Node::LEN
+    }
+
+    fn verify_hashes(&self) -> usize {
+        self.ots_scheme.verify_hashes() + 1 + self.tree_height
+    }
+}
+
+impl<O: Estimate, H: Hasher> Composition for Merkle<O, H>
+    where <O as SignatureScheme>::Public: AsRef<[u8]> {
+    type Leaf = O;
+
+    fn leaf_scheme(&self) -> &O {
+        &self.ots_scheme
+    }
+
+    fn first_leaf(&self, seed: U256) -> (O::Private, O::Public) {
+        self.get_ots_pair(StdRng::from_seed(seed).gen(), 0)
+    }
+}
+
+
+/// Merkle with the tree height fixed at compile time, so the number of
+/// leaves and the signer state are known statically, e.g. for signers
+/// reserving storage up front. Out of range heights fail to compile.
+pub struct FixedMerkle<O, const HEIGHT: usize, H = Sha256>(Merkle<O, H>);
+
+pub type MerkleH10<O> = FixedMerkle<O, 10>;
+pub type MerkleH16<O> = FixedMerkle<O, 16>;
+pub type MerkleH20<O> = FixedMerkle<O, 20>;
+
+impl<O: SignatureScheme, const HEIGHT: usize> FixedMerkle<O, HEIGHT>
+    where <O as SignatureScheme>::Public: AsRef<[u8]> {
+    pub fn new(ots_scheme: O) -> Self {
+        let () = Self::VALID_HEIGHT;
+        Self(Merkle::new(HEIGHT, ots_scheme))
+    }
+}
+
+impl<O: SignatureScheme, const HEIGHT: usize, H: Hasher> FixedMerkle<O, HEIGHT, H>
+    where <O as SignatureScheme>::Public: AsRef<[u8]> {
+    const VALID_HEIGHT: () = assert!(HEIGHT >= 1 && HEIGHT <= MAX_TREE_HEIGHT, "tree height out of range");
+
+    pub const NUM_LEAVES: usize = 1 << HEIGHT;
+    /// Nodes in the authentication path of a signature
+    pub const PATH_LEN: usize = HEIGHT;
+    /// Bytes of signer state to persist between signatures: the secret and
+    /// the index of the next leaf
+    pub const STATE_SIZE: usize = 32 + 8;
+
+    pub fn with_hasher<H2: Hasher>(self) -> FixedMerkle<O, HEIGHT, H2> {
+        FixedMerkle(self.0.with_hasher())
+    }
+}
+
+impl<O, const HEIGHT: usize, H> Deref for FixedMerkle<O, HEIGHT, H> {
+    type Target = Merkle<O, H>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<O: SignatureScheme, const HEIGHT: usize, H: Hasher> SignatureScheme for FixedMerkle<O, HEIGHT, H>
+    where <O as SignatureScheme>::Public: AsRef<[u8]> {
+    type Private = (U256, usize);
+    type Public = H::Node;
+    type Signature = Signature<O, H::Node>;
+
+    fn gen_keys(&self, seed: Option<U256>) -> (Self::Private, Self::Public) {
+        self.0.gen_keys(seed)
+    }
+
+    fn sign(&self, msg: &[u8], private: &Self::Private) -> Self::Signature {
+        self.0.sign(msg, private)
+    }
+
+    fn verify(&self, msg: &[u8], public: &Self::Public, sig: &Self::Signature) -> bool {
+        self.0.verify(msg, public, sig)
+    }
+
+    fn max_message_len(&self) -> Option<usize> {
+        self.0.max_message_len()
+    }
+
+    fn try_sign(&self, msg: &[u8], private: &Self::Private) -> Result<Self::Signature, Error> {
+        self.0.try_sign(msg, private)
+    }
+}
+
+impl<O: Encode, const HEIGHT: usize, H: Hasher> Encode for FixedMerkle<O, HEIGHT, H>
+    where <O as SignatureScheme>::Public: AsRef<[u8]> {
+    fn write_signature(&self, sig: &Self::Signature, out: &mut Vec<u8>) {
+        self.0.write_signature(sig, out)
+    }
+
+    fn read_signature(&self, input: &mut Reader) -> Result<Self::Signature, Error> {
+        self.0.read_signature(input)
+    }
+
+    fn write_public(&self, public: &H::Node, out: &mut Vec<u8>) {
+        self.0.write_public(public, out)
+    }
 
-        root == *public
+    fn read_public(&self, input: &mut Reader) -> Result<H::Node, Error> {
+        self.0.read_public(input)
     }
 }
 
+impl<O: Estimate, const HEIGHT: usize, H: Hasher> Estimate for FixedMerkle<O, HEIGHT, H>
+    where <O as SignatureScheme>::Public: AsRef<[u8]> {
+    fn public_size(&self) -> usize {
+        self.0.public_size()
+    }
+
+    fn signature_size(&self) -> usize {
+        self.0.signature_size()
+    }
+
+    fn verify_hashes(&self) -> usize {
+        self.0.verify_hashes()
+    }
+}
+
+impl<O: Estimate, const HEIGHT: usize, H: Hasher> Composition for FixedMerkle<O, HEIGHT, H>
+    where <O as SignatureScheme>::Public: AsRef<[u8]> {
+    type Leaf = O;
+
+    fn leaf_scheme(&self) -> &O {
+        self.0.leaf_scheme()
+    }
+
+    fn first_leaf(&self, seed: U256) -> (O::Private, O::Public) {
+        self.0.first_leaf(seed)
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use crate::lamport::Lamport;
+    use crate::U512;
+    use crate::util::{hash, to_hex, Sha512};
+    use crate::winternitz::Winternitz;
 
     use super::*;
 
@@ -133,4 +775,206 @@ mod tests {
 
         assert!(!merkle.verify(msg1, &public, &sig));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn malformed() {
+        let msg = b"My OS update";
+
+        let merkle = Merkle::new(4, Winternitz::new(16));
+        let (private, public) = merkle.gen_keys(None);
+
+        let mut sig = merkle.sign(msg, &private);
+        sig.leaf_idx = usize::MAX;
+        assert!(!merkle.verify(msg, &public, &sig));
+
+        let mut sig = merkle.sign(msg, &private);
+        sig.path = vec![[0; 32]; 100].into();
+        assert!(!merkle.verify(msg, &public, &sig));
+
+        assert!(matches!(merkle.try_sign(msg, &(private.0, 16)), Err(Error::MalformedKey)));
+    }
+
+    #[test]
+    fn distributed() {
+        let msg = b"My OS update";
+
+        let merkle = Merkle::new(3, Winternitz::new(16));
+        let secret_a = hash(b"Secret of party A");
+        let secret_b = hash(b"Secret of party B");
+
+        let mut leaf_publics = merkle.leaf_publics(secret_a, 0..4);
+        leaf_publics.extend(merkle.leaf_publics(secret_b, 4..8));
+        let tree = merkle.from_leaf_publics(&leaf_publics);
+        let public = tree.root();
+
+        let sig = tree.complete(merkle.sign_leaf(msg, secret_b, 5)).unwrap();
+        assert!(merkle.verify(msg, &public, &sig));
+        assert!(!merkle.verify(b"My important message", &public, &sig));
+
+        // Party A doesn't hold leaf 6
+        assert!(tree.complete(merkle.sign_leaf(msg, secret_a, 6)).is_none());
+
+        // A single party holding all leaves gives the usual public key
+        let (private, public) = merkle.gen_keys(None);
+        let tree = merkle.from_leaf_publics(&merkle.leaf_publics(private.0, 0..8));
+        assert_eq!(tree.root(), public);
+    }
+
+    #[test]
+    fn audit_keygen() {
+        let merkle = Merkle::new(4, Winternitz::new(16));
+        let (private, public) = merkle.gen_keys(None);
+
+        let audit = merkle.audit_keygen(&private, 5);
+        assert!(merkle.verify_audit(&public, &audit, 5));
+        assert!(!merkle.verify_audit(&public, &audit, 0));
+
+        let (_, other_public) = merkle.gen_keys(None);
+        assert!(!merkle.verify_audit(&other_public, &audit, 5));
+
+        // Claiming other OTS parameters fails on the leaf key sizes
+        let claimed = Merkle::new(4, Winternitz::new(4));
+        let audit = KeygenAudit::<Winternitz> { prg: audit.prg, openings: audit.openings };
+        assert!(!claimed.verify_audit(&public, &audit, 5));
+    }
+
+    #[test]
+    fn partial_trees() {
+        let merkle = Merkle::new(4, Winternitz::new(16));
+        let (private, public) = merkle.gen_keys(None);
+
+        let left = merkle.build_partial(&private, 0, 3);
+        let right = merkle.build_partial(&private, 8, 3);
+        let overlap = merkle.build_partial(&private, 4, 2);
+        assert_eq!(left.root(), merkle.get_node(private.0, 1, 0));
+
+        let parts: Vec<_> = [left, right, overlap].iter()
+            .map(|part| PartialTree::from_bytes(&part.to_bytes()).unwrap())
+            .collect();
+        assert_eq!(merkle.merge_partials(&parts), Some(public));
+        assert_eq!(merkle.merge_partials(&parts[1..]), None);
+
+        // A subtree built from another key conflicts with the overlapping one
+        let (other_private, _) = merkle.gen_keys(None);
+        let mut conflicting = parts.clone();
+        conflicting[0] = merkle.build_partial(&other_private, 0, 3);
+        assert_eq!(merkle.merge_partials(&conflicting), None);
+
+        let mut tampered = parts[1].to_bytes();
+        tampered[20] ^= 1;
+        let tampered = PartialTree::from_bytes(&tampered).unwrap();
+        assert_eq!(merkle.merge_partials(&[parts[0].clone(), tampered]), None);
+        assert!(PartialTree::<U256>::from_bytes(&parts[1].to_bytes()[1..]).is_none());
+
+        // Hostile starts and heights are turned away rather than overflowing
+        let mut hostile = parts[1].to_bytes();
+        hostile[..8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(PartialTree::<U256>::from_bytes(&hostile).is_none());
+        let mut hostile = [0; 16];
+        hostile[8..].copy_from_slice(&58u64.to_le_bytes());
+        assert!(PartialTree::<U512>::from_bytes(&hostile).is_none());
+        let hostile = PartialTree { start: usize::MAX - 1, levels: parts[1].levels[2..].to_vec() };
+        assert_eq!(merkle.merge_partials(&[parts[0].clone(), hostile]), None);
+    }
+
+    #[test]
+    fn delegate() {
+        let msg = b"My OS update";
+
+        let merkle = Merkle::new(4, Winternitz::new(16));
+        let (private, public) = merkle.gen_keys(None);
+
+        let mut key = merkle.delegate(&private, 4..8);
+        assert_eq!(key.range(), 4..8);
+        for _ in 0..3 {
+            key = merkle.next_delegated(key).unwrap();
+        }
+        let sig = merkle.sign_delegated(msg, &key);
+        assert_eq!(sig.leaf_idx(), 7);
+        assert!(merkle.verify(msg, &public, &sig));
+        assert!(merkle.next_delegated(key).is_none());
+
+        let whole = merkle.delegate(&private, 0..16);
+        assert!(merkle.verify(msg, &public, &merkle.sign_delegated(msg, &whole)));
+    }
+
+    #[test]
+    fn epochs() {
+        let msg = b"My OS update";
+
+        let merkle = Merkle::new(4, Winternitz::new(16));
+        let (private, public) = merkle.gen_keys(None);
+        let epochs = Epochs::new(1000, 100, 4);
+
+        assert!(merkle.key_for_time(private, &epochs, 999).is_none());
+
+        let private = merkle.key_for_time(private, &epochs, 1250).unwrap();
+        assert_eq!(private.1, 8);
+        let sig = merkle.sign(msg, &private);
+        assert!(merkle.verify_fresh(msg, &public, &sig, &epochs, 1299, 0));
+        assert!(merkle.verify_fresh(msg, &public, &sig, &epochs, 1300, 1));
+        assert!(!merkle.verify_fresh(msg, &public, &sig, &epochs, 1300, 0));
+        assert!(!merkle.verify_fresh(msg, &public, &sig, &epochs, 1199, 1));
+
+        // Used up the leaves of the epoch, or past the end of the tree
+        let private = (private.0, 11);
+        assert!(merkle.next_key(private).and_then(|p| merkle.key_for_time(p, &epochs, 1250)).is_none());
+        assert!(merkle.key_for_time(private, &epochs, 1400).is_none());
+        assert!(merkle.key_for_time(private, &epochs, u64::MAX).is_none());
+
+        // Epochs whose leaf indices overflow
+        let epochs = Epochs::new(0, 1, usize::MAX / 2);
+        assert_eq!(epochs.leaves(1), Some(usize::MAX / 2..usize::MAX - 1));
+        assert_eq!(epochs.leaves(2), None);
+        assert!(merkle.key_for_time(private, &epochs, 3).is_none());
+    }
+
+    #[test]
+    fn signature_size() {
+        let merkle = Merkle::new(4, Winternitz::new(16));
+        let (private, _) = merkle.gen_keys(None);
+        let sig = merkle.sign(b"My OS update", &private);
+
+        let size = 8 + sig.leaf_public.as_ref().len() + sig.leaf_sig.as_ref().len() + sig.path.len() * 32;
+        assert_eq!(merkle.signature_size(), size);
+    }
+
+    #[test]
+    fn fixed_heights() {
+        let msg = b"My OS update";
+
+        assert_eq!((MerkleH10::<Winternitz>::NUM_LEAVES, MerkleH16::<Winternitz>::PATH_LEN), (1024, 16));
+
+        let merkle = FixedMerkle::<_, 3>::new(Winternitz::new(16));
+        let (private, public) = merkle.gen_keys(None);
+        let sig = merkle.sign(msg, &merkle.next_key(private).unwrap());
+        assert!(merkle.verify(msg, &public, &sig));
+        assert!(Merkle::new(3, Winternitz::new(16)).verify(msg, &public, &sig));
+        assert_eq!(sig.path.len(), FixedMerkle::<Winternitz, 3>::PATH_LEN);
+    }
+
+    #[test]
+    #[should_panic]
+    fn too_high() {
+        Merkle::new(MAX_TREE_HEIGHT + 1, Winternitz::new(16));
+    }
+
+    #[test]
+    fn sha512() {
+        let msg = b"My OS update";
+
+        let merkle = Merkle::new(4, Winternitz::new(16)).with_hasher::<Sha512>();
+        let (private, public) = merkle.gen_keys(Some([7; 32]));
+        let sig = merkle.sign(msg, &private);
+        assert!(merkle.verify(msg, &public, &sig));
+        assert_eq!(to_hex(&hash(public)), "40bb6157806fae01b2a36f23c6e8381af0d968b29e8ff571d5969c0f416df13d");
+        assert_eq!(merkle.public_size(), 64);
+
+        // Partial trees carry the wider nodes too
+        let parts: Vec<_> = [0, 8].iter()
+            .map(|&start| merkle.build_partial(&private, start, 3))
+            .map(|part| PartialTree::from_bytes(&part.to_bytes()).unwrap())
+            .collect();
+        assert_eq!(merkle.merge_partials(&parts), Some(public));
+    }
+}
@@ -0,0 +1,104 @@
+use crate::{SignatureScheme, U256};
+use crate::framing::{write_len, Encode, Reader};
+use crate::util::hash_pair;
+
+/// The longest message that can be embedded in a `SignedMessage`
+pub const MAX_MSG_LEN: usize = 32;
+
+/// A short message along with a signature on it, for protocols without a
+/// separate channel for the message. Encoded as the length of the message,
+/// the message and the encoded signature.
+pub struct SignedMessage<S: SignatureScheme> {
+    msg: Box<[u8]>,
+    sig: S::Signature,
+}
+
+// Labelled, so a signed message can't be passed off as a plain signature on
+// the same bytes or vice versa
+fn recovery_msg(msg: &[u8]) -> U256 {
+    hash_pair(b"recoverable message", msg)
+}
+
+impl<S: SignatureScheme> SignedMessage<S> {
+    /// Returns the embedded message if the signature on it is valid
+    pub fn open(&self, scheme: &S, public: &S::Public) -> Option<&[u8]> {
+        scheme.verify(&recovery_msg(&self.msg), public, &self.sig).then_some(&*self.msg)
+    }
+}
+
+impl<S: Encode> SignedMessage<S> {
+    pub fn to_bytes(&self, scheme: &S) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_len(&mut bytes, self.msg.len());
+        bytes.extend_from_slice(&self.msg);
+        scheme.write_signature(&self.sig, &mut bytes);
+        bytes
+    }
+
+    /// Parses a message signed with `scheme`, or returns `None` if it is
+    /// malformed. The signature is only checked by `open`.
+    pub fn from_bytes(scheme: &S, bytes: &[u8]) -> Option<Self> {
+        let mut input = Reader::new(bytes);
+        let msg_len = input.len(MAX_MSG_LEN).ok()?;
+        let msg = input.take(msg_len).ok()?.into();
+        let sig = scheme.decode_signature(input.rest()).ok()?;
+
+        Some(Self { msg, sig })
+    }
+}
+
+pub fn sign_recoverable<S: SignatureScheme>(scheme: &S, private: &S::Private, msg: &[u8]) -> SignedMessage<S> {
+    assert!(msg.len() <= MAX_MSG_LEN);
+
+    SignedMessage {
+        msg: msg.into(),
+        sig: scheme.sign(&recovery_msg(msg), private),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::merkle::Merkle;
+    use crate::winternitz::Winternitz;
+
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let merkle = Merkle::new(3, Winternitz::new(16));
+        let (private, public) = merkle.gen_keys(None);
+
+        let signed = sign_recoverable(&merkle, &private, b"Unlock door 3");
+        assert_eq!(signed.open(&merkle, &public), Some(&b"Unlock door 3"[..]));
+
+        let substituted = SignedMessage::<Merkle<Winternitz>> {
+            msg: b"Unlock door 4"[..].into(),
+            sig: signed.sig,
+        };
+        assert_eq!(substituted.open(&merkle, &public), None);
+
+        // A plain signature on the message doesn't open
+        let plain = SignedMessage::<Merkle<Winternitz>> {
+            msg: b"Unlock door 3"[..].into(),
+            sig: merkle.sign(b"Unlock door 3", &private),
+        };
+        assert_eq!(plain.open(&merkle, &public), None);
+    }
+
+    #[test]
+    fn encoding() {
+        let merkle = Merkle::new(3, Winternitz::new(16));
+        let (private, public) = merkle.gen_keys(None);
+
+        let bytes = sign_recoverable(&merkle, &private, b"Unlock door 3").to_bytes(&merkle);
+        let signed = SignedMessage::from_bytes(&merkle, &bytes).unwrap();
+        assert_eq!(signed.to_bytes(&merkle), bytes);
+        assert_eq!(signed.open(&merkle, &public), Some(&b"Unlock door 3"[..]));
+
+        assert!(SignedMessage::from_bytes(&merkle, &bytes[..bytes.len() - 1]).is_none());
+        let mut too_long = bytes.clone();
+        too_long[0] = MAX_MSG_LEN as u8 + 1;
+        assert!(SignedMessage::from_bytes(&merkle, &too_long).is_none());
+    }
+}
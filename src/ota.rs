@@ -0,0 +1,288 @@
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::error::Error;
+use std::fmt;
+
+use crate::{SignatureScheme, U256};
+use crate::merkle::{Merkle, Signature};
+use crate::util::{hash, hash_pair};
+
+/// States that `version` of an update is the payload hashing to `payload_hash`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Manifest {
+    version: u64,
+    payload_hash: U256,
+}
+
+impl Manifest {
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn payload_hash(&self) -> &U256 {
+        &self.payload_hash
+    }
+
+    fn digest(&self) -> U256 {
+        hash_pair(b"update manifest", [&self.version.to_le_bytes()[..], &self.payload_hash].concat())
+    }
+}
+
+pub struct SignedManifest<O: SignatureScheme> {
+    manifest: Manifest,
+    sig: Signature<O>,
+}
+
+impl<O: SignatureScheme> SignedManifest<O> {
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UpdateError {
+    /// The manifest isn't signed by any trusted key
+    BadSignature,
+    /// The update isn't newer than the installed version
+    Rollback,
+    /// The payload isn't the one in the manifest
+    PayloadMismatch,
+}
+
+impl fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadSignature => write!(f, "manifest isn't signed by a trusted key"),
+            Self::Rollback => write!(f, "update isn't newer than the installed version"),
+            Self::PayloadMismatch => write!(f, "payload doesn't match the manifest"),
+        }
+    }
+}
+
+impl Error for UpdateError {}
+
+
+/// Signs manifests for successive versions of an update with a Merkle key,
+/// using each leaf once.
+pub struct UpdateServer<O> {
+    merkle: Merkle<O>,
+    private: Option<(U256, usize)>,
+    version: u64,
+}
+
+impl<O: SignatureScheme> UpdateServer<O>
+    where <O as SignatureScheme>::Public: AsRef<[u8]> {
+    /// `version` is the last version published with this key, if any
+    pub fn new(merkle: Merkle<O>, private: (U256, usize), version: u64) -> Self {
+        Self { merkle, private: Some(private), version }
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// The key state to persist, along with `version`, or `None` if the key
+    /// is used up. It must be persisted after each `publish` and before the
+    /// manifest is released, or a restart would sign with the same leaf again.
+    pub fn state(&self) -> Option<(U256, usize)> {
+        self.private
+    }
+
+    /// Signs the manifest for the next version, or returns `None` once the
+    /// key is used up.
+    pub fn publish(&mut self, payload: &[u8]) -> Option<SignedManifest<O>> {
+        let private = self.private.take()?;
+        let manifest = Manifest { version: self.version + 1, payload_hash: hash(payload) };
+        let sig = self.merkle.sign(&manifest.digest(), &private);
+
+        self.private = self.merkle.next_key(private);
+        self.version = manifest.version;
+        Some(SignedManifest { manifest, sig })
+    }
+}
+
+
+/// Digests of manifests whose signatures were already checked, so devices
+/// checking on every boot can skip verifying them again, or of chains whose
+/// certificates were, for `ChainVerifier::verify_cached`. Entries expire
+/// `ttl` after they are added, and at most `max_entries` are kept.
+pub struct ManifestCache {
+    max_entries: usize,
+    ttl: u64,
+    expiries: BTreeMap<U256, u64>,
+}
+
+impl ManifestCache {
+    pub fn new(max_entries: usize, ttl: u64) -> Self {
+        Self { max_entries, ttl, expiries: BTreeMap::new() }
+    }
+
+    pub fn contains(&self, digest: &U256, now: u64) -> bool {
+        self.expiries.get(digest).is_some_and(|&expiry| now < expiry)
+    }
+
+    pub fn insert(&mut self, digest: U256, now: u64) {
+        self.expiries.retain(|_, &mut expiry| now < expiry);
+        if self.expiries.len() >= self.max_entries {
+            let soonest = self.expiries.iter().min_by_key(|&(_, expiry)| expiry).map(|(digest, _)| *digest);
+            match soonest {
+                Some(soonest) => self.expiries.remove(&soonest),
+                None => return,
+            };
+        }
+
+        self.expiries.insert(digest, now.saturating_add(self.ttl));
+    }
+
+    /// Each entry as its digest followed by its expiry as a little-endian
+    /// `u64`, for persisting across boots
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.expiries.iter()
+            .flat_map(|(digest, expiry)| digest.iter().copied().chain(expiry.to_le_bytes()))
+            .collect()
+    }
+
+    pub fn from_bytes(bytes: &[u8], max_entries: usize, ttl: u64) -> Option<Self> {
+        if !bytes.len().is_multiple_of(40) || bytes.len() / 40 > max_entries {
+            return None;
+        }
+
+        let expiries = bytes.chunks_exact(40)
+            .map(|entry| (entry[..32].try_into().unwrap(), u64::from_le_bytes(entry[32..].try_into().unwrap())))
+            .collect();
+        Some(Self { max_entries, ttl, expiries })
+    }
+}
+
+
+/// Installs updates signed by any of its trust anchors, refusing to go back
+/// to older versions.
+pub struct UpdateClient<O> {
+    merkle: Merkle<O>,
+    anchors: Vec<U256>,
+    version: u64,
+}
+
+impl<O: SignatureScheme> UpdateClient<O>
+    where <O as SignatureScheme>::Public: AsRef<[u8]> {
+    pub fn new(merkle: Merkle<O>, anchors: Vec<U256>, installed_version: u64) -> Self {
+        Self { merkle, anchors, version: installed_version }
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Checks an update and records its version as installed
+    pub fn accept(&mut self, signed: &SignedManifest<O>, payload: &[u8]) -> Result<(), UpdateError> {
+        self.check(signed, payload, None)
+    }
+
+    /// Like `accept`, but skips verifying the signature on manifests found in
+    /// `cache`, and adds the manifest to it once verified
+    pub fn accept_cached(&mut self, signed: &SignedManifest<O>, payload: &[u8], cache: &mut ManifestCache,
+                         now: u64) -> Result<(), UpdateError> {
+        self.check(signed, payload, Some((cache, now)))
+    }
+
+    fn check(&mut self, signed: &SignedManifest<O>, payload: &[u8],
+             cache: Option<(&mut ManifestCache, u64)>) -> Result<(), UpdateError> {
+        let manifest = &signed.manifest;
+        let digest = manifest.digest();
+        let cached = cache.as_ref().is_some_and(|(cache, now)| cache.contains(&digest, *now));
+        if !cached && !self.anchors.iter().any(|anchor| self.merkle.verify(&digest, anchor, &signed.sig)) {
+            return Err(UpdateError::BadSignature);
+        }
+        if let (Some((cache, now)), false) = (cache, cached) {
+            cache.insert(digest, now);
+        }
+
+        if manifest.version <= self.version {
+            return Err(UpdateError::Rollback);
+        }
+        if hash(payload) != manifest.payload_hash {
+            return Err(UpdateError::PayloadMismatch);
+        }
+
+        self.version = manifest.version;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::winternitz::Winternitz;
+
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let merkle = Merkle::new(2, Winternitz::new(16));
+        let (private, public) = merkle.gen_keys(None);
+
+        let mut server = UpdateServer::new(merkle, private, 0);
+        let mut client = UpdateClient::new(Merkle::new(2, Winternitz::new(16)), vec![public], 0);
+
+        assert_eq!(server.state(), Some(private));
+        let v1 = server.publish(b"Firmware 1").unwrap();
+        let v2 = server.publish(b"Firmware 2").unwrap();
+        assert_eq!(v2.manifest().version(), 2);
+        assert_eq!(server.state(), Some((private.0, 2)));
+
+        assert_eq!(client.accept(&v2, b"Firmware 1"), Err(UpdateError::PayloadMismatch));
+        assert_eq!(client.accept(&v2, b"Firmware 2"), Ok(()));
+        assert_eq!(client.accept(&v1, b"Firmware 1"), Err(UpdateError::Rollback));
+        assert_eq!(client.version(), 2);
+
+        let forged = SignedManifest {
+            manifest: Manifest { version: 3, payload_hash: hash(b"Malware") },
+            sig: v2.sig,
+        };
+        assert_eq!(client.accept(&forged, b"Malware"), Err(UpdateError::BadSignature));
+
+        let mut cache = ManifestCache::new(2, 100);
+        let v3 = server.publish(b"Firmware 3").unwrap();
+        assert_eq!(client.accept_cached(&v3, b"Firmware 3", &mut cache, 1000), Ok(()));
+        assert!(cache.contains(&v3.manifest().digest(), 1099));
+        assert!(!cache.contains(&v3.manifest().digest(), 1100));
+        assert!(server.publish(b"Firmware 4").is_some());
+        assert!(server.publish(b"Firmware 5").is_none());
+        assert_eq!(server.state(), None);
+    }
+
+    #[test]
+    fn restart() {
+        let merkle = || Merkle::new(2, Winternitz::new(16));
+        let (private, public) = merkle().gen_keys(None);
+
+        let mut server = UpdateServer::new(merkle(), private, 0);
+        let v1 = server.publish(b"Firmware 1").unwrap();
+        let (state, version) = (server.state().unwrap(), server.version());
+
+        let mut server = UpdateServer::new(merkle(), state, version);
+        let v2 = server.publish(b"Firmware 2").unwrap();
+        assert_eq!((v1.sig.leaf_idx(), v2.sig.leaf_idx()), (0, 1));
+
+        let mut client = UpdateClient::new(merkle(), vec![public], 0);
+        assert_eq!(client.accept(&v1, b"Firmware 1"), Ok(()));
+        assert_eq!(client.accept(&v2, b"Firmware 2"), Ok(()));
+    }
+
+    #[test]
+    fn manifest_cache() {
+        let mut cache = ManifestCache::new(2, 100);
+        let digests: Vec<_> = (0u8..3).map(|i| hash([i])).collect();
+        cache.insert(digests[0], 0);
+        cache.insert(digests[1], 10);
+        cache.insert(digests[2], 20);
+        assert!(!cache.contains(&digests[0], 20));
+        assert!(cache.contains(&digests[1], 20));
+
+        let restored = ManifestCache::from_bytes(&cache.to_bytes(), 2, 100).unwrap();
+        assert!(restored.contains(&digests[2], 119));
+        assert!(!restored.contains(&digests[2], 120));
+        assert!(ManifestCache::from_bytes(&cache.to_bytes(), 1, 100).is_none());
+    }
+}
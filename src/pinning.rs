@@ -0,0 +1,211 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::{SignatureScheme, U256};
+use crate::util::{hash, hash_pair};
+
+/// The hash of a public key, as encoded by `bech32::fingerprint`
+pub fn fingerprint(public: impl AsRef<[u8]>) -> U256 {
+    hash(public)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Pin {
+    fingerprint: U256,
+    /// When a pin dropped by an update stops being accepted
+    retired_at: Option<u64>,
+}
+
+impl Pin {
+    fn accepted(&self, time: u64) -> bool {
+        self.retired_at.is_none_or(|retired_at| time < retired_at)
+    }
+}
+
+/// Replaces the pinned keys once countersigned by a quorum of the keys
+/// currently pinned. Keys left out stay accepted until `retire_at`, so
+/// signers can switch over without an outage.
+pub struct PinUpdate<S: SignatureScheme> {
+    version: u64,
+    pins: Vec<U256>,
+    quorum: usize,
+    retire_at: u64,
+    sigs: Vec<(S::Public, S::Signature)>,
+}
+
+impl<S: SignatureScheme> PinUpdate<S> where S::Public: AsRef<[u8]> {
+    /// `version` must be above that of the pin set it updates, so updates
+    /// can't be replayed or rolled back
+    pub fn new(version: u64, pins: Vec<U256>, quorum: usize, retire_at: u64) -> Self {
+        Self { version, pins, quorum, retire_at, sigs: Vec::new() }
+    }
+
+    fn msg(&self) -> U256 {
+        let mut msg = Vec::with_capacity(24 + 32 * self.pins.len());
+        msg.extend_from_slice(&self.version.to_le_bytes());
+        msg.extend_from_slice(&(self.quorum as u64).to_le_bytes());
+        msg.extend_from_slice(&self.retire_at.to_le_bytes());
+        for pin in &self.pins {
+            msg.extend_from_slice(pin);
+        }
+
+        hash_pair(b"pin update", msg)
+    }
+
+    /// Countersigns the update. For stateful schemes, the private key must be
+    /// advanced afterwards like after any other signature.
+    pub fn countersign(&mut self, scheme: &S, private: &S::Private, public: S::Public) {
+        let sig = scheme.sign(&self.msg(), private);
+        self.sigs.push((public, sig));
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PinError {
+    /// The update isn't newer than the pin set
+    Stale { version: u64, current: u64 },
+    /// Fewer distinct pinned keys countersigned than the quorum requires
+    NoQuorum { signers: usize, quorum: usize },
+    /// The update has no pins, or a quorum it can't meet itself
+    Malformed,
+}
+
+impl fmt::Display for PinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stale { version, current } => write!(f, "pin update {} isn't newer than version {}", version, current),
+            Self::NoQuorum { signers, quorum } =>
+                write!(f, "pin update countersigned by {} pinned keys, {} required", signers, quorum),
+            Self::Malformed => write!(f, "malformed pin update"),
+        }
+    }
+}
+
+impl Error for PinError {}
+
+/// Accepts signatures only from pinned keys, with the pins rotated through
+/// `PinUpdate`s. Times are whatever unit the caller uses consistently, e.g.
+/// UNIX seconds.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PinSet {
+    version: u64,
+    pins: Vec<Pin>,
+    quorum: usize,
+}
+
+impl PinSet {
+    /// Pins the keys with these fingerprints, requiring `quorum` of them to
+    /// countersign updates
+    pub fn new(fingerprints: impl IntoIterator<Item = U256>, quorum: usize) -> Self {
+        let pins: Vec<_> = fingerprints.into_iter()
+            .map(|fingerprint| Pin { fingerprint, retired_at: None })
+            .collect();
+        assert!((1..=pins.len()).contains(&quorum));
+
+        Self { version: 0, pins, quorum }
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn is_pinned(&self, public: impl AsRef<[u8]>, time: u64) -> bool {
+        let fingerprint = fingerprint(public);
+        self.pins.iter().any(|pin| pin.fingerprint == fingerprint && pin.accepted(time))
+    }
+
+    pub fn verify<S>(&self, scheme: &S, msg: &[u8], public: &S::Public, sig: &S::Signature, time: u64) -> bool
+        where S: SignatureScheme, S::Public: AsRef<[u8]> {
+        self.is_pinned(public, time) && scheme.verify(msg, public, sig)
+    }
+
+    /// Applies `update` if enough of the current pins countersigned it.
+    /// Pins being retired don't count towards the quorum.
+    pub fn apply<S>(&mut self, scheme: &S, update: &PinUpdate<S>, time: u64) -> Result<(), PinError>
+        where S: SignatureScheme, S::Public: AsRef<[u8]> {
+        if update.version <= self.version {
+            return Err(PinError::Stale { version: update.version, current: self.version });
+        }
+        if !(1..=update.pins.len()).contains(&update.quorum) {
+            return Err(PinError::Malformed);
+        }
+
+        let msg = update.msg();
+        let active: Vec<_> = self.pins.iter()
+            .filter(|pin| pin.retired_at.is_none())
+            .map(|pin| pin.fingerprint)
+            .collect();
+        let mut signers: Vec<_> = update.sigs.iter()
+            .filter(|(public, sig)| scheme.verify(&msg, public, sig))
+            .map(|(public, _)| fingerprint(public))
+            .filter(|fingerprint| active.contains(fingerprint))
+            .collect();
+        signers.sort_unstable();
+        signers.dedup();
+        if signers.len() < self.quorum {
+            return Err(PinError::NoQuorum { signers: signers.len(), quorum: self.quorum });
+        }
+
+        // Pins already being retired keep the earlier deadline
+        let retiring = self.pins.iter()
+            .filter(|pin| !update.pins.contains(&pin.fingerprint) && pin.accepted(time))
+            .map(|pin| {
+                let retired_at = pin.retired_at.map_or(update.retire_at, |at| at.min(update.retire_at));
+                Pin { retired_at: Some(retired_at), ..*pin }
+            });
+        let pins = update.pins.iter()
+            .map(|&fingerprint| Pin { fingerprint, retired_at: None })
+            .chain(retiring)
+            .collect();
+
+        *self = Self { version: update.version, pins, quorum: update.quorum };
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Range;
+
+    use crate::horst::Horst;
+    use crate::sphincs::Sphincs;
+    use crate::winternitz::Winternitz;
+
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let msg = b"My OS update";
+        let sphincs = Sphincs::new(2, 2, Winternitz::new(16), Horst::new(8, 64));
+        let keys: Vec<_> = (0..4).map(|_| sphincs.gen_keys(None)).collect();
+        let fingerprints = |range: Range<usize>| keys[range].iter().map(|(_, public)| fingerprint(public)).collect();
+
+        let mut pins = PinSet::new(fingerprints(0..3), 2);
+        let sig = sphincs.sign(msg, &keys[0].0);
+        assert!(pins.verify(&sphincs, msg, &keys[0].1, &sig, 0));
+        let sig = sphincs.sign(msg, &keys[3].0);
+        assert!(!pins.verify(&sphincs, msg, &keys[3].1, &sig, 0));
+
+        // Rotate key 0 out for key 3
+        let mut update = PinUpdate::new(1, fingerprints(1..4), 2, 100);
+        // The same key counts once, and key 3 isn't pinned yet
+        update.countersign(&sphincs, &keys[1].0, keys[1].1);
+        update.countersign(&sphincs, &keys[1].0, keys[1].1);
+        update.countersign(&sphincs, &keys[3].0, keys[3].1);
+        assert_eq!(pins.apply(&sphincs, &update, 50), Err(PinError::NoQuorum { signers: 1, quorum: 2 }));
+
+        update.countersign(&sphincs, &keys[2].0, keys[2].1);
+        assert_eq!(pins.apply(&sphincs, &update, 50), Ok(()));
+        assert_eq!(pins.apply(&sphincs, &update, 50), Err(PinError::Stale { version: 1, current: 1 }));
+
+        assert!(pins.verify(&sphincs, msg, &keys[3].1, &sig, 50));
+        assert!(pins.is_pinned(keys[0].1, 99));
+        assert!(!pins.is_pinned(keys[0].1, 100));
+
+        // Retiring keys can't push another update through
+        let mut update = PinUpdate::new(2, fingerprints(0..1), 1, 200);
+        update.countersign(&sphincs, &keys[0].0, keys[0].1);
+        assert_eq!(pins.apply(&sphincs, &update, 60), Err(PinError::NoQuorum { signers: 0, quorum: 2 }));
+    }
+}
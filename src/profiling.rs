@@ -0,0 +1,146 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::hint::black_box;
+
+/// Peak resource usage of an operation, in bytes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Profile {
+    /// Highest heap usage above what was allocated when the operation began.
+    /// Only tracked with `ProfilingAllocator` as the global allocator.
+    pub peak_heap: usize,
+    /// Deepest stack reached below the caller of `measure`, as seen by the
+    /// probe in `Hasher`, which every recursive tree walk bottoms out in
+    pub max_stack: usize,
+}
+
+impl Profile {
+    /// The larger of each field, for keeping the worst case seen
+    pub fn max(self, other: Self) -> Self {
+        Self {
+            peak_heap: self.peak_heap.max(other.peak_heap),
+            max_stack: self.max_stack.max(other.max_stack),
+        }
+    }
+}
+
+thread_local! {
+    // Bytes currently allocated by this thread, and the most since the
+    // innermost `measure` began. Frees of memory allocated on other threads
+    // can make the count negative.
+    static HEAP: Cell<(isize, isize)> = const { Cell::new((0, 0)) };
+    // Stack address at the start of the innermost `measure`, zero if none
+    // is running, and the lowest address probed since
+    static STACK: Cell<(usize, usize)> = const { Cell::new((0, usize::MAX)) };
+}
+
+fn track(delta: isize) {
+    // The thread-local may be gone while the thread is being torn down
+    let _ = HEAP.try_with(|heap| {
+        let (current, peak) = heap.get();
+        let current = current + delta;
+        heap.set((current, peak.max(current)));
+    });
+}
+
+/// Forwards to the system allocator, counting the bytes each thread has
+/// allocated. Install it with `#[global_allocator]` to get heap figures.
+pub struct ProfilingAllocator;
+
+unsafe impl GlobalAlloc for ProfilingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            track(layout.size() as isize);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        track(-(layout.size() as isize));
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            track(new_size as isize - layout.size() as isize);
+        }
+        new_ptr
+    }
+}
+
+fn stack_address() -> usize {
+    let marker = 0u8;
+    black_box(&marker) as *const u8 as usize
+}
+
+/// Records how deep the stack is here, if a `measure` is running
+#[inline(never)]
+pub fn probe() {
+    let address = stack_address();
+    let _ = STACK.try_with(|stack| {
+        let (base, lowest) = stack.get();
+        if base != 0 {
+            stack.set((base, lowest.min(address)));
+        }
+    });
+}
+
+/// Runs `f`, returning its peak heap and stack usage on this thread along
+/// with its result. Calls may be nested.
+pub fn measure<T>(f: impl FnOnce() -> T) -> (T, Profile) {
+    let base = stack_address();
+    let outer_stack = STACK.with(|stack| stack.replace((base, base)));
+    let (start, outer_peak) = HEAP.with(|heap| {
+        let (current, peak) = heap.get();
+        heap.set((current, current));
+        (current, peak)
+    });
+
+    let result = f();
+
+    let lowest = STACK.with(|stack| {
+        let (_, lowest) = stack.get();
+        stack.set((outer_stack.0, outer_stack.1.min(lowest)));
+        lowest
+    });
+    let peak = HEAP.with(|heap| {
+        let (current, peak) = heap.get();
+        heap.set((current, outer_peak.max(peak)));
+        peak
+    });
+
+    let profile = Profile {
+        peak_heap: (peak - start).max(0) as usize,
+        max_stack: base.saturating_sub(lowest),
+    };
+    (result, profile)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::SignatureScheme;
+    use crate::horst::Horst;
+
+    use super::*;
+
+    #[global_allocator]
+    static ALLOCATOR: ProfilingAllocator = ProfilingAllocator;
+
+    #[test]
+    fn it_works() {
+        let (_, profile) = measure(|| black_box(vec![0u8; 1 << 20]));
+        assert!(profile.peak_heap >= 1 << 20);
+
+        let horst = Horst::new(12, 48);
+        let ((private, _), keygen) = measure(|| horst.gen_keys(None));
+        // The private key is only the seed, not a value per leaf
+        assert!(keygen.peak_heap < (1 << 12) * 32);
+        assert!(keygen.max_stack > 0);
+
+        // Nested measurements count towards the outer one
+        let (inner, outer) = measure(|| measure(|| horst.sign(b"My OS update", &private)).1);
+        assert!(outer.peak_heap >= inner.peak_heap && outer.max_stack >= inner.max_stack);
+    }
+}
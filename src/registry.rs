@@ -0,0 +1,131 @@
+use std::any::Any;
+use std::convert::TryFrom;
+
+use crate::SignatureScheme;
+use crate::framing::Encode;
+use crate::horst::Horst;
+use crate::lamport::Lamport;
+use crate::merkle::Merkle;
+use crate::spec::{SchemeSpec, SpecError};
+use crate::sphincs::Sphincs;
+use crate::winternitz::Winternitz;
+
+/// A scheme with its key and signature types erased, so that schemes only
+/// known at runtime, e.g. from a `SchemeSpec`, can be used side by side.
+/// Keys and signatures of the wrong type are rejected rather than panicking.
+pub trait ErasedScheme {
+    fn verify(&self, msg: &[u8], public: &dyn Any, sig: &dyn Any) -> bool;
+
+    /// The encoding of `public`, or `None` if it isn't a key of this scheme
+    fn public_bytes(&self, public: &dyn Any) -> Option<Vec<u8>>;
+
+    /// Decodes a key of this scheme, or returns `None` if `bytes` isn't one
+    fn decode_public(&self, bytes: &[u8]) -> Option<Box<dyn Any>>;
+
+    /// Decodes a signature of this scheme, or returns `None` if `bytes` isn't
+    /// one
+    fn decode_signature(&self, bytes: &[u8]) -> Option<Box<dyn Any>>;
+}
+
+impl<S> ErasedScheme for S
+    where S: Encode, S::Public: AsRef<[u8]> + 'static, S::Signature: 'static {
+    fn verify(&self, msg: &[u8], public: &dyn Any, sig: &dyn Any) -> bool {
+        match (public.downcast_ref(), sig.downcast_ref()) {
+            (Some(public), Some(sig)) => SignatureScheme::verify(self, msg, public, sig),
+            _ => false,
+        }
+    }
+
+    fn public_bytes(&self, public: &dyn Any) -> Option<Vec<u8>> {
+        public.downcast_ref::<S::Public>().map(|public| public.as_ref().to_vec())
+    }
+
+    fn decode_public(&self, bytes: &[u8]) -> Option<Box<dyn Any>> {
+        let public = Encode::decode_public(self, bytes).ok()?;
+        Some(Box::new(public))
+    }
+
+    fn decode_signature(&self, bytes: &[u8]) -> Option<Box<dyn Any>> {
+        let sig = Encode::decode_signature(self, bytes).ok()?;
+        Some(Box::new(sig))
+    }
+}
+
+type Builder = Box<dyn Fn(&SchemeSpec) -> Option<Box<dyn ErasedScheme>> + Send + Sync>;
+
+/// Builds erased schemes from specs, trying each registered scheme type in
+/// turn. The default registry has the SHA-256 instantiations of Lamport,
+/// Winternitz, HORST, Merkle over either OTS and SPHINCS over Winternitz and
+/// HORST.
+pub struct Registry {
+    builders: Vec<Builder>,
+}
+
+impl Registry {
+    /// A registry without any schemes
+    pub fn empty() -> Self {
+        Self { builders: Vec::new() }
+    }
+
+    pub fn with_scheme<S>(mut self) -> Self
+        where S: ErasedScheme + for<'a> TryFrom<&'a SchemeSpec, Error = SpecError> + 'static {
+        self.builders.push(Box::new(|spec| {
+            S::try_from(spec).ok().map(|scheme| Box::new(scheme) as Box<dyn ErasedScheme>)
+        }));
+        self
+    }
+
+    pub fn build(&self, spec: &SchemeSpec) -> Result<Box<dyn ErasedScheme>, SpecError> {
+        self.builders.iter()
+            .find_map(|builder| builder(spec))
+            .ok_or_else(|| SpecError::new(format!("no registered scheme for '{}'", spec)))
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::empty()
+            .with_scheme::<Lamport>()
+            .with_scheme::<Winternitz>()
+            .with_scheme::<Horst>()
+            .with_scheme::<Merkle<Winternitz>>()
+            .with_scheme::<Merkle<Lamport>>()
+            .with_scheme::<Sphincs<Winternitz, Horst>>()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::framing::Encode;
+
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let msg = b"My OS update";
+        let registry = Registry::default();
+
+        let merkle = Merkle::new(3, Winternitz::new(16));
+        let (private, public) = merkle.gen_keys(None);
+        let sig = merkle.sign(msg, &private);
+
+        let erased = registry.build(&"merkle-wots16-h3".parse().unwrap()).unwrap();
+        assert!(erased.verify(msg, &public, &sig));
+        assert!(!erased.verify(b"Another update", &public, &sig));
+        assert_eq!(erased.public_bytes(&public), Some(public.to_vec()));
+
+        let decoded = erased.decode_signature(&merkle.encode_signature(&sig)).unwrap();
+        assert!(erased.verify(msg, &*erased.decode_public(&public).unwrap(), &*decoded));
+        assert!(erased.decode_public(&public[..31]).is_none());
+
+        // Other parameters, or keys of other types, don't verify
+        let erased = registry.build(&"merkle-wots4-h3".parse().unwrap()).unwrap();
+        assert!(!erased.verify(msg, &public, &sig));
+        assert!(!erased.verify(msg, &public.to_vec(), &sig));
+        assert_eq!(erased.public_bytes(&0u8), None);
+
+        assert!(registry.build(&"goldreich-lamport-n64-h8".parse().unwrap()).is_err());
+        assert!(Registry::empty().build(&"wots16".parse().unwrap()).is_err());
+    }
+}
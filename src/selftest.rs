@@ -0,0 +1,229 @@
+use std::error::Error;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::{Estimate, SignatureScheme, U256};
+use crate::budget::{Budget, SystemClock};
+use crate::goldreich::Goldreich;
+use crate::horst::Horst;
+use crate::lamport::Lamport;
+use crate::merkle::Merkle;
+use crate::sphincs::Sphincs;
+#[cfg(feature = "blake3")]
+use crate::util::Blake3;
+#[cfg(feature = "shake")]
+use crate::util::Shake256;
+use crate::util::{hash, hex, Hasher, Sha512, Sha512_256};
+use crate::winternitz::{CompressedWinternitz, Winternitz, WotsPlus};
+
+const SEED: U256 = [0x42; 32];
+const MSG: &[u8] = b"Power-on self-test";
+
+const fn kat<const N: usize>(hex: &str) -> [u8; N] {
+    match hex::decode(hex) {
+        Some(bytes) => bytes,
+        None => panic!("malformed known answer"),
+    }
+}
+
+// Digests of "abc" from each hash backend
+const SHA256_ABC: U256 = kat("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+const SHA512_256_ABC: U256 = kat("53048e2681941ef99b2e29b76b4c7dabe4c2d0c634fc6d46e0e2f13107e7af23");
+const SHA512_ABC: [u8; 64] = kat("ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+    2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f");
+#[cfg(feature = "shake")]
+const SHAKE256_ABC: U256 = kat("483366601360a8771c6863080cc4114d8db44530f8f1e1ee4f94ea37e78b5739");
+#[cfg(feature = "blake3")]
+const BLAKE3_ABC: U256 = kat("6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85");
+
+// Hashes of the public keys generated from SEED by each check below
+const LAMPORT_KAT: U256 = kat("086f9d661ea1ebdc5208780602ba1b5d99bedf900e5ae09b4d1423834c72b913");
+const WINTERNITZ_KAT: U256 = kat("a95fac72d693b024f1e01e9895c19516e15cede5dcb6c31c027e0256983e55b1");
+const HORST_KAT: U256 = kat("4199433f22dccf5dec1a5f6e8983507b00bb859d172e152084b531859d11b0d8");
+const MERKLE_KAT: U256 = kat("878130887cc81d6d66387666a8cfe447c0746d4a48b50b74acda3e6bdb054850");
+const GOLDREICH_KAT: U256 = kat("758a4091e36ee2c2d46b77634df3ffa7207fbee13655a4b426f45d2196310a87");
+const SPHINCS_KAT: U256 = kat("1a84f5d4bc4a0d09fed2b3dd98ec0daba5ea801ccb64b47c1f1399eaba56ee4f");
+const WOTS_PLUS_KAT: U256 = kat("0de112686f023c18ffd3a7a37864386dfab00e8a3f0419fd44c6ea3dc63625e1");
+const COMPRESSED_WINTERNITZ_KAT: U256 = kat("1a71bcc6b0b4a537151860862e0ba6f25dee3b6376857f3c2ae7ffe354d87da9");
+const MERKLE_SHA512_256_KAT: U256 = kat("513747daac699b25759ab19c5e7bbc6b840ab1f02449f709bb2752275012da3f");
+const HORST_SHA512_KAT: U256 = kat("e88e6a224ca270d0a548ac2504ecc6c4d337392353120bb4bcd7e235d3772d16");
+#[cfg(feature = "shake")]
+const MERKLE_SHAKE256_KAT: U256 = kat("419eb6b4d075f007d05793fcb4205520851719f91e3f3c5a572727bde1c92e5d");
+#[cfg(feature = "blake3")]
+const MERKLE_BLAKE3_KAT: U256 = kat("d046c148a658ba0f46bb7706d6f7ca99fe1d55703e86a4dd497c539b039888cd");
+
+pub struct SelfTestReport {
+    results: Vec<(&'static str, bool)>,
+}
+
+impl SelfTestReport {
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|&(_, passed)| passed)
+    }
+
+    /// The outcome of each check by name
+    pub fn results(&self) -> &[(&'static str, bool)] {
+        &self.results
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.results.iter()
+            .filter(|&&(_, passed)| !passed)
+            .map(|&(name, _)| name)
+    }
+}
+
+/// The Merkle check instantiated with `H` throughout
+fn merkle<H: Hasher<Node = U256>>() -> Merkle<Winternitz<H>, H> {
+    Merkle::new(3, Winternitz::new(16).with_hasher::<H>()).with_hasher::<H>()
+}
+
+/// Generates keys from a fixed seed, compares the public key against a known
+/// answer, and checks that a signature verifies and doesn't verify for
+/// another message.
+fn check_scheme<S: SignatureScheme>(scheme: &S, public_bytes: impl Fn(&S::Public) -> Vec<u8>, expected: U256) -> bool {
+    let (private, public) = scheme.gen_keys(Some(SEED));
+    let sig = scheme.sign(MSG, &private);
+
+    hash(public_bytes(&public)) == expected
+        && scheme.verify(MSG, &public, &sig)
+        && !scheme.verify(b"power-on self-test", &public, &sig)
+}
+
+/// Runs fast known-answer and pairwise consistency tests for every hash
+/// backend and signature scheme, in the spirit of a FIPS power-on
+/// self-test. Long-running services should call this at startup and refuse
+/// to sign if it doesn't pass.
+pub fn self_test() -> SelfTestReport {
+    #[allow(unused_mut)]
+    let mut results = vec![
+        ("sha256", hash(b"abc") == SHA256_ABC),
+        ("sha512/256", Sha512_256::digest(&[b"abc"]) == SHA512_256_ABC),
+        ("sha512", Sha512::digest(&[b"abc"]).as_ref() == SHA512_ABC),
+        ("lamport", check_scheme(&Lamport::new(32), |p| p.as_ref().to_vec(), LAMPORT_KAT)),
+        ("winternitz", check_scheme(&Winternitz::new(16), |p| p.as_ref().to_vec(), WINTERNITZ_KAT)),
+        ("horst", check_scheme(&Horst::new(8, 32), |p| p.to_vec(), HORST_KAT)),
+        ("merkle", check_scheme(&Merkle::new(3, Winternitz::new(16)), |p| p.to_vec(), MERKLE_KAT)),
+        ("goldreich", check_scheme(&Goldreich::new(4, Lamport::new(64)), |p| p.0.as_ref().to_vec(), GOLDREICH_KAT)),
+        ("sphincs", check_scheme(&Sphincs::new(2, 2, Winternitz::new(16), Horst::new(8, 64)), |p| p.to_vec(), SPHINCS_KAT)),
+        ("wots+", check_scheme(&WotsPlus::new(16), |p| p.as_ref().to_vec(), WOTS_PLUS_KAT)),
+        ("compressed-winternitz", check_scheme(&CompressedWinternitz::new(16), |p| p.to_vec(), COMPRESSED_WINTERNITZ_KAT)),
+        ("merkle/sha512/256", check_scheme(&merkle::<Sha512_256>(), |p| p.to_vec(), MERKLE_SHA512_256_KAT)),
+        ("horst/sha512", check_scheme(&Horst::new(8, 32).with_hasher::<Sha512>(), |p| p.as_ref().to_vec(), HORST_SHA512_KAT)),
+    ];
+
+    #[cfg(feature = "shake")]
+    results.extend([
+        ("shake256", Shake256::digest(&[b"abc"]) == SHAKE256_ABC),
+        ("merkle/shake256", check_scheme(&merkle::<Shake256>(), |p| p.to_vec(), MERKLE_SHAKE256_KAT)),
+    ]);
+    #[cfg(feature = "blake3")]
+    results.extend([
+        ("blake3", Blake3::digest(&[b"abc"]) == BLAKE3_ABC),
+        ("merkle/blake3", check_scheme(&merkle::<Blake3>(), |p| p.to_vec(), MERKLE_BLAKE3_KAT)),
+    ]);
+
+    SelfTestReport { results }
+}
+
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompositionError(String);
+
+impl fmt::Display for CompositionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid composition: {}", self.0)
+    }
+}
+
+impl Error for CompositionError {}
+
+/// A scheme signing messages with the keys at its leaves, e.g. the OTS
+/// leaves of a Merkle tree, which `validate_composition` checks through a
+/// single leaf rather than a whole key
+pub trait Composition: Estimate {
+    type Leaf: Estimate;
+
+    fn leaf_scheme(&self) -> &Self::Leaf;
+
+    /// The keys of the first leaf of the key generated from `seed`
+    fn first_leaf(&self, seed: U256) -> (<Self::Leaf as SignatureScheme>::Private,
+                                         <Self::Leaf as SignatureScheme>::Public);
+
+    /// What a leaf signs for `msg`
+    fn leaf_msg(&self, msg: &[u8]) -> Box<[u8]> {
+        msg.into()
+    }
+}
+
+/// Checks a composition of schemes before relying on it, without generating
+/// a whole key: that the first leaf's keys are regenerated from the same
+/// seed, and that its signature on the longest accepted message can be made
+/// without panicking, verifies, doesn't verify for another message, and
+/// takes no more hashes to verify than the leaf scheme's `verify_hashes`
+/// claims. The estimates of the composition must cover the leaf scheme's.
+pub fn validate_composition<S: Composition>(scheme: &S) -> Result<(), CompositionError>
+    where <S::Leaf as SignatureScheme>::Public: AsRef<[u8]> {
+    let error = |msg: &str| Err(CompositionError(msg.into()));
+
+    let leaf = scheme.leaf_scheme();
+    if scheme.signature_size() < leaf.signature_size() || scheme.verify_hashes() < leaf.verify_hashes() {
+        return error("estimates don't cover the leaf scheme");
+    }
+
+    let msg = vec![0x42; scheme.max_message_len().unwrap_or(64).max(1)];
+    let mut other_msg = msg.clone();
+    other_msg[0] ^= 1;
+    let (msg, other_msg) = (scheme.leaf_msg(&msg), scheme.leaf_msg(&other_msg));
+
+    let signed = panic::catch_unwind(AssertUnwindSafe(|| {
+        let (private, public) = scheme.first_leaf(SEED);
+        let regenerated = scheme.first_leaf(SEED).1;
+        (leaf.sign(&msg, &private), public, regenerated)
+    }));
+    let (sig, public, regenerated) = match signed {
+        Ok(signed) => signed,
+        Err(_) => return error("signing panicked"),
+    };
+    if public.as_ref() != regenerated.as_ref() {
+        return error("leaf keys aren't regenerated from their seed");
+    }
+
+    // Counted by a budget of exactly the estimate, rather than by recording
+    let valid = Budget::default()
+        .with_max_hash_calls(leaf.verify_hashes())
+        .with_clock(SystemClock)
+        .run(|| leaf.verify(&msg, &public, &sig));
+
+    match valid {
+        Err(_) => error("verifying takes more hashes than estimated"),
+        Ok(false) => error("signature doesn't verify"),
+        Ok(true) if leaf.verify(&other_msg, &public, &sig) => error("signature verifies for another message"),
+        Ok(true) => Ok(()),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let report = self_test();
+        assert!(report.passed(), "failed: {:?}", report.failures().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn validate() {
+        assert_eq!(validate_composition(&Merkle::new(3, Winternitz::new(16))), Ok(()));
+        assert_eq!(validate_composition(&Goldreich::new(4, Lamport::new(32))), Ok(()));
+        assert_eq!(validate_composition(&Sphincs::new(2, 2, Winternitz::new(4), Horst::new(8, 64))), Ok(()));
+
+        assert!(validate_composition(&Merkle::new(3, Lamport::new(0))).is_err());
+
+        // Only a single leaf is generated, however tall the tree
+        assert_eq!(validate_composition(&Merkle::new(20, Winternitz::new(16))), Ok(()));
+        assert_eq!(validate_composition(&Goldreich::new(256, Lamport::new(32))), Ok(()));
+    }
+}
@@ -0,0 +1,148 @@
+use crate::{SignatureScheme, U256};
+use crate::util::hash_pair;
+
+/// A signature covering a message along with its sequence number
+pub struct Sequenced<Sig> {
+    seq: u64,
+    sig: Sig,
+}
+
+impl<Sig> Sequenced<Sig> {
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    pub fn sig(&self) -> &Sig {
+        &self.sig
+    }
+}
+
+// The session is signed along with the sequence number, so a message can't
+// be replayed into another session with the same key
+fn sequenced_msg(session_id: &U256, seq: u64, msg: &[u8]) -> U256 {
+    hash_pair(b"sequenced message", [&session_id[..], &seq.to_le_bytes(), msg].concat())
+}
+
+/// Numbers the messages of a session, so verifiers can reject replays
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SequenceSigner {
+    session_id: U256,
+    next_seq: u64,
+}
+
+impl SequenceSigner {
+    /// Continues the session `session_id` at `next_seq`, as persisted from
+    /// `next_seq` earlier, or starts it at 0. Every session signed with the
+    /// same key needs its own id, e.g. a random one.
+    pub fn new(session_id: U256, next_seq: u64) -> Self {
+        Self { session_id, next_seq }
+    }
+
+    pub fn session_id(&self) -> &U256 {
+        &self.session_id
+    }
+
+    /// The state to persist, before sending the last signed message, so that
+    /// a restarted signer can't reuse a sequence number
+    pub fn next_seq(&self) -> u64 {
+        self.next_seq
+    }
+
+    pub fn sign<S: SignatureScheme>(&mut self, scheme: &S, private: &S::Private, msg: &[u8]) -> Sequenced<S::Signature> {
+        let seq = self.next_seq;
+        self.next_seq = seq.checked_add(1).expect("sequence numbers used up");
+
+        Sequenced { seq, sig: scheme.sign(&sequenced_msg(&self.session_id, seq, msg), private) }
+    }
+}
+
+/// Accepts messages only with sequence numbers above any accepted before.
+/// Gaps are allowed, so lost messages don't stall the session.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SequenceVerifier {
+    session_id: U256,
+    last_seq: Option<u64>,
+}
+
+impl SequenceVerifier {
+    /// Continues the session `session_id` after `last_seq`, as persisted from
+    /// `last_seq` earlier, or starts it with `None`
+    pub fn new(session_id: U256, last_seq: Option<u64>) -> Self {
+        Self { session_id, last_seq }
+    }
+
+    pub fn session_id(&self) -> &U256 {
+        &self.session_id
+    }
+
+    pub fn last_seq(&self) -> Option<u64> {
+        self.last_seq
+    }
+
+    pub fn verify<S: SignatureScheme>(&mut self, scheme: &S, public: &S::Public, msg: &[u8],
+                                      sig: &Sequenced<S::Signature>) -> bool {
+        if self.last_seq.is_some_and(|last| sig.seq <= last) {
+            return false;
+        }
+        if !scheme.verify(&sequenced_msg(&self.session_id, sig.seq, msg), public, &sig.sig) {
+            return false;
+        }
+
+        self.last_seq = Some(sig.seq);
+        true
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::horst::Horst;
+    use crate::sphincs::Sphincs;
+    use crate::util::hash;
+    use crate::winternitz::Winternitz;
+
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let sphincs = Sphincs::new(2, 2, Winternitz::new(16), Horst::new(8, 64));
+        let (private, public) = sphincs.gen_keys(None);
+
+        let session_id = hash(b"Valve control session");
+        let mut signer = SequenceSigner::new(session_id, 0);
+        let open = signer.sign(&sphincs, &private, b"open valve");
+        let close = signer.sign(&sphincs, &private, b"close valve");
+        let reopen = signer.sign(&sphincs, &private, b"open valve");
+        assert_eq!((open.seq(), reopen.seq(), signer.next_seq()), (0, 2, 3));
+
+        let mut verifier = SequenceVerifier::new(session_id, None);
+        assert!(verifier.verify(&sphincs, &public, b"open valve", &open));
+        assert!(!verifier.verify(&sphincs, &public, b"open valve", &open));
+
+        // Skipping a message is fine, but going back to it isn't
+        assert!(verifier.verify(&sphincs, &public, b"open valve", &reopen));
+        assert!(!verifier.verify(&sphincs, &public, b"close valve", &close));
+        assert_eq!(verifier.last_seq(), Some(2));
+
+        // Nor can the sequence number be changed
+        let forged = Sequenced { seq: 5, sig: reopen.sig };
+        assert!(!verifier.verify(&sphincs, &public, b"open valve", &forged));
+
+        let mut restarted = SequenceSigner::new(session_id, signer.next_seq());
+        let next = restarted.sign(&sphincs, &private, b"close valve");
+        assert!(SequenceVerifier::new(session_id, verifier.last_seq()).verify(&sphincs, &public, b"close valve", &next));
+    }
+
+    #[test]
+    fn sessions() {
+        let sphincs = Sphincs::new(2, 2, Winternitz::new(16), Horst::new(8, 64));
+        let (private, public) = sphincs.gen_keys(None);
+        let (first, second) = (hash(b"First session"), hash(b"Second session"));
+
+        // A message of one session can't be replayed into another one at the
+        // same sequence number
+        let open = SequenceSigner::new(first, 0).sign(&sphincs, &private, b"open valve");
+        assert!(!SequenceVerifier::new(second, None).verify(&sphincs, &public, b"open valve", &open));
+        assert!(SequenceVerifier::new(first, None).verify(&sphincs, &public, b"open valve", &open));
+    }
+}
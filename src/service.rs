@@ -0,0 +1,321 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, PoisonError, mpsc};
+use std::thread::{self, JoinHandle};
+
+use crate::{SignatureScheme, U256};
+use crate::merkle::{Merkle, Signature};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Priority {
+    Normal,
+    /// Served before any queued normal requests
+    High,
+}
+
+type Reply<O> = mpsc::Sender<Option<Signature<O>>>;
+
+/// Writes the key state somewhere durable, with `None` once the key is used
+/// up. The worker calls it before handing out each signature, so a crash can
+/// at worst skip a leaf but never reuse one, as well as on `flush`, `close`
+/// and drop.
+pub type Persist = Box<dyn FnMut(Option<(U256, usize)>) + Send>;
+
+enum Request<O: SignatureScheme> {
+    Sign(Box<[u8]>, Reply<O>),
+    Flush(mpsc::Sender<()>),
+}
+
+struct Queue<O: SignatureScheme> {
+    high: VecDeque<Request<O>>,
+    normal: VecDeque<Request<O>>,
+    shutdown: bool,
+}
+
+type Shared<O> = Arc<(Mutex<Queue<O>>, Condvar)>;
+
+// Fails the queued requests and turns away new ones if the worker dies, so
+// no requester waits forever
+struct Abandon<'a, O: SignatureScheme>(&'a Mutex<Queue<O>>);
+
+impl<O: SignatureScheme> Drop for Abandon<'_, O> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            let mut queue = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+            queue.shutdown = true;
+            queue.high.clear();
+            queue.normal.clear();
+        }
+    }
+}
+
+/// Owns a Merkle private key on a worker thread, so any number of threads can
+/// request signatures while the key state is advanced strictly in order.
+pub struct SignerService<O: SignatureScheme> {
+    shared: Shared<O>,
+    worker: Option<JoinHandle<Option<(U256, usize)>>>,
+}
+
+impl<O> SignerService<O>
+    where O: SignatureScheme + Send + 'static, O::Private: Send, O::Public: AsRef<[u8]> + Send,
+          O::Signature: Send {
+    pub fn new(merkle: Merkle<O>, private: (U256, usize), persist: Persist) -> Self {
+        let shared: Shared<O> = Arc::new((
+            Mutex::new(Queue { high: VecDeque::new(), normal: VecDeque::new(), shutdown: false }),
+            Condvar::new(),
+        ));
+
+        let worker_shared = Arc::clone(&shared);
+        let worker = thread::spawn(move || Self::run(merkle, private, persist, worker_shared));
+
+        Self { shared, worker: Some(worker) }
+    }
+
+    fn run(merkle: Merkle<O>, private: (U256, usize), mut persist: Persist,
+           shared: Shared<O>) -> Option<(U256, usize)> {
+        let (queue, available) = &*shared;
+        let _abandon = Abandon(queue);
+        let mut private = Some(private);
+        loop {
+            let request = {
+                let mut queue = queue.lock().unwrap();
+                loop {
+                    if let Some(request) = queue.high.pop_front().or_else(|| queue.normal.pop_front()) {
+                        break Some(request);
+                    }
+                    if queue.shutdown {
+                        break None;
+                    }
+                    queue = available.wait(queue).unwrap();
+                }
+            };
+
+            // The queue is unlocked again, so a panicking sink can't poison it
+            match request {
+                None => {
+                    persist(private);
+                    return private;
+                }
+                Some(Request::Sign(msg, reply)) => {
+                    let sig = private.take().map(|key| {
+                        private = merkle.next_key(key);
+                        persist(private);
+                        merkle.sign(&msg, &key)
+                    });
+                    // The requester may have stopped waiting
+                    let _ = reply.send(sig);
+                }
+                Some(Request::Flush(done)) => {
+                    persist(private);
+                    let _ = done.send(());
+                }
+            }
+        }
+    }
+
+    fn push(&self, request: Request<O>, priority: Priority) {
+        let (queue, available) = &*self.shared;
+        let mut queue = queue.lock().unwrap();
+        if queue.shutdown {
+            // Dropping the request fails it
+            return;
+        }
+        match priority {
+            Priority::High => queue.high.push_back(request),
+            Priority::Normal => queue.normal.push_back(request),
+        }
+        available.notify_one();
+    }
+
+    /// Signs `msg` with the next leaf, blocking until the worker gets to it.
+    /// Returns `None` once the key is used up, or if the worker died.
+    pub fn sign(&self, msg: &[u8], priority: Priority) -> Option<Signature<O>> {
+        let (reply, response) = mpsc::channel();
+        self.push(Request::Sign(msg.into(), reply), priority);
+
+        response.recv().ok().flatten()
+    }
+
+    /// Blocks until the requests queued so far are signed and the key state
+    /// is persisted
+    pub fn flush(&self) {
+        let (done, flushed) = mpsc::channel();
+        self.push(Request::Flush(done), Priority::Normal);
+
+        // A dead worker persisted the state before its last signature
+        let _ = flushed.recv();
+    }
+
+    /// Finishes the queued requests and stops the worker, returning the key
+    /// state it persisted last, or `None` if the key is used up or the worker
+    /// died
+    pub fn close(mut self) -> Option<(U256, usize)> {
+        self.stop()
+    }
+}
+
+impl<O: SignatureScheme> SignerService<O> {
+    fn stop(&mut self) -> Option<(U256, usize)> {
+        let (queue, available) = &*self.shared;
+        queue.lock().unwrap_or_else(PoisonError::into_inner).shutdown = true;
+        available.notify_one();
+
+        self.worker.take()?.join().ok().flatten()
+    }
+}
+
+impl<O: SignatureScheme> Drop for SignerService<O> {
+    /// Finishes the queued requests and persists the key state before
+    /// stopping the worker
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::winternitz::{Key, Winternitz};
+
+    use super::*;
+
+    type Persisted = Arc<Mutex<Vec<Option<(U256, usize)>>>>;
+
+    fn persisted() -> (Persisted, Persist) {
+        let states = Persisted::default();
+        let sink = Arc::clone(&states);
+        (states, Box::new(move |state| sink.lock().unwrap().push(state)))
+    }
+
+    /// Winternitz that crashes while signing "crash"
+    struct Crashing(Winternitz);
+
+    impl SignatureScheme for Crashing {
+        type Private = U256;
+        type Public = Key;
+        type Signature = Key;
+
+        fn gen_keys(&self, seed: Option<U256>) -> (Self::Private, Self::Public) {
+            self.0.gen_keys(seed)
+        }
+
+        fn sign(&self, msg: &[u8], private: &Self::Private) -> Self::Signature {
+            assert_ne!(msg, b"crash", "crashed mid-sign");
+            self.0.sign(msg, private)
+        }
+
+        fn verify(&self, msg: &[u8], public: &Self::Public, sig: &Self::Signature) -> bool {
+            self.0.verify(msg, public, sig)
+        }
+    }
+
+    #[test]
+    fn it_works() {
+        let merkle = Merkle::new(3, Winternitz::new(16));
+        let (private, public) = merkle.gen_keys(None);
+        let service = SignerService::new(merkle, private, persisted().1);
+
+        let sigs: Vec<_> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..8u8)
+                .map(|i| {
+                    let service = &service;
+                    scope.spawn(move || {
+                        let priority = if i % 2 == 0 { Priority::High } else { Priority::Normal };
+                        (i, service.sign(&[i], priority).unwrap())
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+        });
+
+        let verifier = Merkle::new(3, Winternitz::new(16));
+        let mut leaves: Vec<_> = sigs.iter()
+            .map(|(i, sig)| {
+                assert!(verifier.verify(&[*i], &public, sig));
+                sig.leaf_idx()
+            })
+            .collect();
+        leaves.sort_unstable();
+        assert_eq!(leaves, (0..8).collect::<Vec<_>>());
+
+        assert!(service.sign(b"One too many", Priority::High).is_none());
+        assert_eq!(service.close(), None);
+    }
+
+    #[test]
+    fn close() {
+        let merkle = Merkle::new(3, Winternitz::new(16));
+        let (private, _) = merkle.gen_keys(None);
+        let (states, persist) = persisted();
+        let service = SignerService::new(merkle, private, persist);
+
+        service.sign(b"My OS update", Priority::Normal).unwrap();
+        assert_eq!(service.close(), Some((private.0, 1)));
+        assert_eq!(*states.lock().unwrap(), [Some((private.0, 1)); 2]);
+    }
+
+    #[test]
+    fn flush_and_drop() {
+        let merkle = Merkle::new(3, Winternitz::new(16));
+        let (private, _) = merkle.gen_keys(None);
+        let (states, persist) = persisted();
+        let service = SignerService::new(merkle, private, persist);
+
+        service.sign(b"My OS update", Priority::Normal).unwrap();
+        service.flush();
+        assert_eq!(states.lock().unwrap().last(), Some(&Some((private.0, 1))));
+
+        // Dropping without closing still leaves the state persisted
+        service.sign(b"My important message", Priority::High).unwrap();
+        drop(service);
+        assert_eq!(states.lock().unwrap().last(), Some(&Some((private.0, 2))));
+    }
+
+    #[test]
+    fn crash_mid_sign() {
+        let merkle = Merkle::new(3, Crashing(Winternitz::new(16)));
+        let (private, public) = merkle.gen_keys(None);
+        let (states, persist) = persisted();
+        let service = SignerService::new(merkle, private, persist);
+
+        let first = service.sign(b"My OS update", Priority::Normal).unwrap();
+        assert!(service.sign(b"crash", Priority::Normal).is_none());
+        assert!(service.sign(b"My important message", Priority::Normal).is_none());
+        service.flush();
+        assert_eq!(service.close(), None);
+
+        // The crashed signature's leaf was persisted as used, so the restarted
+        // service skips it rather than issuing it again
+        let persisted = states.lock().unwrap().last().copied().flatten().unwrap();
+        assert_eq!(persisted, (private.0, 2));
+        let merkle = Merkle::new(3, Crashing(Winternitz::new(16)));
+        let service = SignerService::new(merkle, persisted, Box::new(|_| ()));
+        let next = service.sign(b"My important message", Priority::Normal).unwrap();
+        assert_eq!((first.leaf_idx(), next.leaf_idx()), (0, 2));
+        assert!(Merkle::new(3, Crashing(Winternitz::new(16))).verify(b"My important message", &public, &next));
+    }
+
+    #[test]
+    fn persist_panics() {
+        // Fails on its second write, which comes with the second signature
+        let sink = |mut writes: usize| -> Persist {
+            Box::new(move |_| {
+                writes += 1;
+                assert!(writes < 2, "disk full");
+            })
+        };
+
+        let merkle = || Merkle::new(3, Winternitz::new(16));
+        let (private, _) = merkle().gen_keys(None);
+        let service = SignerService::new(merkle(), private, sink(0));
+        service.sign(b"My OS update", Priority::Normal).unwrap();
+        assert!(service.sign(b"My important message", Priority::Normal).is_none());
+        assert!(service.sign(b"My other message", Priority::Normal).is_none());
+        assert_eq!(service.close(), None);
+
+        // Failing on the final write at shutdown, with or without closing
+        let service = SignerService::new(merkle(), private, sink(1));
+        assert_eq!(service.close(), None);
+        let service = SignerService::new(merkle(), private, sink(1));
+        drop(service);
+    }
+}
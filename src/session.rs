@@ -0,0 +1,155 @@
+use std::convert::TryInto;
+
+use crate::{SignatureScheme, U256};
+use crate::framing::{write_len, Encode, Reader};
+use crate::tree::{leaf_hash, MerkleTree, Proof};
+use crate::util::{hash, hash_pair_in, Domain};
+use crate::winternitz::{Key, Winternitz};
+
+/// A session transcript signed with the one-time key of that session, along
+/// with the proof that the key is part of the issuer's commitment. Encoded
+/// as the session id, the key, the signature and then the proof.
+pub struct Token {
+    session_id: usize,
+    public: Key,
+    sig: Key,
+    proof: Proof,
+}
+
+impl Token {
+    pub fn session_id(&self) -> usize {
+        self.session_id
+    }
+
+    pub fn to_bytes(&self, winternitz: &Winternitz) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_len(&mut bytes, self.session_id);
+        winternitz.write_public(&self.public, &mut bytes);
+        winternitz.write_signature(&self.sig, &mut bytes);
+        bytes.extend_from_slice(&self.proof.to_bytes());
+        bytes
+    }
+
+    /// Parses a token whose keys are of `winternitz`, or returns `None` if it
+    /// is malformed
+    pub fn from_bytes(winternitz: &Winternitz, bytes: &[u8]) -> Option<Self> {
+        let mut input = Reader::new(bytes);
+        let session_id = input.u64().ok()?.try_into().ok()?;
+        let public = winternitz.read_public(&mut input).ok()?;
+        let sig = winternitz.read_signature(&mut input).ok()?;
+        let proof = Proof::from_bytes(input.rest())?;
+
+        Some(Self { session_id, public, sig, proof })
+    }
+}
+
+
+/// Issues session tokens from a master seed. Each session gets its own
+/// Winternitz key derived from the seed and the session id, and the public
+/// keys of all sessions are committed to in a Merkle tree, so the server only
+/// needs the root to verify tokens.
+pub struct SessionIssuer {
+    master_seed: U256,
+    winternitz: Winternitz,
+    tree: MerkleTree,
+    /// One bit per session, set once its key has signed
+    issued: Box<[u64]>,
+}
+
+impl SessionIssuer {
+    pub fn new(master_seed: U256, winternitz: Winternitz, num_sessions: usize) -> Self {
+        let leaves: Vec<_> = (0..num_sessions)
+            .map(|id| leaf_hash(Self::get_keys(master_seed, &winternitz, id).1))
+            .collect();
+
+        Self {
+            master_seed,
+            winternitz,
+            tree: MerkleTree::new(&leaves),
+            issued: vec![0; num_sessions.div_ceil(64)].into_boxed_slice(),
+        }
+    }
+
+    // The id is hashed as a u64, so keys are the same on 32-bit targets
+    fn get_keys(master_seed: U256, winternitz: &Winternitz, session_id: usize) -> (U256, Key) {
+        let seed = hash_pair_in(Domain::Secret, master_seed, (session_id as u64).to_le_bytes());
+        winternitz.gen_keys(Some(seed))
+    }
+
+    pub fn commitment(&self) -> U256 {
+        self.tree.root()
+    }
+
+    /// Signs the hash of `transcript` with the key of `session_id`, or returns
+    /// `None` if the commitment has no such session, or its one-time key has
+    /// already signed.
+    pub fn issue(&mut self, session_id: usize, transcript: &[u8]) -> Option<Token> {
+        if session_id >= self.tree.len() {
+            return None;
+        }
+        let (word, bit) = (session_id / 64, 1 << (session_id % 64));
+        if self.issued[word] & bit != 0 {
+            return None;
+        }
+        self.issued[word] |= bit;
+
+        let (private, public) = Self::get_keys(self.master_seed, &self.winternitz, session_id);
+        let sig = self.winternitz.sign(&hash(transcript), &private);
+
+        Some(Token {
+            session_id,
+            public,
+            sig,
+            proof: self.tree.prove(session_id),
+        })
+    }
+}
+
+
+pub fn verify_token(winternitz: &Winternitz, commitment: &U256, transcript: &[u8], token: &Token) -> bool {
+    token.proof.leaf_idx() == token.session_id
+        && token.proof.verify(leaf_hash(&token.public), commitment)
+        && winternitz.verify(&hash(transcript), &token.public, &token.sig)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let winternitz = Winternitz::new(16);
+        let mut issuer = SessionIssuer::new(hash(b"My master seed"), winternitz, 20);
+        let commitment = issuer.commitment();
+
+        let token = issuer.issue(7, b"My handshake transcript").unwrap();
+        assert!(verify_token(&winternitz, &commitment, b"My handshake transcript", &token));
+        assert!(!verify_token(&winternitz, &commitment, b"Another transcript", &token));
+
+        let mut token = issuer.issue(8, b"My handshake transcript").unwrap();
+        token.session_id = 9;
+        assert!(!verify_token(&winternitz, &commitment, b"My handshake transcript", &token));
+
+        // Each session's key signs only once
+        assert!(issuer.issue(7, b"Another transcript").is_none());
+        assert!(issuer.issue(19, b"Another transcript").is_some());
+
+        assert!(issuer.issue(20, b"My handshake transcript").is_none());
+        assert!(issuer.issue(usize::MAX, b"My handshake transcript").is_none());
+    }
+
+    #[test]
+    fn encoding() {
+        let winternitz = Winternitz::new(16);
+        let mut issuer = SessionIssuer::new(hash(b"My master seed"), winternitz, 5);
+
+        let bytes = issuer.issue(3, b"My handshake transcript").unwrap().to_bytes(&winternitz);
+        let token = Token::from_bytes(&winternitz, &bytes).unwrap();
+        assert_eq!(token.to_bytes(&winternitz), bytes);
+        assert!(verify_token(&winternitz, &issuer.commitment(), b"My handshake transcript", &token));
+
+        assert!(Token::from_bytes(&winternitz, &bytes[..bytes.len() - 1]).is_none());
+        assert!(Token::from_bytes(&Winternitz::new(4), &bytes).is_none());
+    }
+}
@@ -0,0 +1,377 @@
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+#[cfg(feature = "serde")]
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{SignatureScheme, U256};
+use crate::util::{accepts_len, hash_pair};
+use crate::goldreich::{self, Goldreich};
+use crate::horst::{self, Horst};
+use crate::lamport::Lamport;
+use crate::merkle::{self, Merkle};
+use crate::sphincs::{self, Sphincs};
+use crate::winternitz::{self, Winternitz};
+
+/// A scheme along with its parameters, written as dash-separated tokens:
+///
+/// * `lamport-n64`: Lamport signing messages of up to 64 bytes
+/// * `wots16`: Winternitz with w = 16
+/// * `horst-t65536-k32`: HORST with t = 65536 leaves and k = 32
+/// * `merkle-wots16-h10`: Merkle of height 10 over the given OTS
+/// * `goldreich-lamport-n64-h256`: Goldreich of height 256 over the given OTS
+/// * `sphincs-d12-h5-wots16-horst-t65536-k32`: SPHINCS with 12 layers of
+///   height 5 subtrees over the given OTS and FTS
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SchemeSpec {
+    Lamport { msg_len: usize },
+    Winternitz { w: usize },
+    Horst { height: usize, k: usize },
+    Merkle { tree_height: usize, ots: Box<SchemeSpec> },
+    Goldreich { tree_height: usize, ots: Box<SchemeSpec> },
+    Sphincs { depth: usize, sub_tree_height: usize, ots: Box<SchemeSpec>, fts: Box<SchemeSpec> },
+}
+
+/// How many signatures a key of a scheme can safely make
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SchemeKind {
+    OneTime,
+    /// Security degrades with every signature
+    FewTime,
+    /// Many signatures, as long as the private key is advanced after each
+    Stateful,
+    Stateless,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpecError(String);
+
+impl SpecError {
+    pub(crate) fn new(msg: impl Into<String>) -> Self {
+        Self(msg.into())
+    }
+}
+
+impl fmt::Display for SpecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid scheme spec: {}", self.0)
+    }
+}
+
+impl Error for SpecError {}
+
+fn error<T>(msg: impl Into<String>) -> Result<T, SpecError> {
+    Err(SpecError::new(msg))
+}
+
+impl SchemeSpec {
+    fn parse_tokens<'a>(tokens: &mut impl Iterator<Item = &'a str>) -> Result<Self, SpecError> {
+        fn param<'a>(tokens: &mut impl Iterator<Item = &'a str>, prefix: &str) -> Result<usize, SpecError> {
+            let token = match tokens.next() {
+                Some(token) => token,
+                None => return error(format!("missing parameter '{}'", prefix)),
+            };
+            match token.strip_prefix(prefix).and_then(|n| n.parse().ok()) {
+                Some(n) if n > 0 => Ok(n),
+                _ => error(format!("expected '{}<n>', found '{}'", prefix, token)),
+            }
+        }
+
+        let name = match tokens.next() {
+            Some(name) => name,
+            None => return error("missing scheme name"),
+        };
+
+        let spec = match name {
+            "lamport" => Self::Lamport { msg_len: param(tokens, "n")? },
+            "horst" => {
+                let t = param(tokens, "t")?;
+                if !t.is_power_of_two() || t == 1 {
+                    return error(format!("HORST t must be a power of two, found {}", t));
+                }
+                if t.trailing_zeros() as usize > horst::MAX_HEIGHT {
+                    return error(format!("HORST t is at most {}", 1 << horst::MAX_HEIGHT));
+                }
+                let (height, k) = (t.trailing_zeros() as usize, param(tokens, "k")?);
+                if horst::top_levels(k) > height {
+                    return error(format!("HORST t must be at least {} for k = {}", 1 << horst::top_levels(k), k));
+                }
+                Self::Horst { height, k }
+            }
+            "merkle" => {
+                let ots = Box::new(Self::parse_tokens(tokens)?);
+                let tree_height = param(tokens, "h")?;
+                if tree_height > merkle::MAX_TREE_HEIGHT {
+                    return error(format!("Merkle trees are at most {} high", merkle::MAX_TREE_HEIGHT));
+                }
+                Self::Merkle { tree_height, ots }
+            }
+            "goldreich" => {
+                let ots = Box::new(Self::parse_tokens(tokens)?);
+                Self::Goldreich { tree_height: param(tokens, "h")?, ots }
+            }
+            "sphincs" => {
+                let depth = param(tokens, "d")?;
+                let sub_tree_height = param(tokens, "h")?;
+                if sub_tree_height > merkle::MAX_TREE_HEIGHT {
+                    return error(format!("SPHINCS subtrees are at most {} high", merkle::MAX_TREE_HEIGHT));
+                }
+                if sphincs::hypertree_height(depth, sub_tree_height).is_none() {
+                    return error(format!("SPHINCS hypertrees are at most {} high", sphincs::MAX_HYPERTREE_HEIGHT));
+                }
+                let ots = Box::new(Self::parse_tokens(tokens)?);
+                let fts = Box::new(Self::parse_tokens(tokens)?);
+                Self::Sphincs { depth, sub_tree_height, ots, fts }
+            }
+            _ => match name.strip_prefix("wots").and_then(|w| w.parse::<usize>().ok()) {
+                Some(w) if w > winternitz::MAX_W => return error(format!("Winternitz w is at most {}", winternitz::MAX_W)),
+                Some(w) if w.is_power_of_two() && w > 1 => Self::Winternitz { w },
+                Some(w) => return error(format!("Winternitz w must be a power of two, found {}", w)),
+                None => return error(format!("unknown scheme '{}'", name)),
+            },
+        };
+
+        Ok(spec)
+    }
+
+    pub fn kind(&self) -> SchemeKind {
+        match self {
+            Self::Lamport { .. } | Self::Winternitz { .. } => SchemeKind::OneTime,
+            Self::Horst { .. } => SchemeKind::FewTime,
+            Self::Merkle { .. } => SchemeKind::Stateful,
+            Self::Goldreich { .. } | Self::Sphincs { .. } => SchemeKind::Stateless,
+        }
+    }
+
+    /// Identifies the parameters along with the values derived from them, so
+    /// that changes to how they are derived show up when comparing setups
+    pub fn parameter_digest(&self) -> U256 {
+        let mut derived = Vec::new();
+        self.push_derived(&mut derived);
+        hash_pair(self.to_string(), derived)
+    }
+
+    fn push_derived(&self, derived: &mut Vec<u8>) {
+        match self {
+            Self::Lamport { .. } | Self::Winternitz { .. } | Self::Horst { .. } => {}
+            Self::Merkle { ots, .. } | Self::Goldreich { ots, .. } => ots.push_derived(derived),
+            Self::Sphincs { depth, sub_tree_height, ots, fts } => {
+                let idx_len = sphincs::idx_len(*depth, *sub_tree_height).map_or(u64::MAX, |len| len as u64);
+                derived.extend_from_slice(&idx_len.to_le_bytes());
+                ots.push_derived(derived);
+                fts.push_derived(derived);
+            }
+        }
+    }
+}
+
+impl FromStr for SchemeSpec {
+    type Err = SpecError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tokens = s.split('-');
+        let spec = Self::parse_tokens(&mut tokens)?;
+        match tokens.next() {
+            Some(token) => error(format!("unexpected '{}'", token)),
+            None => Ok(spec),
+        }
+    }
+}
+
+impl fmt::Display for SchemeSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Lamport { msg_len } => write!(f, "lamport-n{}", msg_len),
+            Self::Winternitz { w } => write!(f, "wots{}", w),
+            Self::Horst { height, k } => write!(f, "horst-t{}-k{}", 1usize << height, k),
+            Self::Merkle { tree_height, ots } => write!(f, "merkle-{}-h{}", ots, tree_height),
+            Self::Goldreich { tree_height, ots } => write!(f, "goldreich-{}-h{}", ots, tree_height),
+            Self::Sphincs { depth, sub_tree_height, ots, fts } =>
+                write!(f, "sphincs-d{}-h{}-{}-{}", depth, sub_tree_height, ots, fts),
+        }
+    }
+}
+
+/// Written as the spec string
+#[cfg(feature = "serde")]
+impl Serialize for SchemeSpec {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for SchemeSpec {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(de::Error::custom)
+    }
+}
+
+
+fn mismatch<T>(expected: &str, spec: &SchemeSpec) -> Result<T, SpecError> {
+    error(format!("expected {} but spec is '{}'", expected, spec))
+}
+
+impl TryFrom<&SchemeSpec> for Lamport {
+    type Error = SpecError;
+
+    fn try_from(spec: &SchemeSpec) -> Result<Self, Self::Error> {
+        match *spec {
+            SchemeSpec::Lamport { msg_len } => Ok(Lamport::new(msg_len)),
+            _ => mismatch("Lamport", spec),
+        }
+    }
+}
+
+impl TryFrom<&SchemeSpec> for Winternitz {
+    type Error = SpecError;
+
+    fn try_from(spec: &SchemeSpec) -> Result<Self, Self::Error> {
+        match *spec {
+            SchemeSpec::Winternitz { w } => Ok(Winternitz::new(w)),
+            _ => mismatch("Winternitz", spec),
+        }
+    }
+}
+
+impl TryFrom<&SchemeSpec> for Horst {
+    type Error = SpecError;
+
+    fn try_from(spec: &SchemeSpec) -> Result<Self, Self::Error> {
+        match *spec {
+            SchemeSpec::Horst { height, k } => Ok(Horst::new(height, k)),
+            _ => mismatch("HORST", spec),
+        }
+    }
+}
+
+impl<O> TryFrom<&SchemeSpec> for Merkle<O>
+    where O: SignatureScheme + for<'a> TryFrom<&'a SchemeSpec, Error = SpecError>,
+          <O as SignatureScheme>::Public: AsRef<[u8]> {
+    type Error = SpecError;
+
+    fn try_from(spec: &SchemeSpec) -> Result<Self, Self::Error> {
+        match spec {
+            SchemeSpec::Merkle { tree_height, ots } => Ok(Merkle::new(*tree_height, O::try_from(ots)?)),
+            _ => mismatch("Merkle", spec),
+        }
+    }
+}
+
+impl<O> TryFrom<&SchemeSpec> for Goldreich<O>
+    where O: SignatureScheme + for<'a> TryFrom<&'a SchemeSpec, Error = SpecError> {
+    type Error = SpecError;
+
+    fn try_from(spec: &SchemeSpec) -> Result<Self, Self::Error> {
+        match spec {
+            SchemeSpec::Goldreich { tree_height, ots } => {
+                let ots_scheme = O::try_from(ots)?;
+                if !accepts_len(&ots_scheme, 32) {
+                    return error(format!("'{}' can't sign the 32-byte hashes in '{}'", ots, spec));
+                }
+                if *tree_height > goldreich::MAX_TREE_HEIGHT {
+                    return error(format!("Goldreich trees are at most {} high", goldreich::MAX_TREE_HEIGHT));
+                }
+                Ok(Goldreich::new(*tree_height, ots_scheme))
+            }
+            _ => mismatch("Goldreich", spec),
+        }
+    }
+}
+
+impl<O, F> TryFrom<&SchemeSpec> for Sphincs<O, F>
+    where O: SignatureScheme + Clone + for<'a> TryFrom<&'a SchemeSpec, Error = SpecError>,
+          F: SignatureScheme + for<'a> TryFrom<&'a SchemeSpec, Error = SpecError>,
+          <O as SignatureScheme>::Public: AsRef<[u8]>, <F as SignatureScheme>::Public: AsRef<[u8]> {
+    type Error = SpecError;
+
+    fn try_from(spec: &SchemeSpec) -> Result<Self, Self::Error> {
+        match spec {
+            SchemeSpec::Sphincs { depth, sub_tree_height, ots, fts } => {
+                let (ots_scheme, fts_scheme) = (O::try_from(ots)?, F::try_from(fts)?);
+                if !accepts_len(&ots_scheme, 32) {
+                    return error(format!("'{}' can't sign the 32-byte roots in '{}'", ots, spec));
+                }
+                if !accepts_len(&fts_scheme, 64) {
+                    return error(format!("'{}' can't sign the 64-byte digests in '{}'", fts, spec));
+                }
+                if sphincs::hypertree_height(*depth, *sub_tree_height).is_none() {
+                    return error(format!("SPHINCS hypertrees are at most {} high", sphincs::MAX_HYPERTREE_HEIGHT));
+                }
+                Ok(Sphincs::new(*depth, *sub_tree_height, ots_scheme, fts_scheme))
+            }
+            _ => mismatch("SPHINCS", spec),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        for s in &["lamport-n64", "wots16", "horst-t65536-k32", "merkle-wots16-h10",
+                   "goldreich-lamport-n64-h256", "sphincs-d12-h5-wots16-horst-t65536-k32"] {
+            let spec: SchemeSpec = s.parse().unwrap();
+            assert_eq!(spec.to_string(), *s);
+        }
+
+        assert_eq!("merkle-wots16-h10".parse(), Ok(SchemeSpec::Merkle {
+            tree_height: 10,
+            ots: Box::new(SchemeSpec::Winternitz { w: 16 }),
+        }));
+
+        for s in &["", "wots", "wots15", "horst-t100-k32", "merkle-wots16", "lamport-n64-h3", "lamport-x64",
+                   "horst-t2097152-k32", "merkle-wots16-h25", "sphincs-d1-h25-wots16-horst-t256-k64", "wots512",
+                   "merkle-wots1024-h3", "horst-t16-k32", "sphincs-d32-h4-wots16-horst-t256-k64",
+                   "sphincs-d999999999999999999-h24-wots16-horst-t256-k64"] {
+            assert!(s.parse::<SchemeSpec>().is_err(), "{}", s);
+        }
+
+        // Built by hand, bypassing the parser
+        let spec = SchemeSpec::Sphincs {
+            depth: usize::MAX / 2,
+            sub_tree_height: usize::MAX / 2,
+            ots: Box::new(SchemeSpec::Winternitz { w: 16 }),
+            fts: Box::new(SchemeSpec::Horst { height: 8, k: 64 }),
+        };
+        assert!(Sphincs::<Winternitz, Horst>::try_from(&spec).is_err());
+        spec.parameter_digest();
+    }
+
+    #[test]
+    fn parameter_digest() {
+        let spec: SchemeSpec = "sphincs-d12-h5-wots16-horst-t65536-k32".parse().unwrap();
+        let digest = spec.parameter_digest();
+        assert_eq!(digest, hash_pair(spec.to_string(), 8u64.to_le_bytes()));
+        assert_ne!("sphincs-d12-h6-wots16-horst-t65536-k32".parse::<SchemeSpec>().unwrap().parameter_digest(), digest);
+
+        let spec: SchemeSpec = "merkle-wots16-h10".parse().unwrap();
+        assert_eq!(spec.parameter_digest(), hash_pair("merkle-wots16-h10", []));
+    }
+
+    #[test]
+    fn builds_schemes() {
+        let msg = b"My OS update";
+
+        let spec: SchemeSpec = "merkle-wots16-h3".parse().unwrap();
+        let merkle: Merkle<Winternitz> = (&spec).try_into().unwrap();
+        let (private, public) = merkle.gen_keys(None);
+        let sig = merkle.sign(msg, &private);
+        assert!(merkle.verify(msg, &public, &sig));
+
+        assert!(Merkle::<Lamport>::try_from(&spec).is_err());
+        assert!(Winternitz::try_from(&spec).is_err());
+
+        let spec: SchemeSpec = "goldreich-lamport-n16-h8".parse().unwrap();
+        assert!(Goldreich::<Lamport>::try_from(&spec).is_err());
+        let spec: SchemeSpec = "sphincs-d2-h2-wots16-horst-t256-k32".parse().unwrap();
+        assert!(Sphincs::<Winternitz, Horst>::try_from(&spec).is_err());
+    }
+}
@@ -1,18 +1,41 @@
+use std::convert::TryInto;
+use std::marker::PhantomData;
+
 use bytemuck::bytes_of;
-use rand::prelude::{Rng, SeedableRng, StdRng};
-use rug::Integer;
-use rug::integer::Order;
-use rug::ops::Pow;
-use rug::rand::RandState;
-use sha2::{Digest, Sha256, Sha512};
-
-use crate::{SignatureScheme, U256};
-use crate::util::{hash_pair, div_up};
+use rand::CryptoRng;
+use rand::prelude::{Rng, RngCore, SeedableRng, StdRng};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Estimate, SignatureScheme, SigningMode, U256};
+use crate::framing::{Encode, Reader};
+use crate::selftest::Composition;
+use crate::util::{accepts_len, Domain, Hasher, Sha256};
 use crate::merkle::Merkle;
-use std::convert::TryInto;
+use crate::winternitz::Winternitz;
+use crate::horst::Horst;
+
+/// Leaf indices in the hypertree are `u128`s, so `depth * sub_tree_height`
+/// is at most this
+pub const MAX_HYPERTREE_HEIGHT: usize = 127;
+
+/// `depth * sub_tree_height`, or `None` if it is above `MAX_HYPERTREE_HEIGHT`
+/// or overflows
+pub fn hypertree_height(depth: usize, sub_tree_height: usize) -> Option<usize> {
+    depth.checked_mul(sub_tree_height).filter(|&height| height <= MAX_HYPERTREE_HEIGHT)
+}
+
+/// Bytes taken by a leaf index of the hypertree, and so by every subtree
+/// index, in the subtree seeds, or `None` if the height overflows
+pub fn idx_len(depth: usize, sub_tree_height: usize) -> Option<usize> {
+    Some(depth.checked_mul(sub_tree_height)?.div_ceil(8))
+}
 
 type MerklePublic<O> = <Merkle<O> as SignatureScheme>::Public;
 type MerkleSignature<O> = <Merkle<O> as SignatureScheme>::Signature;
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound(serialize = "O::Public: Serialize, O::Signature: Serialize, F::Public: Serialize, F::Signature: Serialize",
+                                              deserialize = "O::Public: Deserialize<'de>, O::Signature: Deserialize<'de>, F::Public: Deserialize<'de>, F::Signature: Deserialize<'de>")))]
 pub struct Signature<O: SignatureScheme, F: SignatureScheme>
     where <O as SignatureScheme>::Public: AsRef<[u8]> {
     fts_public: F::Public,
@@ -22,97 +45,108 @@ pub struct Signature<O: SignatureScheme, F: SignatureScheme>
 }
 
 
-pub struct Sphincs<O, F> {
+pub struct Sphincs<O, F, H = Sha256> {
     depth: usize,
     sub_tree_height: usize,
     idx_len: usize,
-    merkle: Merkle<O>,
+    merkle: Merkle<O, H>,
     fts_scheme: F,
+    mode: SigningMode,
+    hasher: PhantomData<H>,
 }
 
 impl<O: SignatureScheme + Clone, F: SignatureScheme> Sphincs<O, F>
     where <O as SignatureScheme>::Public: AsRef<[u8]>, <F as SignatureScheme>::Public: AsRef<[u8]> {
-    fn new(depth: usize, sub_tree_height: usize, ots_scheme: O, fts_scheme: F) -> Self {
-        let idx_len = div_up(depth * sub_tree_height + 1, 8);
+    pub fn new(depth: usize, sub_tree_height: usize, ots_scheme: O, fts_scheme: F) -> Self {
+        // The OTS signs subtree roots and FTS public keys, and the FTS signs
+        // 512-bit message digests
+        assert!(accepts_len(&ots_scheme, 32), "OTS can't sign 32-byte roots");
+        assert!(accepts_len(&fts_scheme, 64), "FTS can't sign 64-byte digests");
+        assert!(depth >= 1 && hypertree_height(depth, sub_tree_height).is_some(), "hypertree too high");
+
+        let idx_len = idx_len(depth, sub_tree_height).unwrap();
         let merkle = Merkle::new(sub_tree_height, ots_scheme.clone());
 
         Self {
-            depth, sub_tree_height, idx_len, merkle, fts_scheme
+            depth, sub_tree_height, idx_len, merkle, fts_scheme, mode: SigningMode::Randomized, hasher: PhantomData
+        }
+    }
+}
+
+impl<O: SignatureScheme + Clone, F: SignatureScheme, H: Hasher<Node = U256>> Sphincs<O, F, H>
+    where <O as SignatureScheme>::Public: AsRef<[u8]>, <F as SignatureScheme>::Public: AsRef<[u8]> {
+    /// Hashes the hypertree and the message digest with `H2`. The OTS and FTS
+    /// schemes keep their own hashers.
+    pub fn with_hasher<H2: Hasher<Node = U256>>(self) -> Sphincs<O, F, H2> {
+        let Self { depth, sub_tree_height, idx_len, merkle, fts_scheme, mode, .. } = self;
+        Sphincs {
+            depth, sub_tree_height, idx_len, merkle: merkle.with_hasher(), fts_scheme, mode, hasher: PhantomData
         }
     }
 
-    fn get_sub_tree_keys(&self, private: U256, depth: usize, idx: &Integer) -> (U256, U256) {
-        let mut hasher = Sha256::new();
+    pub fn with_mode(mut self, mode: SigningMode) -> Self {
+        self.mode = mode;
+        self
+    }
 
-        let padding = self.idx_len - idx.significant_digits::<u8>();
-        hasher.update(&private);
-        hasher.update(&idx.to_digits(Order::Lsf));
-        hasher.update(&vec![0u8; padding]);
-        hasher.update(bytes_of(&depth));
-        let tree_seed = hasher.finalize().into();
+    fn get_sub_tree_keys(&self, private: U256, depth: usize, idx: u128) -> (U256, U256) {
+        let idx_bytes = idx.to_le_bytes();
+        let (idx, overflow) = idx_bytes.split_at(self.idx_len);
+        assert!(overflow.iter().all(|&byte| byte == 0), "subtree index overflows idx_len");
+        let tree_seed = H::hash_parts(&[&private, idx, bytes_of(&depth)]);
 
         let (private, public) = self.merkle.gen_keys(Some(tree_seed));
         (private.0, public)
     }
 
-    fn get_fts_keys(&self, private: U256, idx: &Integer) -> (F::Private, F::Public) {
-        let seed = hash_pair(&private, &idx.to_digits(Order::Lsf));
+    fn get_fts_keys(&self, private: U256, idx: u128) -> (F::Private, F::Public) {
+        // Without the trailing zero bytes
+        let len = (u128::BITS - idx.leading_zeros()).div_ceil(8) as usize;
+        let seed = H::hash_pair(private, &idx.to_le_bytes()[..len]);
         self.fts_scheme.gen_keys(Some(seed))
     }
 
-    // TODO: don't hard code this
-    fn transform_msg(msg: &[u8], random: U256) -> Box<[u8]> {
-        let mut hasher = Sha512::new();
-        hasher.update(random);
-        hasher.update(msg);
-        hasher.finalize().as_slice().into()
-    }
-}
-
-impl<O: SignatureScheme + Clone, F: SignatureScheme> SignatureScheme for Sphincs<O, F>
-    where <O as SignatureScheme>::Public: AsRef<[u8]>, <F as SignatureScheme>::Public: AsRef<[u8]> {
-    type Private = (U256, U256);
-    type Public = U256;
-    type Signature = Signature<O, F>;
-
-    fn gen_keys(&self, seed: Option<U256>) -> (Self::Private, Self::Public) {
-        let mut rng = match seed {
-            None => StdRng::from_entropy(),
-            Some(seed) => StdRng::from_seed(seed),
-        };
-
-        let private = (rng.gen(), rng.gen());
-
-        let public = self.get_sub_tree_keys(private.0, self.depth - 1, &Integer::new()).1;
+    // The leaf is picked by the randomized message digest, so the randomizer
+    // can't be chosen independently of the leaf used. The bits are taken
+    // straight from the hash, so verification doesn't depend on any RNG.
+    fn get_fts_idx(&self, digest: &[u8]) -> u128 {
+        let num_bits = self.depth * self.sub_tree_height;
+        let hash = H::hash_pair(digest, b"leaf index");
 
-        (private, public)
+        u128::from_le_bytes(hash[..16].try_into().unwrap()) & ((1 << num_bits) - 1)
     }
 
-    fn sign(&self, msg: &[u8], private: &Self::Private) -> Self::Signature {
-        let (sk1, sk2) = *private;
+    // The leaf used in the lowest of the subtrees `idx` passes through
+    fn sub_tree_idx(&self, idx: u128) -> usize {
+        (idx & ((1 << self.sub_tree_height) - 1)) as usize
+    }
 
-        let num_sub_tree_leaves = 1 << self.sub_tree_height;
-        let num_leaves = Integer::from(num_sub_tree_leaves).pow(self.depth as u32);
-        let mut rand = RandState::new(); // Is this safe?
-        rand.seed(&Integer::from_digits(&[msg, &sk2].concat(), Order::Lsf));
-        let fts_idx = Integer::random_below(num_leaves.clone(), &mut rand);
+    fn transform_msg(msg: &[u8], random: U256) -> Box<[u8]> {
+        H::wide_digest(&[&Domain::Message.prefix(), &random, msg]).into()
+    }
 
-        let (fts_private, fts_public) = self.get_fts_keys(sk1, &fts_idx);
+    /// Signs with the randomizer, and so the leaf, drawn from `rng`, e.g. a
+    /// DRBG, whatever the mode
+    pub fn sign_with_rng(&self, msg: &[u8], private: &(U256, U256),
+                         rng: &mut (impl RngCore + CryptoRng)) -> Signature<O, F> {
+        self.sign_with_random(msg, private.0, rng.gen())
+    }
 
-        let random = Integer::from(Integer::random_bits(256, &mut rand)).to_digits(Order::Lsf)
-            .try_into().unwrap();
+    fn sign_with_random(&self, msg: &[u8], sk1: U256, random: U256) -> Signature<O, F> {
         let msg = Self::transform_msg(msg, random);
+        let fts_idx = self.get_fts_idx(&msg);
 
+        let (fts_private, fts_public) = self.get_fts_keys(sk1, fts_idx);
         let fts_sig = self.fts_scheme.sign(&msg, &fts_private);
 
         let mut node: Box<[u8]> = fts_public.as_ref().into();
         let mut path = Vec::with_capacity(self.depth);
         let mut idx = fts_idx;
         for depth in 0..self.depth{
-            let sub_tree_idx = idx.mod_u(num_sub_tree_leaves) as usize;
-            idx /= num_sub_tree_leaves;
+            let sub_tree_idx = self.sub_tree_idx(idx);
+            idx >>= self.sub_tree_height;
 
-            let (private, public) = self.get_sub_tree_keys(sk1, depth, &idx);
+            let (private, public) = self.get_sub_tree_keys(sk1, depth, idx);
             let sig = self.merkle.sign(&node, &(private, sub_tree_idx));
             path.push((public, sig));
 
@@ -126,15 +160,81 @@ impl<O: SignatureScheme + Clone, F: SignatureScheme> SignatureScheme for Sphincs
             random,
         }
     }
+}
+
+impl Sphincs<Winternitz, Horst> {
+    /// Parameters trading signing time for signature size: three layers of
+    /// height 10 subtrees with w = 256, and the smallest HORST able to sign
+    /// the 512 bit message digest. Signatures are 20,984 bytes, against
+    /// 67,232 bytes for 12 layers of height 5 with w = 16.
+    ///
+    /// Of those, 13,312 bytes are the HORST signature, and each layer carries
+    /// a 1,088 byte Winternitz public key next to its signature, so this is
+    /// about as small as the current signature format gets. The hypertree is
+    /// only 30 levels high, which makes leaf reuse likely after some tens of
+    /// thousands of signatures; keep keys using it to a few thousand.
+    pub fn preset_small_sig() -> Self {
+        Self::new(3, 10, Winternitz::new(256), Horst::new(16, 32))
+    }
+
+    /// Parameters minimizing the hashing done by `verify`, for verifiers on
+    /// slow hardware: six layers of height 10 subtrees with w = 4. Verifying
+    /// takes at most 2,883 hashes, against 12,561 for 12 layers of height 5
+    /// with w = 16, at the cost of 66,608 byte signatures.
+    ///
+    /// Every layer costs a full Winternitz verification but each level of
+    /// subtree height only a single hash, so this uses few layers of fairly
+    /// tall subtrees, with their cost paid by the signer instead.
+    pub fn preset_fast_verify() -> Self {
+        Self::new(6, 10, Winternitz::new(4), Horst::new(16, 32))
+    }
+}
+
+impl<O: SignatureScheme + Clone, F: SignatureScheme, H: Hasher<Node = U256>> SignatureScheme for Sphincs<O, F, H>
+    where <O as SignatureScheme>::Public: AsRef<[u8]>, <F as SignatureScheme>::Public: AsRef<[u8]> {
+    type Private = (U256, U256);
+    type Public = U256;
+    type Signature = Signature<O, F>;
+
+    fn gen_keys(&self, seed: Option<U256>) -> (Self::Private, Self::Public) {
+        let mut rng = match seed {
+            None => StdRng::from_entropy(),
+            Some(seed) => StdRng::from_seed(seed),
+        };
+
+        let private = (rng.gen(), rng.gen());
+
+        let public = self.get_sub_tree_keys(private.0, self.depth - 1, 0).1;
+
+        (private, public)
+    }
+
+    fn sign(&self, msg: &[u8], private: &Self::Private) -> Self::Signature {
+        let random = match self.mode {
+            SigningMode::Randomized => StdRng::from_entropy().gen(),
+            SigningMode::Deterministic => H::hash_pair(private.1, msg),
+        };
+        self.sign_with_random(msg, private.0, random)
+    }
 
     fn verify(&self, msg: &[u8], public: &Self::Public, sig: &Self::Signature) -> bool {
+        if sig.path.len() != self.depth {
+            return false;
+        }
+
         let msg = Self::transform_msg(msg, sig.random);
         if !self.fts_scheme.verify(&msg, &sig.fts_public, &sig.fts_sig) {
             return false;
         }
 
+        let mut idx = self.get_fts_idx(&msg);
         let mut node: Box<[u8]> = sig.fts_public.as_ref().into();
         for (public, sig) in sig.path.iter() {
+            if sig.leaf_idx() != self.sub_tree_idx(idx) {
+                return false;
+            }
+            idx >>= self.sub_tree_height;
+
             if !self.merkle.verify(&node, public, sig) {
                 return false;
             }
@@ -145,13 +245,81 @@ impl<O: SignatureScheme + Clone, F: SignatureScheme> SignatureScheme for Sphincs
     }
 }
 
+impl<O: Encode + Clone, F: Encode, H: Hasher<Node = U256>> Encode for Sphincs<O, F, H>
+    where <O as SignatureScheme>::Public: AsRef<[u8]>, <F as SignatureScheme>::Public: AsRef<[u8]> {
+    /// The FTS public key and signature, each layer's subtree root and
+    /// signature from the bottom up, then the randomness
+    fn write_signature(&self, sig: &Self::Signature, out: &mut Vec<u8>) {
+        self.fts_scheme.write_public(&sig.fts_public, out);
+        self.fts_scheme.write_signature(&sig.fts_sig, out);
+        for (public, sig) in sig.path.iter() {
+            self.merkle.write_public(public, out);
+            self.merkle.write_signature(sig, out);
+        }
+        out.extend_from_slice(&sig.random);
+    }
+
+    fn read_signature(&self, input: &mut Reader) -> Result<Self::Signature, Error> {
+        let fts_public = self.fts_scheme.read_public(input)?;
+        let fts_sig = self.fts_scheme.read_signature(input)?;
+        let path = (0..self.depth)
+            .map(|_| Ok((self.merkle.read_public(input)?, self.merkle.read_signature(input)?)))
+            .collect::<Result<_, Error>>()?;
+        let random = input.node()?;
+
+        Ok(Signature { fts_public, fts_sig, path, random })
+    }
+
+    fn write_public(&self, public: &U256, out: &mut Vec<u8>) {
+        out.extend_from_slice(public);
+    }
+
+    fn read_public(&self, input: &mut Reader) -> Result<U256, Error> {
+        input.node()
+    }
+}
+
+
+impl<O: Estimate + Clone, F: Estimate, H: Hasher<Node = U256>> Estimate for Sphincs<O, F, H>
+    where <O as SignatureScheme>::Public: AsRef<[u8]>, <F as SignatureScheme>::Public: AsRef<[u8]> {
+    fn public_size(&self) -> usize {
+        32
+    }
+
+    fn signature_size(&self) -> usize {
+        let layer_size = self.merkle.public_size() + self.merkle.signature_size();
+        self.fts_scheme.public_size() + self.fts_scheme.signature_size() + self.depth * layer_size + 32
+    }
+
+    fn verify_hashes(&self) -> usize {
+        // The message digest and the leaf index derivation
+        2 + self.fts_scheme.verify_hashes() + self.depth * self.merkle.verify_hashes()
+    }
+}
+
+/// The FTS keys are the leaves, signing the message digests
+impl<O: Estimate + Clone, F: Estimate, H: Hasher<Node = U256>> Composition for Sphincs<O, F, H>
+    where <O as SignatureScheme>::Public: AsRef<[u8]>, <F as SignatureScheme>::Public: AsRef<[u8]> {
+    type Leaf = F;
+
+    fn leaf_scheme(&self) -> &F {
+        &self.fts_scheme
+    }
+
+    fn first_leaf(&self, seed: U256) -> (F::Private, F::Public) {
+        self.get_fts_keys(StdRng::from_seed(seed).gen(), 0)
+    }
+
+    fn leaf_msg(&self, msg: &[u8]) -> Box<[u8]> {
+        Self::transform_msg(msg, [0; 32])
+    }
+}
 
 #[cfg(test)]
 mod tests {
-    use crate::winternitz::Winternitz;
+    use crate::util::{hash_pair, record_hashes, Sha512_256};
 
     use super::*;
-    use crate::horst::Horst;
 
     #[test]
     fn it_works() {
@@ -172,4 +340,157 @@ mod tests {
 
         assert!(!sphincs.verify(msg1, &public, &sig));
     }
+
+    #[test]
+    fn hashers() {
+        let msg = b"My OS update";
+
+        let ots = Winternitz::new(16).with_hasher::<Sha512_256>();
+        let fts = || Horst::new(8, 64).with_hasher::<Sha512_256>();
+        let sphincs = Sphincs::new(2, 2, ots, fts()).with_hasher::<Sha512_256>();
+        let (private, public) = sphincs.gen_keys(Some([7; 32]));
+        let sig = sphincs.sign(msg, &private);
+        assert!(sphincs.verify(msg, &public, &sig));
+        assert!(!sphincs.verify(b"My OS apdate", &public, &sig));
+
+        let default = Sphincs::new(2, 2, Winternitz::new(16), Horst::new(8, 64));
+        assert_ne!(default.gen_keys(Some([7; 32])).1, public);
+
+        // Only the OTS and FTS hash with SHA-512/256 here
+        let mixed = Sphincs::new(2, 2, ots, fts());
+        assert!(!mixed.verify(msg, &public, &sig));
+    }
+
+    #[test]
+    #[cfg(feature = "shake")]
+    fn shake() {
+        use crate::util::Shake256;
+
+        let msg = b"My OS update";
+
+        let ots = Winternitz::new(16).with_hasher::<Shake256>();
+        let fts = Horst::new(8, 64).with_hasher::<Shake256>();
+        let sphincs = Sphincs::new(2, 2, ots, fts).with_hasher::<Shake256>();
+        let (private, public) = sphincs.gen_keys(None);
+        let sig = sphincs.sign(msg, &private);
+        assert!(sphincs.verify(msg, &public, &sig));
+        assert!(!sphincs.verify(b"My OS apdate", &public, &sig));
+    }
+
+    #[test]
+    fn fts_idx() {
+        // The low bits of the hash, whatever RNG the crate is built with
+        let digest = [0x42; 64];
+        let hash = hash_pair(digest, b"leaf index");
+        let sphincs = Sphincs::new(2, 2, Winternitz::new(16), Horst::new(8, 64));
+        assert_eq!(sphincs.get_fts_idx(&digest), (hash[0] & 0xf) as u128);
+        let sphincs = Sphincs::new(3, 5, Winternitz::new(16), Horst::new(8, 64));
+        assert_eq!(sphincs.get_fts_idx(&digest), u16::from_le_bytes([hash[0], hash[1] & 0x7f]) as u128);
+    }
+
+    #[test]
+    fn idx_lens() {
+        for depth in 1..=MAX_HYPERTREE_HEIGHT {
+            for sub_tree_height in 1..=MAX_HYPERTREE_HEIGHT / depth {
+                let bits = depth * sub_tree_height;
+                let len = idx_len(depth, sub_tree_height).unwrap();
+                assert!(len * 8 >= bits && (len - 1) * 8 < bits, "d = {}, h = {}", depth, sub_tree_height);
+            }
+        }
+
+        // Small hypertrees used to round the length down to zero bytes
+        let sphincs = Sphincs::new(2, 1, Winternitz::new(16), Horst::new(8, 64));
+        let (private, public) = sphincs.gen_keys(None);
+        for msg in [&b"My OS update"[..], b"My important message", b"Another update"] {
+            assert!(sphincs.verify(msg, &public, &sphincs.sign(msg, &private)));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "hypertree too high")]
+    fn too_high() {
+        Sphincs::new(16, 8, Winternitz::new(16), Horst::new(8, 64));
+    }
+
+    #[test]
+    fn preset_small_sig() {
+        assert_eq!(Sphincs::preset_small_sig().signature_size(), 20984);
+        assert_eq!(Sphincs::new(12, 5, Winternitz::new(16), Horst::new(16, 32)).signature_size(), 67232);
+    }
+
+    #[test]
+    fn preset_fast_verify() {
+        let sphincs = Sphincs::preset_fast_verify();
+        assert_eq!(sphincs.verify_hashes(), 2883);
+        assert_eq!(Sphincs::new(12, 5, Winternitz::new(16), Horst::new(16, 32)).verify_hashes(), 12561);
+
+        let sphincs = Sphincs::new(3, 4, Winternitz::new(4), Horst::new(10, 52));
+        let (private, public) = sphincs.gen_keys(None);
+        let sig = sphincs.sign(b"My OS update", &private);
+        let (valid, calls) = record_hashes(|| sphincs.verify(b"My OS update", &public, &sig));
+        assert!(valid);
+        // The wide message digest isn't recorded
+        assert!(calls.len() < sphincs.verify_hashes());
+    }
+
+    #[test]
+    fn deterministic() {
+        let msg = b"My OS update";
+
+        let sphincs = Sphincs::new(4, 3, Winternitz::new(16), Horst::new(8, 64))
+            .with_mode(SigningMode::Deterministic);
+        let (private, public) = sphincs.gen_keys(None);
+
+        let sig1 = sphincs.sign(msg, &private);
+        let sig2 = sphincs.sign(msg, &private);
+        assert!(sphincs.verify(msg, &public, &sig1));
+        assert_eq!(sig1.random, sig2.random);
+        assert_eq!(sig1.fts_public, sig2.fts_public);
+
+        let sig3 = sphincs.sign(b"My important message", &private);
+        assert_ne!(sig1.random, sig3.random);
+    }
+
+    #[test]
+    fn sign_with_rng() {
+        let msg = b"My OS update";
+
+        let sphincs = Sphincs::new(4, 3, Winternitz::new(16), Horst::new(8, 64))
+            .with_mode(SigningMode::Deterministic);
+        let (private, public) = sphincs.gen_keys(None);
+
+        // The RNG takes precedence over the mode
+        let sig1 = sphincs.sign_with_rng(msg, &private, &mut StdRng::seed_from_u64(7));
+        let sig2 = sphincs.sign_with_rng(msg, &private, &mut StdRng::seed_from_u64(7));
+        assert!(sphincs.verify(msg, &public, &sig1));
+        assert_eq!(sig1.random, sig2.random);
+        assert_ne!(sig1.random, sphincs.sign(msg, &private).random);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde() {
+        let msg = b"My OS update";
+
+        let sphincs = Sphincs::new(2, 2, Winternitz::new(16), Horst::new(8, 64));
+        let (private, public) = sphincs.gen_keys(None);
+
+        let json = serde_json::to_string(&sphincs.sign(msg, &private)).unwrap();
+        let sig: Signature<Winternitz, Horst> = serde_json::from_str(&json).unwrap();
+        assert!(sphincs.verify(msg, &public, &sig));
+    }
+
+    #[test]
+    fn randomizer_is_bound() {
+        let msg = b"My OS update";
+
+        let sphincs = Sphincs::new(4, 3, Winternitz::new(16), Horst::new(8, 64));
+        let (private, public) = sphincs.gen_keys(None);
+
+        let mut sig = sphincs.sign(msg, &private);
+        assert!(sphincs.verify(msg, &public, &sig));
+
+        sig.random[0] ^= 1;
+        assert!(!sphincs.verify(msg, &public, &sig));
+    }
 }
\ No newline at end of file
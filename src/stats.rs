@@ -0,0 +1,270 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::{Error, Estimate, SignatureScheme, U256};
+#[cfg(feature = "profiling")]
+use crate::profiling::{self, Profile};
+
+// Latency percentiles are taken over this many of the most recent operations
+const WINDOW: usize = 1024;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Latency {
+    pub p50: Duration,
+    pub p99: Duration,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub signatures: u64,
+    pub verifications: u64,
+    pub rejections: u64,
+    pub sign_latency: Latency,
+    pub verify_latency: Latency,
+    pub public_size: usize,
+    pub signature_size: usize,
+    /// Worst case seen for each kind of operation
+    #[cfg(feature = "profiling")]
+    pub keygen_profile: Profile,
+    #[cfg(feature = "profiling")]
+    pub sign_profile: Profile,
+    #[cfg(feature = "profiling")]
+    pub verify_profile: Profile,
+}
+
+impl Stats {
+    /// The stats in the Prometheus text exposition format, with every metric
+    /// name starting with `prefix`
+    pub fn to_prometheus(&self, prefix: &str) -> String {
+        let mut text = String::new();
+        let mut metric = |name: &str, kind: &str, samples: &[(&str, f64)]| {
+            writeln!(text, "# TYPE {}_{} {}", prefix, name, kind).unwrap();
+            for (labels, value) in samples {
+                writeln!(text, "{}_{}{} {}", prefix, name, labels, value).unwrap();
+            }
+        };
+
+        metric("signatures_total", "counter", &[("", self.signatures as f64)]);
+        metric("verifications_total", "counter", &[("", self.verifications as f64)]);
+        metric("rejections_total", "counter", &[("", self.rejections as f64)]);
+        for (name, latency) in &[("sign_seconds", self.sign_latency), ("verify_seconds", self.verify_latency)] {
+            metric(name, "summary", &[
+                ("{quantile=\"0.5\"}", latency.p50.as_secs_f64()),
+                ("{quantile=\"0.99\"}", latency.p99.as_secs_f64()),
+            ]);
+        }
+        metric("public_key_bytes", "gauge", &[("", self.public_size as f64)]);
+        metric("signature_bytes", "gauge", &[("", self.signature_size as f64)]);
+        #[cfg(feature = "profiling")]
+        {
+            let profiles = [
+                ("{op=\"keygen\"}", self.keygen_profile),
+                ("{op=\"sign\"}", self.sign_profile),
+                ("{op=\"verify\"}", self.verify_profile),
+            ];
+            let heap: Vec<_> = profiles.iter().map(|&(op, profile)| (op, profile.peak_heap as f64)).collect();
+            let stack: Vec<_> = profiles.iter().map(|&(op, profile)| (op, profile.max_stack as f64)).collect();
+            metric("peak_heap_bytes", "gauge", &heap);
+            metric("max_stack_bytes", "gauge", &stack);
+        }
+
+        text
+    }
+}
+
+
+#[derive(Default)]
+struct Recorder {
+    signatures: u64,
+    verifications: u64,
+    rejections: u64,
+    sign_latencies: VecDeque<Duration>,
+    verify_latencies: VecDeque<Duration>,
+    #[cfg(feature = "profiling")]
+    profiles: [Profile; 3],
+}
+
+#[derive(Clone, Copy)]
+enum Operation {
+    Keygen,
+    Sign,
+    Verify,
+}
+
+fn push_latency(latencies: &mut VecDeque<Duration>, latency: Duration) {
+    if latencies.len() == WINDOW {
+        latencies.pop_front();
+    }
+    latencies.push_back(latency);
+}
+
+fn percentiles(latencies: &VecDeque<Duration>) -> Latency {
+    let mut sorted: Vec<_> = latencies.iter().copied().collect();
+    sorted.sort_unstable();
+
+    let percentile = |p: usize| match sorted.len() {
+        0 => Duration::default(),
+        n => sorted[(n * p / 100).min(n - 1)],
+    };
+    Latency { p50: percentile(50), p99: percentile(99) }
+}
+
+/// Wraps a scheme, recording counts, latencies and sizes of the operations
+/// done through it, so services get observability without wrapping every
+/// call themselves.
+pub struct Instrumented<S> {
+    scheme: S,
+    recorder: Mutex<Recorder>,
+}
+
+impl<S: Estimate> Instrumented<S> {
+    pub fn new(scheme: S) -> Self {
+        Self { scheme, recorder: Mutex::new(Recorder::default()) }
+    }
+
+    pub fn scheme(&self) -> &S {
+        &self.scheme
+    }
+
+    pub fn stats(&self) -> Stats {
+        let recorder = self.recorder.lock().unwrap();
+        Stats {
+            signatures: recorder.signatures,
+            verifications: recorder.verifications,
+            rejections: recorder.rejections,
+            sign_latency: percentiles(&recorder.sign_latencies),
+            verify_latency: percentiles(&recorder.verify_latencies),
+            public_size: self.scheme.public_size(),
+            signature_size: self.scheme.signature_size(),
+            #[cfg(feature = "profiling")]
+            keygen_profile: recorder.profiles[Operation::Keygen as usize],
+            #[cfg(feature = "profiling")]
+            sign_profile: recorder.profiles[Operation::Sign as usize],
+            #[cfg(feature = "profiling")]
+            verify_profile: recorder.profiles[Operation::Verify as usize],
+        }
+    }
+
+    #[cfg(feature = "profiling")]
+    fn run<T>(&self, operation: Operation, f: impl FnOnce() -> T) -> T {
+        let (result, profile) = profiling::measure(f);
+        let profiles = &mut self.recorder.lock().unwrap().profiles;
+        profiles[operation as usize] = profiles[operation as usize].max(profile);
+        result
+    }
+
+    #[cfg(not(feature = "profiling"))]
+    fn run<T>(&self, _: Operation, f: impl FnOnce() -> T) -> T {
+        f()
+    }
+
+    fn record_sign(&self, latency: Duration) {
+        let mut recorder = self.recorder.lock().unwrap();
+        recorder.signatures += 1;
+        push_latency(&mut recorder.sign_latencies, latency);
+    }
+
+    fn record_verify(&self, valid: bool, latency: Duration) {
+        let mut recorder = self.recorder.lock().unwrap();
+        recorder.verifications += 1;
+        recorder.rejections += !valid as u64;
+        push_latency(&mut recorder.verify_latencies, latency);
+    }
+}
+
+impl<S: Estimate> SignatureScheme for Instrumented<S> {
+    type Private = S::Private;
+    type Public = S::Public;
+    type Signature = S::Signature;
+
+    fn gen_keys(&self, seed: Option<U256>) -> (Self::Private, Self::Public) {
+        self.run(Operation::Keygen, || self.scheme.gen_keys(seed))
+    }
+
+    fn sign(&self, msg: &[u8], private: &Self::Private) -> Self::Signature {
+        let start = Instant::now();
+        let sig = self.run(Operation::Sign, || self.scheme.sign(msg, private));
+        self.record_sign(start.elapsed());
+        sig
+    }
+
+    fn verify(&self, msg: &[u8], public: &Self::Public, sig: &Self::Signature) -> bool {
+        let start = Instant::now();
+        let valid = self.run(Operation::Verify, || self.scheme.verify(msg, public, sig));
+        self.record_verify(valid, start.elapsed());
+        valid
+    }
+
+    fn max_message_len(&self) -> Option<usize> {
+        self.scheme.max_message_len()
+    }
+
+    /// Only signatures actually made are counted
+    fn try_sign(&self, msg: &[u8], private: &Self::Private) -> Result<Self::Signature, Error> {
+        let start = Instant::now();
+        let sig = self.run(Operation::Sign, || self.scheme.try_sign(msg, private))?;
+        self.record_sign(start.elapsed());
+        Ok(sig)
+    }
+
+    fn try_verify(&self, msg: &[u8], public: &Self::Public, sig: &Self::Signature) -> Result<(), Error> {
+        let start = Instant::now();
+        let result = self.run(Operation::Verify, || self.scheme.try_verify(msg, public, sig));
+        self.record_verify(result.is_ok(), start.elapsed());
+        result
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::merkle::Merkle;
+    use crate::winternitz::Winternitz;
+
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let merkle = Instrumented::new(Merkle::new(3, Winternitz::new(16)));
+        let (private, public) = merkle.gen_keys(None);
+
+        let sig = merkle.sign(b"My OS update", &private);
+        assert!(merkle.verify(b"My OS update", &public, &sig));
+        assert!(!merkle.verify(b"Another update", &public, &sig));
+
+        let stats = merkle.stats();
+        assert_eq!((stats.signatures, stats.verifications, stats.rejections), (1, 2, 1));
+        assert!(stats.sign_latency.p50 > Duration::default());
+        assert_eq!(stats.signature_size, merkle.scheme().signature_size());
+
+        let text = stats.to_prometheus("release_signer");
+        assert!(text.contains("# TYPE release_signer_verifications_total counter\nrelease_signer_verifications_total 2\n"));
+
+        // The scheme's own checks still apply
+        assert_eq!(merkle.try_sign(b"My OS update", &(private.0, 8)).err(), Some(Error::MalformedKey));
+        assert_eq!(merkle.try_verify(b"Another update", &public, &sig), Err(Error::InvalidSignature));
+        assert_eq!((merkle.stats().signatures, merkle.stats().rejections), (1, 2));
+        assert!(text.contains("release_signer_verify_seconds{quantile=\"0.99\"} "));
+    }
+
+    #[test]
+    #[cfg(feature = "profiling")]
+    fn profiling() {
+        let merkle = Instrumented::new(Merkle::new(3, Winternitz::new(16)));
+        let (private, public) = merkle.gen_keys(None);
+        let sig = merkle.sign(b"My OS update", &private);
+        merkle.verify(b"My OS update", &public, &sig);
+
+        // The allocator is installed by the profiling module's tests
+        let stats = merkle.stats();
+        assert!(stats.keygen_profile.max_stack > 0);
+        assert!(stats.sign_profile.peak_heap >= merkle.scheme().signature_size());
+        assert!(stats.verify_profile.peak_heap > 0);
+
+        let text = stats.to_prometheus("release_signer");
+        assert!(text.contains("# TYPE release_signer_peak_heap_bytes gauge\n"));
+        assert!(text.contains("release_signer_max_stack_bytes{op=\"verify\"} "));
+    }
+}
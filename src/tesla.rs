@@ -0,0 +1,306 @@
+use std::convert::{TryFrom, TryInto};
+
+use crate::U256;
+use crate::hashchain::Chain;
+use crate::util::{hash, hash_n, hash_pair};
+
+/// Division of time into key disclosure intervals. Interval `i` (starting
+/// from 1) covers `[start + (i - 1) * interval_len, start + i * interval_len)`,
+/// and its key is disclosed `delay` intervals later.
+#[derive(Clone, Copy)]
+pub struct Schedule {
+    start: u64,
+    interval_len: u64,
+    delay: usize,
+}
+
+impl Schedule {
+    pub fn new(start: u64, interval_len: u64, delay: usize) -> Self {
+        assert!(interval_len > 0);
+        assert!(delay >= 1);
+
+        Self { start, interval_len, delay }
+    }
+
+    /// The interval `time` falls in, 0 if it is before the start, or `None`
+    /// if its number doesn't fit in a `usize`
+    pub fn interval(&self, time: u64) -> Option<usize> {
+        match time.checked_sub(self.start) {
+            Some(elapsed) => usize::try_from(elapsed / self.interval_len).ok()?.checked_add(1),
+            None => Some(0),
+        }
+    }
+}
+
+
+/// On the wire, the interval as a little-endian `u64`, the MAC, a byte
+/// telling whether a key is disclosed, followed by its interval and the key
+/// if so, and then the message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Packet {
+    msg: Box<[u8]>,
+    interval: usize,
+    mac: U256,
+    disclosed: Option<(usize, U256)>,
+}
+
+impl Packet {
+    pub fn msg(&self) -> &[u8] {
+        &self.msg
+    }
+
+    pub fn interval(&self) -> usize {
+        self.interval
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(8 + 32 + 1 + 8 + 32 + self.msg.len());
+        bytes.extend_from_slice(&(self.interval as u64).to_le_bytes());
+        bytes.extend_from_slice(&self.mac);
+        match self.disclosed {
+            None => bytes.push(0),
+            Some((idx, key)) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&(idx as u64).to_le_bytes());
+                bytes.extend_from_slice(&key);
+            }
+        }
+        bytes.extend_from_slice(&self.msg);
+        bytes
+    }
+
+    /// Parses a packet, or returns `None` if it is malformed
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let index = |bytes: &[u8]| u64::from_le_bytes(bytes.try_into().unwrap()).try_into().ok();
+        if bytes.len() < 8 + 32 + 1 {
+            return None;
+        }
+
+        let interval = index(&bytes[..8])?;
+        let mac = bytes[8..40].try_into().unwrap();
+        let (disclosed, msg) = match bytes[40] {
+            0 => (None, &bytes[41..]),
+            1 if bytes.len() >= 41 + 8 + 32 => {
+                let disclosed = (index(&bytes[41..49])?, bytes[49..81].try_into().unwrap());
+                (Some(disclosed), &bytes[81..])
+            }
+            _ => return None,
+        };
+
+        Some(Self { msg: msg.into(), interval, mac, disclosed })
+    }
+}
+
+fn mac(key: &U256, msg: &[u8]) -> U256 {
+    // Keep the MAC key distinct from the disclosed chain element
+    let mac_key = hash_pair(key, b"TESLA MAC key");
+    hash_pair(mac_key, hash(msg))
+}
+
+
+pub struct Sender {
+    chain: Chain,
+    schedule: Schedule,
+}
+
+impl Sender {
+    /// Creates a sender able to authenticate packets during the first
+    /// `num_intervals` intervals of `schedule`.
+    pub fn new(seed: U256, num_intervals: usize, schedule: Schedule) -> Self {
+        Self {
+            chain: Chain::new(seed, num_intervals),
+            schedule,
+        }
+    }
+
+    /// The commitment to the key chain, which receivers must obtain
+    /// authentically, e.g. signed with one of the signature schemes
+    pub fn commitment(&self) -> U256 {
+        self.chain.anchor()
+    }
+
+    /// Authenticates `msg` with the key of the current interval and discloses
+    /// the key from `delay` intervals ago. Returns `None` outside the
+    /// lifetime of the key chain.
+    pub fn send(&self, msg: &[u8], now: u64) -> Option<Packet> {
+        let interval = self.schedule.interval(now)?;
+        if interval == 0 || interval > self.chain.len() {
+            return None;
+        }
+
+        let disclosed = interval.checked_sub(self.schedule.delay)
+            .filter(|&i| i > 0)
+            .map(|i| (i, self.chain.element(i)));
+
+        Some(Packet {
+            msg: msg.into(),
+            interval,
+            mac: mac(&self.chain.element(interval), msg),
+            disclosed,
+        })
+    }
+}
+
+
+pub struct Receiver {
+    schedule: Schedule,
+    max_clock_skew: u64,
+    key: (usize, U256),
+    buffer: Vec<Packet>,
+}
+
+impl Receiver {
+    /// `max_clock_skew` bounds how far the sender's clock may be ahead of ours.
+    pub fn new(commitment: U256, schedule: Schedule, max_clock_skew: u64) -> Self {
+        Self {
+            schedule,
+            max_clock_skew,
+            key: (0, commitment),
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Processes a packet received at time `now`, returning the messages which
+    /// became authenticated as a result, in order of their intervals.
+    pub fn receive(&mut self, packet: Packet, now: u64) -> Vec<Box<[u8]>> {
+        // The latest interval the sender can have reached by now
+        let sender_interval = self.schedule.interval(now.saturating_add(self.max_clock_skew)).unwrap_or(usize::MAX);
+
+        // Keys are disclosed `delay` intervals late, so one from further on
+        // is bogus, and checking it would cost a hash per interval claimed
+        if let Some((idx, key)) = packet.disclosed {
+            if idx.saturating_add(self.schedule.delay) <= sender_interval {
+                self.disclose(idx, key);
+            }
+        }
+
+        // The packet is only safe if its key can't have been disclosed yet
+        if packet.interval > self.key.0 && packet.interval <= sender_interval
+            && sender_interval < packet.interval.saturating_add(self.schedule.delay) {
+            self.buffer.push(packet);
+        }
+
+        self.authenticate_buffered()
+    }
+
+    fn disclose(&mut self, idx: usize, key: U256) {
+        let (known_idx, known_key) = self.key;
+        if idx > known_idx && hash_n(key, idx - known_idx) == known_key {
+            self.key = (idx, key);
+        }
+    }
+
+    fn authenticate_buffered(&mut self) -> Vec<Box<[u8]>> {
+        let (known_idx, known_key) = self.key;
+        let (mut ready, pending): (Vec<_>, _) = self.buffer.drain(..)
+            .partition(|packet| packet.interval <= known_idx);
+        self.buffer = pending;
+
+        ready.sort_by_key(|packet| packet.interval);
+        ready.into_iter()
+            .filter(|packet| {
+                let key = hash_n(known_key, known_idx - packet.interval);
+                mac(&key, &packet.msg) == packet.mac
+            })
+            .map(|packet| packet.msg)
+            .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let schedule = Schedule::new(1000, 10, 2);
+        let sender = Sender::new(hash(b"My secret"), 100, schedule);
+        let mut receiver = Receiver::new(sender.commitment(), schedule, 1);
+
+        let p1 = sender.send(b"Reading 1", 1000).unwrap();
+        let p2 = sender.send(b"Reading 2", 1015).unwrap();
+        let p3 = sender.send(b"Reading 3", 1025).unwrap();
+        let p4 = sender.send(b"Reading 4", 1035).unwrap();
+
+        assert!(receiver.receive(p1, 1001).is_empty());
+        assert!(receiver.receive(p2, 1016).is_empty());
+        let msgs = receiver.receive(p3, 1026);
+        assert_eq!(msgs, vec![Box::from(&b"Reading 1"[..])]);
+        let msgs = receiver.receive(p4, 1036);
+        assert_eq!(msgs, vec![Box::from(&b"Reading 2"[..])]);
+    }
+
+    #[test]
+    fn rejects_late_and_forged_packets() {
+        let schedule = Schedule::new(0, 10, 2);
+        let sender = Sender::new(hash(b"My secret"), 100, schedule);
+        let mut receiver = Receiver::new(sender.commitment(), schedule, 0);
+
+        // Arrives after its key could have been disclosed
+        let late = sender.send(b"Late", 5).unwrap();
+        assert!(receiver.receive(late, 25).is_empty());
+
+        let mut forged = sender.send(b"Forged", 35).unwrap();
+        forged.msg = b"Evil"[..].into();
+        assert!(receiver.receive(forged, 35).is_empty());
+
+        let disclosing = sender.send(b"Later", 55).unwrap();
+        assert!(receiver.receive(disclosing, 55).is_empty());
+        assert!(receiver.buffer.iter().all(|packet| packet.interval > 4));
+    }
+
+    #[test]
+    fn rejects_keys_from_the_future() {
+        let schedule = Schedule::new(0, 10, 2);
+        let sender = Sender::new(hash(b"My secret"), 100, schedule);
+        let mut receiver = Receiver::new(sender.commitment(), schedule, 0);
+
+        // Would take 2^64 hashes to check
+        let mut packet = sender.send(b"Reading", 5).unwrap();
+        packet.disclosed = Some((usize::MAX, [0; 32]));
+        packet.interval = usize::MAX;
+        assert!(receiver.receive(packet, 5).is_empty());
+        assert!(receiver.buffer.is_empty());
+
+        // Disclosed ahead of time
+        let mut packet = sender.send(b"Reading", 25).unwrap();
+        packet.disclosed = Some((2, sender.chain.element(2)));
+        assert!(receiver.receive(packet, 25).is_empty());
+        assert_eq!(receiver.key.0, 0);
+    }
+
+    #[test]
+    fn schedule() {
+        let schedule = Schedule::new(1000, 10, 2);
+        assert_eq!(schedule.interval(999), Some(0));
+        assert_eq!(schedule.interval(1000), Some(1));
+        assert_eq!(schedule.interval(1019), Some(2));
+
+        // The last interval would be numbered past `usize::MAX` on 64-bit targets
+        let schedule = Schedule::new(0, 1, 2);
+        assert_eq!(schedule.interval(u64::MAX), (u64::MAX as usize).checked_add(1));
+        let sender = Sender::new(hash(b"My secret"), 100, schedule);
+        assert!(sender.send(b"Reading", u64::MAX).is_none());
+        let mut receiver = Receiver::new(sender.commitment(), schedule, u64::MAX);
+        assert!(receiver.receive(sender.send(b"Reading", 5).unwrap(), 5).is_empty());
+    }
+
+    #[test]
+    fn encoding() {
+        let schedule = Schedule::new(0, 10, 2);
+        let sender = Sender::new(hash(b"My secret"), 100, schedule);
+
+        for packet in [sender.send(b"Reading 1", 5).unwrap(), sender.send(b"", 45).unwrap()] {
+            let bytes = packet.to_bytes();
+            assert_eq!(Packet::from_bytes(&bytes), Some(packet));
+
+            assert_eq!(Packet::from_bytes(&bytes[..40]), None);
+        }
+
+        let mut bytes = sender.send(b"Reading", 45).unwrap().to_bytes();
+        assert_eq!(Packet::from_bytes(&bytes[..60]), None);
+        bytes[40] = 2;
+        assert_eq!(Packet::from_bytes(&bytes), None);
+    }
+}
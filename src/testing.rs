@@ -0,0 +1,108 @@
+//! Faulty scheme implementations for negative tests, e.g. of how Merkle,
+//! Goldreich and SPHINCS behave over a broken OTS or FTS, or of whether
+//! `validate_composition` and other checks catch a fault.
+
+use rand::prelude::{Rng, SeedableRng, StdRng};
+
+use crate::{Estimate, SignatureScheme, U256};
+
+/// How a `BrokenScheme` misbehaves
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// `verify` accepts any signature
+    AcceptAll,
+    /// Signatures carry the private key they were made with
+    LeakKey,
+    /// `gen_keys` ignores the seed, so derived keys can't be regenerated
+    RandomKeygen,
+}
+
+/// Wraps a scheme, behaving like it except for the fault
+#[derive(Clone, Copy, Debug)]
+pub struct BrokenScheme<S> {
+    scheme: S,
+    fault: Fault,
+}
+
+impl<S> BrokenScheme<S> {
+    pub fn new(scheme: S, fault: Fault) -> Self {
+        Self { scheme, fault }
+    }
+}
+
+impl<S: SignatureScheme> SignatureScheme for BrokenScheme<S> where S::Private: Clone {
+    type Private = S::Private;
+    type Public = S::Public;
+    /// The leaked private key is only set with `Fault::LeakKey`
+    type Signature = (S::Signature, Option<S::Private>);
+
+    fn gen_keys(&self, seed: Option<U256>) -> (Self::Private, Self::Public) {
+        match self.fault {
+            Fault::RandomKeygen => self.scheme.gen_keys(Some(StdRng::from_entropy().gen())),
+            _ => self.scheme.gen_keys(seed),
+        }
+    }
+
+    fn sign(&self, msg: &[u8], private: &Self::Private) -> Self::Signature {
+        let leaked = (self.fault == Fault::LeakKey).then(|| private.clone());
+        (self.scheme.sign(msg, private), leaked)
+    }
+
+    fn verify(&self, msg: &[u8], public: &Self::Public, sig: &Self::Signature) -> bool {
+        self.fault == Fault::AcceptAll || self.scheme.verify(msg, public, &sig.0)
+    }
+
+    fn max_message_len(&self) -> Option<usize> {
+        self.scheme.max_message_len()
+    }
+}
+
+impl<S: Estimate> Estimate for BrokenScheme<S> where S::Private: Clone {
+    fn public_size(&self) -> usize {
+        self.scheme.public_size()
+    }
+
+    fn signature_size(&self) -> usize {
+        self.scheme.signature_size()
+    }
+
+    fn verify_hashes(&self) -> usize {
+        self.scheme.verify_hashes()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::goldreich::Goldreich;
+    use crate::lamport::Lamport;
+    use crate::merkle::Merkle;
+    use crate::selftest::validate_composition;
+    use crate::winternitz::Winternitz;
+
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let msg = b"My OS update";
+        let broken = |fault| BrokenScheme::new(Winternitz::new(16), fault);
+
+        // A Merkle tree over an accepting OTS accepts any message
+        let merkle = Merkle::new(3, broken(Fault::AcceptAll));
+        let (private, public) = merkle.gen_keys(None);
+        let sig = merkle.sign(msg, &private);
+        assert!(merkle.verify(b"Another update", &public, &sig));
+        assert!(validate_composition(&merkle).is_err());
+
+        // Leaf keys that can't be regenerated don't match the tree
+        assert!(validate_composition(&Merkle::new(3, broken(Fault::RandomKeygen))).is_err());
+        assert!(validate_composition(&Goldreich::new(4, BrokenScheme::new(Lamport::new(32), Fault::RandomKeygen)))
+            .is_err());
+
+        // Leaked keys go unnoticed
+        let leaking = broken(Fault::LeakKey);
+        let (private, _) = leaking.gen_keys(None);
+        assert_eq!(leaking.sign(msg, &private).1, Some(private));
+        assert_eq!(validate_composition(&Merkle::new(3, leaking)), Ok(()));
+    }
+}
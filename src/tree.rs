@@ -0,0 +1,609 @@
+use std::convert::TryInto;
+use std::marker::PhantomData;
+
+use crate::U256;
+use crate::framing::{write_len, write_nodes, Reader};
+use crate::util::{hash_in, floored_log, Domain, Hasher, Node, Sha256};
+
+/// The default leaf hash for serialized records
+pub fn leaf_hash(record: impl AsRef<[u8]>) -> U256 {
+    hash_in(Domain::Leaf, record)
+}
+
+// Indices, sizes and counts are little-endian `u64`s in the encoded proofs
+fn read_index(input: &mut Reader) -> Option<usize> {
+    input.u64().ok()?.try_into().ok()
+}
+
+// A count followed by that many nodes, which must all be there
+fn read_nodes<N: Node>(input: &mut Reader) -> Option<Box<[N]>> {
+    let count = input.len(input.rest().len() / N::LEN).ok()?;
+    input.nodes(count).ok()
+}
+
+fn write_nodes_with_len<N: Node>(out: &mut Vec<u8>, nodes: &[N]) {
+    write_len(out, nodes.len());
+    write_nodes(out, nodes);
+}
+
+/// Encoded as the leaf index, the tree size and the path
+pub struct Proof<H: Hasher = Sha256> {
+    leaf_idx: usize,
+    tree_size: usize,
+    path: Box<[H::Node]>,
+    hasher: PhantomData<H>,
+}
+
+impl<H: Hasher> Proof<H> {
+    pub fn leaf_idx(&self) -> usize {
+        self.leaf_idx
+    }
+
+    /// The siblings of the nodes on the path from the leaf to the root,
+    /// leaving out promoted nodes
+    pub fn path(&self) -> &[H::Node] {
+        &self.path
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_len(&mut bytes, self.leaf_idx);
+        write_len(&mut bytes, self.tree_size);
+        write_nodes_with_len(&mut bytes, &self.path);
+        bytes
+    }
+
+    /// The inverse of `to_bytes`, or `None` if `bytes` is malformed
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut input = Reader::new(bytes);
+        let leaf_idx = read_index(&mut input)?;
+        let tree_size = read_index(&mut input)?;
+        let path = read_nodes(&mut input)?;
+
+        input.rest().is_empty().then_some(Self { leaf_idx, tree_size, path, hasher: PhantomData })
+    }
+
+    pub fn verify_record<T>(&self, record: &T, leaf_hash: impl Fn(&T) -> H::Node, root: &H::Node) -> bool {
+        self.verify(leaf_hash(record), root)
+    }
+
+    pub fn verify(&self, leaf: H::Node, root: &H::Node) -> bool {
+        if self.leaf_idx >= self.tree_size {
+            return false;
+        }
+
+        let mut path = self.path.iter();
+        let mut node = leaf;
+        let mut idx = self.leaf_idx;
+        let mut len = self.tree_size;
+        while len > 1 {
+            // A node without a sibling is promoted to the next level as is
+            if idx ^ 1 < len {
+                let sibling = match path.next() {
+                    Some(sibling) => sibling,
+                    None => return false,
+                };
+                node = if idx & 1 == 0 {
+                    H::hash_pair_in(Domain::Node, node, sibling)
+                } else {
+                    H::hash_pair_in(Domain::Node, sibling, node)
+                };
+            }
+
+            idx /= 2;
+            len = len.div_ceil(2);
+        }
+
+        path.next().is_none() && node == *root
+    }
+}
+
+/// Proof that a tree of `old_size` leaves is a prefix of a tree of `new_size`
+/// leaves, as in RFC 6962 section 2.1.2. Encoded as both sizes and the path.
+pub struct ConsistencyProof<H: Hasher = Sha256> {
+    old_size: usize,
+    new_size: usize,
+    path: Box<[H::Node]>,
+    hasher: PhantomData<H>,
+}
+
+impl<H: Hasher> ConsistencyProof<H> {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_len(&mut bytes, self.old_size);
+        write_len(&mut bytes, self.new_size);
+        write_nodes_with_len(&mut bytes, &self.path);
+        bytes
+    }
+
+    /// The inverse of `to_bytes`, or `None` if `bytes` is malformed
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut input = Reader::new(bytes);
+        let old_size = read_index(&mut input)?;
+        let new_size = read_index(&mut input)?;
+        let path = read_nodes(&mut input)?;
+
+        input.rest().is_empty().then_some(Self { old_size, new_size, path, hasher: PhantomData })
+    }
+
+    pub fn verify(&self, old_root: &H::Node, new_root: &H::Node) -> bool {
+        if self.old_size == 0 || self.old_size > self.new_size {
+            return false;
+        }
+        if self.old_size == self.new_size {
+            return self.path.is_empty() && old_root == new_root;
+        }
+
+        // Verification algorithm from RFC 9162 section 2.1.4.2
+        let mut path = self.path.iter();
+        let first = if self.old_size.is_power_of_two() {
+            *old_root
+        } else {
+            match path.next() {
+                Some(&node) => node,
+                None => return false,
+            }
+        };
+
+        let mut old_idx = self.old_size - 1;
+        let mut new_idx = self.new_size - 1;
+        while old_idx & 1 == 1 {
+            old_idx >>= 1;
+            new_idx >>= 1;
+        }
+
+        let mut old_node = first;
+        let mut new_node = first;
+        for node in path {
+            if new_idx == 0 {
+                return false;
+            }
+
+            if old_idx & 1 == 1 || old_idx == new_idx {
+                old_node = H::hash_pair_in(Domain::Node, node, old_node);
+                new_node = H::hash_pair_in(Domain::Node, node, new_node);
+                while old_idx & 1 == 0 && old_idx != 0 {
+                    old_idx >>= 1;
+                    new_idx >>= 1;
+                }
+            } else {
+                new_node = H::hash_pair_in(Domain::Node, new_node, node);
+            }
+
+            old_idx >>= 1;
+            new_idx >>= 1;
+        }
+
+        new_idx == 0 && old_node == *old_root && new_node == *new_root
+    }
+}
+
+/// Inclusion proof for several leaves at once. Nodes shared between the
+/// individual paths, or computable from the proven leaves, are left out.
+/// Encoded as the tree size, the leaf indices and the nodes.
+pub struct MultiProof<H: Hasher = Sha256> {
+    leaf_indices: Box<[usize]>,
+    tree_size: usize,
+    nodes: Box<[H::Node]>,
+    hasher: PhantomData<H>,
+}
+
+impl<H: Hasher> MultiProof<H> {
+    /// The proven leaf indices in ascending order
+    pub fn leaf_indices(&self) -> &[usize] {
+        &self.leaf_indices
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_len(&mut bytes, self.tree_size);
+        write_len(&mut bytes, self.leaf_indices.len());
+        self.leaf_indices.iter().for_each(|&idx| write_len(&mut bytes, idx));
+        write_nodes_with_len(&mut bytes, &self.nodes);
+        bytes
+    }
+
+    /// The inverse of `to_bytes`, or `None` if `bytes` is malformed
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut input = Reader::new(bytes);
+        let tree_size = read_index(&mut input)?;
+        let num_leaves = input.len(input.rest().len() / 8).ok()?;
+        let leaf_indices = (0..num_leaves).map(|_| read_index(&mut input)).collect::<Option<_>>()?;
+        let nodes = read_nodes(&mut input)?;
+
+        input.rest().is_empty().then_some(Self { leaf_indices, tree_size, nodes, hasher: PhantomData })
+    }
+
+    /// Verifies the proof given the leaves in the order of `leaf_indices`.
+    pub fn verify(&self, leaves: &[H::Node], root: &H::Node) -> bool {
+        if leaves.is_empty() || leaves.len() != self.leaf_indices.len() {
+            return false;
+        }
+        let ascending = self.leaf_indices.windows(2).all(|w| w[0] < w[1]);
+        if !ascending || *self.leaf_indices.last().unwrap() >= self.tree_size {
+            return false;
+        }
+
+        let mut nodes = self.nodes.iter();
+        let mut known: Vec<_> = self.leaf_indices.iter().copied()
+            .zip(leaves.iter().copied())
+            .collect();
+        let mut len = self.tree_size;
+        while len > 1 {
+            let mut next = Vec::with_capacity(known.len());
+            let mut i = 0;
+            while i < known.len() {
+                let (idx, node) = known[i];
+                let parent = if idx ^ 1 >= len {
+                    node
+                } else if idx & 1 == 0 && known.get(i + 1).map(|&(j, _)| j) == Some(idx + 1) {
+                    i += 1;
+                    H::hash_pair_in(Domain::Node, node, known[i].1)
+                } else {
+                    let sibling = match nodes.next() {
+                        Some(sibling) => sibling,
+                        None => return false,
+                    };
+                    if idx & 1 == 0 {
+                        H::hash_pair_in(Domain::Node, node, sibling)
+                    } else {
+                        H::hash_pair_in(Domain::Node, sibling, node)
+                    }
+                };
+                next.push((idx / 2, parent));
+                i += 1;
+            }
+
+            known = next;
+            len = len.div_ceil(2);
+        }
+
+        nodes.next().is_none() && known[0].1 == *root
+    }
+}
+
+
+/// A Merkle tree over an arbitrary number of leaves. Lone nodes at the end of
+/// a level are promoted unchanged, so the shape matches RFC 6962.
+pub struct MerkleTree<H: Hasher = Sha256> {
+    levels: Vec<Box<[H::Node]>>,
+    hasher: PhantomData<H>,
+}
+
+impl MerkleTree {
+    pub fn new(leaves: &[U256]) -> Self {
+        Self::with_hasher(leaves)
+    }
+
+    /// Builds a tree over structured records, e.g. manifest entries, hashing
+    /// each with `leaf_hash`
+    pub fn from_records<T>(records: &[T], leaf_hash: impl Fn(&T) -> U256) -> Self {
+        let leaves: Vec<_> = records.iter().map(leaf_hash).collect();
+        Self::new(&leaves)
+    }
+
+    /// Builds a tree over serialized records with the default leaf hash
+    pub fn from_serialized<T: AsRef<[u8]>>(records: &[T]) -> Self {
+        Self::from_records(records, |record| leaf_hash(record))
+    }
+}
+
+impl<H: Hasher> MerkleTree<H> {
+    /// Builds a tree whose inner nodes are hashed with `H`
+    pub fn with_hasher(leaves: &[H::Node]) -> Self {
+        assert!(!leaves.is_empty());
+
+        let mut levels = vec![Box::<[H::Node]>::from(leaves)];
+        while levels.last().unwrap().len() > 1 {
+            let level = levels.last().unwrap();
+            let next = level.chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => H::hash_pair_in(Domain::Node, left, right),
+                    [node] => *node,
+                    _ => unreachable!(),
+                })
+                .collect();
+            levels.push(next);
+        }
+
+        Self { levels, hasher: PhantomData }
+    }
+
+    pub fn len(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.levels[0].is_empty()
+    }
+
+    pub fn height(&self) -> usize {
+        self.levels.len() - 1
+    }
+
+    pub fn root(&self) -> H::Node {
+        self.levels[self.height()][0]
+    }
+
+    pub fn leaf(&self, idx: usize) -> H::Node {
+        self.levels[0][idx]
+    }
+
+    pub fn prove(&self, leaf_idx: usize) -> Proof<H> {
+        assert!(leaf_idx < self.len());
+
+        let mut path = Vec::with_capacity(self.height());
+        let mut idx = leaf_idx;
+        for level in &self.levels[..self.height()] {
+            if let Some(&sibling) = level.get(idx ^ 1) {
+                path.push(sibling);
+            }
+            idx /= 2;
+        }
+
+        Proof {
+            leaf_idx,
+            tree_size: self.len(),
+            path: path.into_boxed_slice(),
+            hasher: PhantomData,
+        }
+    }
+
+    pub fn prove_many(&self, leaf_indices: &[usize]) -> MultiProof<H> {
+        let mut leaf_indices = leaf_indices.to_vec();
+        leaf_indices.sort_unstable();
+        leaf_indices.dedup();
+        assert!(leaf_indices.iter().all(|&idx| idx < self.len()));
+
+        let mut nodes = Vec::new();
+        let mut known = leaf_indices.clone();
+        for level in &self.levels[..self.height()] {
+            let mut i = 0;
+            while i < known.len() {
+                let idx = known[i];
+                if idx & 1 == 0 && known.get(i + 1) == Some(&(idx + 1)) {
+                    i += 1;
+                } else if let Some(&sibling) = level.get(idx ^ 1) {
+                    nodes.push(sibling);
+                }
+                i += 1;
+            }
+
+            known = known.iter().map(|idx| idx / 2).collect();
+            known.dedup();
+        }
+
+        MultiProof {
+            leaf_indices: leaf_indices.into_boxed_slice(),
+            tree_size: self.len(),
+            nodes: nodes.into_boxed_slice(),
+            hasher: PhantomData,
+        }
+    }
+
+    pub fn prove_consistency(&self, old_size: usize) -> ConsistencyProof<H> {
+        assert!(0 < old_size && old_size <= self.len());
+
+        let mut path = Vec::new();
+        self.push_sub_proof(old_size, 0, self.len(), true, &mut path);
+
+        ConsistencyProof {
+            old_size,
+            new_size: self.len(),
+            path: path.into_boxed_slice(),
+            hasher: PhantomData,
+        }
+    }
+
+    // SUBPROOF from RFC 6962 over the leaves in start..end
+    fn push_sub_proof(&self, old_size: usize, start: usize, end: usize, complete: bool, path: &mut Vec<H::Node>) {
+        if old_size == end - start {
+            if !complete {
+                path.push(self.get_node(start, end));
+            }
+            return;
+        }
+
+        let split = 1 << floored_log(end - start - 1);
+        if old_size <= split {
+            self.push_sub_proof(old_size, start, start + split, complete, path);
+            path.push(self.get_node(start + split, end));
+        } else {
+            self.push_sub_proof(old_size - split, start + split, end, false, path);
+            path.push(self.get_node(start, start + split));
+        }
+    }
+
+    // Root of the subtree over the leaves in start..end
+    fn get_node(&self, start: usize, end: usize) -> H::Node {
+        let len = end - start;
+        let height = len.next_power_of_two().trailing_zeros() as usize;
+        if start & ((1 << height) - 1) == 0 && (len == 1 << height || end == self.len()) {
+            return self.levels[height][start >> height];
+        }
+
+        let split = start + (1 << (height - 1));
+        H::hash_pair_in(Domain::Node, self.get_node(start, split), self.get_node(split, end))
+    }
+
+    /// Replaces a leaf, rehashing only the nodes on its path to the root.
+    /// Proofs for other leaves handed out earlier become stale and should be
+    /// regenerated with `prove`.
+    pub fn update_leaf(&mut self, leaf_idx: usize, leaf: H::Node) {
+        assert!(leaf_idx < self.len());
+
+        self.levels[0][leaf_idx] = leaf;
+
+        let mut idx = leaf_idx;
+        for height in 0..self.height() {
+            let (lower, upper) = self.levels.split_at_mut(height + 1);
+            let level = &lower[height];
+            let left = idx & !1;
+            upper[0][idx / 2] = match level.get(left + 1) {
+                Some(right) => H::hash_pair_in(Domain::Node, level[left], right),
+                None => level[left],
+            };
+            idx /= 2;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::util::hash;
+
+    use super::*;
+
+    fn leaves(n: usize) -> Vec<U256> {
+        (0..n).map(|i| hash(i.to_le_bytes())).collect()
+    }
+
+    #[test]
+    fn it_works() {
+        for n in 1..20 {
+            let leaves = leaves(n);
+            let tree = MerkleTree::new(&leaves);
+
+            for (i, &leaf) in leaves.iter().enumerate() {
+                let proof = tree.prove(i);
+                assert!(proof.verify(leaf, &tree.root()));
+                assert!(!proof.verify(hash(b"Not a leaf"), &tree.root()));
+            }
+        }
+    }
+
+    #[test]
+    fn encoding() {
+        let leaves = leaves(13);
+        let tree = MerkleTree::new(&leaves);
+
+        let bytes = tree.prove(5).to_bytes();
+        let proof = Proof::<Sha256>::from_bytes(&bytes).unwrap();
+        assert_eq!(proof.to_bytes(), bytes);
+        assert!(proof.verify(leaves[5], &tree.root()));
+        assert!(Proof::<Sha256>::from_bytes(&bytes[..bytes.len() - 1]).is_none());
+        assert!(Proof::<Sha256>::from_bytes(&[bytes.as_slice(), &[0]].concat()).is_none());
+
+        let bytes = tree.prove_many(&[2, 3, 9]).to_bytes();
+        let proof = MultiProof::<Sha256>::from_bytes(&bytes).unwrap();
+        assert_eq!(proof.to_bytes(), bytes);
+        assert!(proof.verify(&[leaves[2], leaves[3], leaves[9]], &tree.root()));
+        assert!(MultiProof::<Sha256>::from_bytes(&bytes[..bytes.len() - 1]).is_none());
+
+        // Counts claiming more than the input holds
+        let mut bogus = bytes.clone();
+        bogus[8..16].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(MultiProof::<Sha256>::from_bytes(&bogus).is_none());
+        let mut bogus = tree.prove(5).to_bytes();
+        bogus[16..24].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(Proof::<Sha256>::from_bytes(&bogus).is_none());
+    }
+
+    #[test]
+    fn records() {
+        struct Entry {
+            path: &'static str,
+            size: u64,
+            digest: U256,
+            mode: u32,
+        }
+
+        fn entry_hash(entry: &Entry) -> U256 {
+            let encoded = [entry.path.as_bytes(), &[0], &entry.size.to_le_bytes(), &entry.digest, &entry.mode.to_le_bytes()];
+            leaf_hash(encoded.concat())
+        }
+
+        let entries = [
+            Entry { path: "bin/app", size: 1024, digest: hash(b"app"), mode: 0o755 },
+            Entry { path: "etc/app.conf", size: 64, digest: hash(b"conf"), mode: 0o644 },
+            Entry { path: "README", size: 10, digest: hash(b"readme"), mode: 0o644 },
+        ];
+        let tree = MerkleTree::from_records(&entries, entry_hash);
+
+        let proof = tree.prove(1);
+        assert!(proof.verify_record(&entries[1], entry_hash, &tree.root()));
+        let tampered = Entry { mode: 0o777, ..entries[1] };
+        assert!(!proof.verify_record(&tampered, entry_hash, &tree.root()));
+
+        let tree = MerkleTree::from_serialized(&["bin/app", "README"]);
+        assert!(tree.prove(0).verify(leaf_hash("bin/app"), &tree.root()));
+    }
+
+    #[test]
+    fn update_leaf() {
+        let mut leaves = leaves(13);
+        let mut tree = MerkleTree::new(&leaves);
+        let old_root = tree.root();
+
+        leaves[12] = hash(b"My new manifest entry");
+        tree.update_leaf(12, leaves[12]);
+        leaves[5] = hash(b"My other entry");
+        tree.update_leaf(5, leaves[5]);
+
+        assert_ne!(tree.root(), old_root);
+        assert_eq!(tree.root(), MerkleTree::new(&leaves).root());
+        assert!(tree.prove(5).verify(leaves[5], &tree.root()));
+        assert!(tree.prove(12).verify(leaves[12], &tree.root()));
+    }
+
+    #[test]
+    fn multi_proof() {
+        let leaves = leaves(29);
+        let tree = MerkleTree::new(&leaves);
+
+        let proof = tree.prove_many(&[17, 3, 28, 4, 5, 3]);
+        assert_eq!(proof.leaf_indices(), &[3, 4, 5, 17, 28]);
+
+        let proven: Vec<_> = proof.leaf_indices().iter().map(|&i| leaves[i]).collect();
+        assert!(proof.verify(&proven, &tree.root()));
+
+        let separate_len: usize = proof.leaf_indices().iter().map(|&i| tree.prove(i).path.len()).sum();
+        assert!(proof.nodes.len() < separate_len);
+
+        let mut tampered = proven.clone();
+        tampered[2] = hash(b"Not a leaf");
+        assert!(!proof.verify(&tampered, &tree.root()));
+        assert!(!proof.verify(&proven[1..], &tree.root()));
+
+        let all: Vec<_> = (0..leaves.len()).collect();
+        let proof = tree.prove_many(&all);
+        assert!(proof.nodes.is_empty());
+        assert!(proof.verify(&leaves, &tree.root()));
+    }
+
+    #[test]
+    fn consistency() {
+        let leaves = leaves(17);
+        for new_size in 1..=leaves.len() {
+            let new_tree = MerkleTree::new(&leaves[..new_size]);
+
+            for old_size in 1..=new_size {
+                let old_tree = MerkleTree::new(&leaves[..old_size]);
+                let proof = new_tree.prove_consistency(old_size);
+
+                assert!(proof.verify(&old_tree.root(), &new_tree.root()));
+                assert!(!proof.verify(&hash(b"Not a root"), &new_tree.root()));
+                assert!(!proof.verify(&old_tree.root(), &hash(b"Not a root")));
+            }
+        }
+    }
+
+    #[test]
+    fn consistency_encoding() {
+        let leaves = leaves(17);
+        let tree = MerkleTree::new(&leaves);
+        let old_root = MerkleTree::new(&leaves[..6]).root();
+
+        let bytes = tree.prove_consistency(6).to_bytes();
+        let proof = ConsistencyProof::<Sha256>::from_bytes(&bytes).unwrap();
+        assert_eq!(proof.to_bytes(), bytes);
+        assert!(proof.verify(&old_root, &tree.root()));
+
+        assert!(ConsistencyProof::<Sha256>::from_bytes(&bytes[..bytes.len() - 1]).is_none());
+        assert!(ConsistencyProof::<Sha256>::from_bytes(&[bytes.as_slice(), &[0]].concat()).is_none());
+        let mut bogus = bytes.clone();
+        bogus[16..24].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(ConsistencyProof::<Sha256>::from_bytes(&bogus).is_none());
+    }
+}
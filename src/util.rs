@@ -1,10 +1,292 @@
-use sha2::{Digest, Sha256};
+use std::convert::TryInto;
+use std::fmt::Debug;
+use std::hash::Hash;
 
-use crate::U256;
-use std::mem::size_of;
+use sha2::Digest;
+
+use crate::{SignatureScheme, U256, U512};
+
+/// A single invocation of `hash` or `hash_pair`
+pub struct HashCall {
+    pub input: Box<[u8]>,
+    pub output: Box<[u8]>,
+}
+
+#[cfg(any(test, feature = "unstable", feature = "corpus", feature = "archive"))]
+pub use recording::record_hashes;
+
+// Checking whether to record costs every hash a thread-local lookup, so the
+// hook is only built for the features that record
+#[cfg(any(test, feature = "unstable", feature = "corpus", feature = "archive"))]
+mod recording {
+    use std::cell::RefCell;
+    use std::panic::{self, AssertUnwindSafe};
+
+    use super::HashCall;
+
+    thread_local! {
+        static RECORDED: RefCell<Option<Vec<HashCall>>> = const { RefCell::new(None) };
+    }
+
+    pub(super) fn record(input: impl FnOnce() -> Box<[u8]>, output: &[u8]) {
+        RECORDED.with(|recorded| {
+            if let Some(calls) = recorded.borrow_mut().as_mut() {
+                calls.push(HashCall { input: input(), output: output.into() });
+            }
+        });
+    }
+
+    /// Runs `f` and returns, along with its result, every hash computed
+    /// through this module on the current thread while it ran, in order.
+    pub fn record_hashes<T>(f: impl FnOnce() -> T) -> (T, Vec<HashCall>) {
+        let outer = RECORDED.with(|recorded| recorded.replace(Some(Vec::new())));
+        // Stop recording even if `f` unwinds, e.g. out of a `Budget`
+        let result = panic::catch_unwind(AssertUnwindSafe(f));
+        let calls = RECORDED.with(|recorded| recorded.replace(outer)).unwrap();
+
+        match result {
+            Ok(result) => (result, calls),
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    }
+}
+
+/// The role a hash plays. It is prefixed to the input along with
+/// `DOMAIN_VERSION`, so that e.g. a message digest can't collide with the
+/// encoding of an inner node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Domain {
+    Message = 0,
+    Leaf = 1,
+    Node = 2,
+    Chain = 3,
+    /// Keys and bitmasks derived from a public seed, as in WOTS+
+    Mask = 4,
+    /// Chain ends compressed into a single public key
+    PublicKey = 5,
+    /// Secret values derived from a private seed, e.g. HORST leaves
+    Secret = 6,
+}
+
+/// Bumped whenever the domain prefixes change
+pub const DOMAIN_VERSION: u8 = 1;
+
+impl Domain {
+    pub fn prefix(self) -> [u8; 2] {
+        [DOMAIN_VERSION, self as u8]
+    }
+}
+
+/// The output of a `Hasher`, and so the width of the nodes of the trees
+/// hashed with it
+pub trait Node: Copy + Eq + Ord + Hash + Default + Debug + AsRef<[u8]> + AsMut<[u8]> + Send + Sync + 'static {
+    const LEN: usize;
+
+    /// The first 32 bytes, e.g. for deriving seeds
+    fn truncate(&self) -> U256 {
+        self.as_ref()[..32].try_into().unwrap()
+    }
+}
+
+impl Node for U256 {
+    const LEN: usize = 32;
+}
+
+impl Node for U512 {
+    const LEN: usize = 64;
+}
+
+/// A hash function that the schemes can be instantiated with. Only `digest`
+/// needs implementing; the rest is how the schemes use it. Implementations
+/// are stateless marker types, shareable between threads. The one-time
+/// schemes and SPHINCS need 256-bit output, while the tree schemes take
+/// nodes of either width.
+pub trait Hasher: Send + Sync {
+    type Node: Node;
+
+    /// Hashes the concatenation of `parts`
+    fn digest(parts: &[&[u8]]) -> Self::Node;
+
+    /// A 512-bit digest of the concatenation of `parts`, for the SPHINCS
+    /// message digest. SHA-512 unless the hash function has a wider variant.
+    fn wide_digest(parts: &[&[u8]]) -> [u8; 64] {
+        let mut hasher = sha2::Sha512::new();
+        for part in parts {
+            hasher.update(part);
+        }
+        let mut out = [0; 64];
+        out.copy_from_slice(&hasher.finalize());
+        out
+    }
+
+    fn hash_parts(parts: &[&[u8]]) -> Self::Node {
+        #[cfg(feature = "profiling")]
+        crate::profiling::probe();
+        crate::budget::charge();
+        let output = Self::digest(parts);
+        #[cfg(any(test, feature = "unstable", feature = "corpus", feature = "archive"))]
+        recording::record(|| parts.concat().into(), output.as_ref());
+        output
+    }
+
+    fn hash(data: impl AsRef<[u8]>) -> Self::Node {
+        Self::hash_parts(&[data.as_ref()])
+    }
+
+    fn hash_pair(left: impl AsRef<[u8]>, right: impl AsRef<[u8]>) -> Self::Node {
+        Self::hash_parts(&[left.as_ref(), right.as_ref()])
+    }
+
+    fn hash_in(domain: Domain, data: impl AsRef<[u8]>) -> Self::Node {
+        Self::hash_parts(&[&domain.prefix(), data.as_ref()])
+    }
+
+    /// Hashes each of `values` in place, like `hash_in`. Backends that hash
+    /// several independent inputs at once, e.g. multi-buffer SIMD ones, can
+    /// override this to take whole batches of chain steps, but must give the
+    /// same results.
+    fn hash_each_in(domain: Domain, values: &mut [Self::Node]) {
+        values.iter_mut().for_each(|value| *value = Self::hash_in(domain, *value));
+    }
+
+    fn hash_n_in(domain: Domain, data: Self::Node, times: usize) -> Self::Node {
+        (0..times).fold(data, |acc, _| Self::hash_in(domain, acc))
+    }
+
+    fn hash_pair_in(domain: Domain, left: impl AsRef<[u8]>, right: impl AsRef<[u8]>) -> Self::Node {
+        Self::hash_parts(&[&domain.prefix(), left.as_ref(), right.as_ref()])
+    }
+}
+
+/// The default
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Sha256;
+
+impl Hasher for Sha256 {
+    type Node = U256;
+
+    fn digest(parts: &[&[u8]]) -> U256 {
+        let mut hasher = sha2::Sha256::new();
+        for part in parts {
+            hasher.update(part);
+        }
+        hasher.finalize().into()
+    }
+}
+
+/// SHA-512 truncated to 256 bits, which is faster than SHA-256 on 64-bit
+/// platforms without SHA instructions
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Sha512_256;
+
+impl Hasher for Sha512_256 {
+    type Node = U256;
+
+    fn digest(parts: &[&[u8]]) -> U256 {
+        let mut hasher = sha2::Sha512Trunc256::new();
+        for part in parts {
+            hasher.update(part);
+        }
+        hasher.finalize().into()
+    }
+}
+
+/// SHA-512 with 512-bit nodes, for trees whose signatures must hold up for
+/// decades, e.g. in archives. Only the tree schemes take it: `Merkle`,
+/// `Goldreich` and `Horst`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Sha512;
+
+impl Hasher for Sha512 {
+    type Node = U512;
+
+    fn digest(parts: &[&[u8]]) -> U512 {
+        U512(Self::wide_digest(parts))
+    }
+}
+
+/// SHAKE256 with 256-bit output, and 512-bit output for wide digests, as in
+/// the SHAKE instances of SPHINCS+
+#[cfg(feature = "shake")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Shake256;
+
+#[cfg(feature = "shake")]
+impl Shake256 {
+    fn read<const N: usize>(parts: &[&[u8]]) -> [u8; N] {
+        use sha3::digest::{ExtendableOutput, Update, XofReader};
+
+        let mut hasher = sha3::Shake256::default();
+        for part in parts {
+            hasher.update(part);
+        }
+        let mut out = [0; N];
+        hasher.finalize_xof().read(&mut out);
+        out
+    }
+}
+
+#[cfg(feature = "shake")]
+impl Hasher for Shake256 {
+    type Node = U256;
+
+    fn digest(parts: &[&[u8]]) -> U256 {
+        Self::read(parts)
+    }
+
+    fn wide_digest(parts: &[&[u8]]) -> [u8; 64] {
+        Self::read(parts)
+    }
+}
+
+/// BLAKE3 with 256-bit output, and 512-bit output for wide digests. Faster
+/// than SHA-256 on CPUs without SHA instructions, where hash-bound key
+/// generation, e.g. of HORST or large Merkle trees, benefits the most.
+#[cfg(feature = "blake3")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Blake3;
+
+#[cfg(feature = "blake3")]
+impl Blake3 {
+    fn hasher(parts: &[&[u8]]) -> blake3::Hasher {
+        let mut hasher = blake3::Hasher::new();
+        for part in parts {
+            hasher.update(part);
+        }
+        hasher
+    }
+}
+
+#[cfg(feature = "blake3")]
+impl Hasher for Blake3 {
+    type Node = U256;
+
+    fn digest(parts: &[&[u8]]) -> U256 {
+        // Setting up a hasher costs about as much as hashing a block, and
+        // most inputs here fit in one
+        let mut block = [0; blake3::BLOCK_LEN];
+        let mut len = 0;
+        for part in parts {
+            if len + part.len() > block.len() {
+                return Self::hasher(parts).finalize().into();
+            }
+            block[len..len + part.len()].copy_from_slice(part);
+            len += part.len();
+        }
+        blake3::hash(&block[..len]).into()
+    }
+
+    fn wide_digest(parts: &[&[u8]]) -> [u8; 64] {
+        let mut out = [0; 64];
+        Self::hasher(parts).finalize_xof().fill(&mut out);
+        out
+    }
+}
+
+// Shorthands for SHA-256, used outside of the schemes
 
 pub fn hash(data: impl AsRef<[u8]>) -> U256 {
-    Sha256::digest(data.as_ref()).into()
+    Sha256::hash(data)
 }
 
 pub fn hash_n(data: U256, times: usize) -> U256 {
@@ -12,16 +294,151 @@ pub fn hash_n(data: U256, times: usize) -> U256 {
 }
 
 pub fn hash_pair(left: impl AsRef<[u8]>, right: impl AsRef<[u8]>) -> U256 {
-    let mut hasher = Sha256::new();
-    hasher.update(left);
-    hasher.update(right);
-    hasher.finalize().into()
+    Sha256::hash_pair(left, right)
+}
+
+pub fn hash_in(domain: Domain, data: impl AsRef<[u8]>) -> U256 {
+    Sha256::hash_in(domain, data)
+}
+
+pub fn hash_n_in(domain: Domain, data: U256, times: usize) -> U256 {
+    Sha256::hash_n_in(domain, data, times)
+}
+
+pub fn hash_pair_in(domain: Domain, left: impl AsRef<[u8]>, right: impl AsRef<[u8]>) -> U256 {
+    Sha256::hash_pair_in(domain, left, right)
 }
 
-pub fn div_up(dividend: usize, divisor: usize) -> usize {
-    (dividend + (divisor / 2)) / divisor
+/// Whether `scheme` can sign messages of `len` bytes
+pub fn accepts_len<S: SignatureScheme>(scheme: &S, len: usize) -> bool {
+    scheme.max_message_len().is_none_or(|max| len <= max)
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    let mut out = vec![0; 2 * bytes.len()];
+    hex::encode_into(bytes, &mut out).to_owned()
+}
+
+/// Hex conversion into fixed buffers, without allocating
+pub mod hex {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+    /// Writes `bytes` as lowercase hex to the start of `out`, which must be
+    /// at least twice as long
+    pub fn encode_into<'a>(bytes: &[u8], out: &'a mut [u8]) -> &'a str {
+        let out = &mut out[..2 * bytes.len()];
+        for (byte, pair) in bytes.iter().zip(out.chunks_exact_mut(2)) {
+            pair[0] = DIGITS[(byte >> 4) as usize];
+            pair[1] = DIGITS[(byte & 0xf) as usize];
+        }
+
+        std::str::from_utf8(out).unwrap()
+    }
+
+    const fn digit(c: u8) -> Option<u8> {
+        match c {
+            b'0'..=b'9' => Some(c - b'0'),
+            b'a'..=b'f' => Some(c - b'a' + 10),
+            b'A'..=b'F' => Some(c - b'A' + 10),
+            _ => None,
+        }
+    }
+
+    /// Parses exactly `N` bytes of hex in either case. Usable in constants.
+    pub const fn decode<const N: usize>(hex: &str) -> Option<[u8; N]> {
+        let hex = hex.as_bytes();
+        if hex.len() != 2 * N {
+            return None;
+        }
+
+        let mut out = [0; N];
+        let mut i = 0;
+        while i < N {
+            match (digit(hex[2 * i]), digit(hex[2 * i + 1])) {
+                (Some(high), Some(low)) => out[i] = high << 4 | low,
+                _ => return None,
+            }
+            i += 1;
+        }
+
+        Some(out)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn it_works() {
+            const KEY: Option<[u8; 3]> = decode("00fFa5");
+            assert_eq!(KEY, Some([0x00, 0xff, 0xa5]));
+            assert_eq!(decode::<3>("00ffa"), None);
+            assert_eq!(decode::<3>("00ffag"), None);
+
+            let mut out = [0; 8];
+            assert_eq!(encode_into(&[0x00, 0xff, 0xa5], &mut out), "00ffa5");
+        }
+    }
 }
 
 pub fn floored_log(n: usize) -> usize {
-    (size_of::<usize>() * 8) - n.leading_zeros() as usize - 1
-}
\ No newline at end of file
+    usize::BITS as usize - n.leading_zeros() as usize - 1
+}
+
+/// Reads `bytes` as a little-endian number and splits it into `bits`-bit
+/// digits, least significant first, padding the last digit with zeros
+pub fn base_w(bytes: &[u8], bits: usize) -> impl Iterator<Item = usize> + '_ {
+    assert!((1..=32).contains(&bits));
+
+    (0..(bytes.len() * 8).div_ceil(bits)).map(move |i| {
+        let (start, shift) = (i * bits / 8, i * bits % 8);
+        let end = bytes.len().min(start + 8);
+        let mut window = [0; 8];
+        window[..end - start].copy_from_slice(&bytes[start..end]);
+
+        ((u64::from_le_bytes(window) >> shift) & ((1 << bits) - 1)) as usize
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        assert_eq!(to_hex(&Sha256::hash(b"abc")), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(to_hex(&Sha512_256::hash(b"abc")), "53048e2681941ef99b2e29b76b4c7dabe4c2d0c634fc6d46e0e2f13107e7af23");
+        assert_eq!(Sha256::hash_pair(b"a", b"bc"), hash(b"abc"));
+    }
+
+    #[test]
+    fn sha512() {
+        let digest = Sha512::hash(b"abc");
+        assert_eq!(to_hex(&digest.as_ref()[..8]), "ddaf35a193617aba");
+        assert_eq!(digest.truncate(), digest.0[..32]);
+    }
+
+    #[test]
+    fn base_ws() {
+        assert_eq!(base_w(&[0xb4, 0x01], 4).collect::<Vec<_>>(), [4, 0xb, 1, 0]);
+        assert_eq!(base_w(&[0xb4, 0x01], 3).collect::<Vec<_>>(), [4, 6, 6, 0, 0, 0]);
+        assert_eq!(base_w(&[0xff; 9], 32).collect::<Vec<_>>(), [0xffff_ffff, 0xffff_ffff, 0xff]);
+        assert_eq!(base_w(&[], 8).count(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "blake3")]
+    fn blake3() {
+        assert_eq!(to_hex(&Blake3::hash(b"")), "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262");
+        assert_eq!(Blake3::wide_digest(&[b"abc"])[..32], Blake3::hash(b"abc"));
+    }
+
+    #[test]
+    #[cfg(feature = "shake")]
+    fn shake() {
+        assert_eq!(to_hex(&Shake256::hash(b"")), "46b9dd2b0ba88d13233b3feb743eeb243fcd52ea62b81b82b50c27646ed5762f");
+        // The 256-bit digest is a prefix of the wide one
+        assert_eq!(Shake256::wide_digest(&[b"abc"])[..32], Shake256::hash(b"abc"));
+    }
+}
@@ -1,42 +1,188 @@
-use bytemuck::{bytes_of, cast_slice};
+use std::convert::TryInto;
+use std::marker::PhantomData;
+
+use bytemuck::cast_slice;
 use rand::prelude::{SeedableRng, StdRng};
 use rand::{RngCore, Rng};
-use rug::Integer;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Estimate, SignatureScheme, U256};
+use crate::framing::{Encode, Reader};
+use crate::util::{base_w, floored_log, Domain, Hasher, Sha256};
+#[cfg(feature = "unstable")]
+use crate::witness::{self, Witness};
+
+/// Walks a hash chain from `start`, the value at step `from_step`, to step
+/// `to_step`. Key generation, signing and verification all go through this,
+/// so related constructions can reuse the exact same chains. Chains here
+/// aren't keyed by an address or public seed, see `chain_plus` for that.
+pub fn chain<H: Hasher<Node = U256>>(start: U256, from_step: usize, to_step: usize) -> U256 {
+    walk(start, from_step, to_step, |_, value| H::hash_in(Domain::Chain, value))
+}
+
+/// Like `chain`, but keying each step and masking its input with values
+/// derived from the public seed and the address of the step, as in WOTS+
+pub fn chain_plus<H: Hasher<Node = U256>>(start: U256, from_step: usize, to_step: usize, seed: &U256, address: Address) -> U256 {
+    walk(start, from_step, to_step, |step, value| {
+        let address = address.with_hash(step as u32);
+        let key = H::hash_pair_in(Domain::Mask, seed, address.with_key_and_mask(0).to_bytes());
+        let mask = H::hash_pair_in(Domain::Mask, seed, address.with_key_and_mask(1).to_bytes());
+
+        let mut masked = value;
+        masked.iter_mut().zip(mask).for_each(|(byte, mask)| *byte ^= mask);
+        H::hash_pair_in(Domain::Chain, key, masked)
+    })
+}
+
+/// Walks every chain `i` from `values[i]`, its value at step `from[i]`, to
+/// step `to[i]`, like `chain` on each. The chains advance in lockstep, with
+/// the steps of a round hashed in one `Hasher::hash_each_in` batch, so the
+/// long chains of large `w` can go through a multi-buffer backend.
+pub fn chains<H: Hasher<Node = U256>>(values: &mut [U256], from: &[usize], to: &[usize]) {
+    assert!(values.len() == from.len() && values.len() == to.len());
+    assert!(from.iter().zip(to).all(|(from, to)| from <= to));
+
+    let mut walking = Vec::with_capacity(values.len());
+    let mut batch = Vec::with_capacity(values.len());
+    for step in 0..to.iter().copied().max().unwrap_or(0) {
+        walking.clear();
+        walking.extend((0..values.len()).filter(|&i| (from[i]..to[i]).contains(&step)));
+        batch.clear();
+        batch.extend(walking.iter().map(|&i| values[i]));
+
+        H::hash_each_in(Domain::Chain, &mut batch);
+        walking.iter().zip(&batch).for_each(|(&i, &value)| values[i] = value);
+    }
+}
+
+// Applies `step` to go from each step of a chain to the next
+fn walk(start: U256, from_step: usize, to_step: usize, mut step: impl FnMut(usize, U256) -> U256) -> U256 {
+    assert!(from_step <= to_step);
+    (from_step..to_step).fold(start, |value, i| step(i, value))
+}
+
+/// Where a WOTS+ hash sits, so no two hashes under the same public seed are
+/// keyed or masked alike. Encoded in 32 bytes as in RFC 8391, with the
+/// layer, tree and key pair set by whatever places the key, e.g. a
+/// hypertree, and the rest by the chains themselves.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Address {
+    pub layer: u32,
+    pub tree: u64,
+    pub key_pair: u32,
+    chain: u32,
+    hash: u32,
+    key_and_mask: u32,
+}
 
-use crate::{SignatureScheme, U256};
-use crate::util::{hash, hash_n, div_up, floored_log};
-use rug::integer::Order;
+impl Address {
+    /// The chain within the key, as `WotsPlus` numbers them from 0
+    pub fn with_chain(self, chain: u32) -> Self {
+        Self { chain, ..self }
+    }
 
+    /// The step within the chain, which `chain_plus` sets for each step
+    pub fn with_hash(self, hash: u32) -> Self {
+        Self { hash, ..self }
+    }
+
+    fn with_key_and_mask(self, key_and_mask: u32) -> Self {
+        Self { key_and_mask, ..self }
+    }
+
+    pub fn to_bytes(&self) -> U256 {
+        let mut bytes = [0; 32];
+        bytes[..4].copy_from_slice(&self.layer.to_be_bytes());
+        bytes[4..12].copy_from_slice(&self.tree.to_be_bytes());
+        // The type of address, always that of a WOTS+ hash
+        bytes[12..16].copy_from_slice(&0u32.to_be_bytes());
+        bytes[16..20].copy_from_slice(&self.key_pair.to_be_bytes());
+        bytes[20..24].copy_from_slice(&self.chain.to_be_bytes());
+        bytes[24..28].copy_from_slice(&self.hash.to_be_bytes());
+        bytes[28..].copy_from_slice(&self.key_and_mask.to_be_bytes());
+        bytes
+    }
+}
+
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Key(Box<[U256]>);
 
+/// A WOTS+ public key: the public seed followed by the ends of the chains
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PlusKey(Box<[U256]>);
+
+impl PlusKey {
+    pub fn seed(&self) -> &U256 {
+        &self.0[0]
+    }
+
+    fn ends(&self) -> &[U256] {
+        &self.0[1..]
+    }
+}
+
+impl AsRef<[u8]> for PlusKey {
+    fn as_ref(&self) -> &[u8] {
+        cast_slice(&self.0)
+    }
+}
+
+impl Key {
+    /// The inverse of `as_ref`, or `None` if `bytes` isn't a whole number of
+    /// chain values
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if !bytes.len().is_multiple_of(32) {
+            return None;
+        }
+
+        Some(Self(bytes.chunks_exact(32).map(|value| value.try_into().unwrap()).collect()))
+    }
+}
+
 impl AsRef<[u8]> for Key {
     fn as_ref(&self) -> &[u8] {
-        cast_slice(&*self.0)
+        cast_slice(&self.0)
     }
 }
 
 
+/// Digits are at most a byte, so chains are at most 255 steps long
+pub const MAX_W: usize = 256;
+
 #[derive(Clone, Copy)]
-pub struct Winternitz {
+pub struct Winternitz<H = Sha256> {
     w: usize,
     len1: usize,
     len2: usize,
     len: usize,
+    hasher: PhantomData<H>,
 }
 
 impl Winternitz {
     pub fn new(w: usize) -> Self {
-        assert!(w.is_power_of_two());
+        assert!(w.is_power_of_two() && (2..=MAX_W).contains(&w));
 
+        // As in RFC 8391, with the message digits rounded up when log_w
+        // doesn't divide 256
         let log_w = w.trailing_zeros() as usize;
-        let len1 = div_up(256, log_w);
+        let len1 = 256usize.div_ceil(log_w);
         let len2 = floored_log(len1 * (w - 1)) / log_w + 1;
         let len = len1 + len2;
 
         Self {
-            w, len1, len2, len
+            w, len1, len2, len, hasher: PhantomData
         }
     }
+}
+
+impl<H: Hasher<Node = U256>> Winternitz<H> {
+    pub fn with_hasher<H2: Hasher<Node = U256>>(self) -> Winternitz<H2> {
+        let Self { w, len1, len2, len, .. } = self;
+        Winternitz { w, len1, len2, len, hasher: PhantomData }
+    }
 
     fn gen_private(&self, seed: U256) -> Key {
         let mut rng = StdRng::from_seed(seed);
@@ -49,31 +195,33 @@ impl Winternitz {
         Key(private.into_boxed_slice())
     }
 
-    fn push_base_w(&self, val: &[u8], digits: &mut Vec<usize>) {
-        let mut i = Integer::from_digits(val, Order::Lsf);
-        while i > 0 {
-            digits.push(i.mod_u(self.w as u32) as usize);
-            i /= self.w as u32;
-        }
+    /// Every hash computed while verifying, for building verification circuits
+    #[cfg(feature = "unstable")]
+    pub fn export_witness(&self, msg: &[u8], public: &Key, sig: &Key) -> Witness {
+        witness::export(self, msg, public, sig)
     }
 
     fn hash_counts(&self, msg: &[u8]) -> Vec<usize> {
-        let mut counts = Vec::with_capacity(self.len);
+        self.digits(&H::hash_in(Domain::Message, msg))
+    }
 
-        // Is this fine? (not necessarily self.len1 long)
-        self.push_base_w(&hash(msg), &mut counts);
+    // Exactly len1 digits of the digest followed by len2 of the checksum,
+    // least significant first, so every signature has the same shape
+    fn digits(&self, digest: &U256) -> Vec<usize> {
+        let log_w = self.w.trailing_zeros() as usize;
+        let mut digits: Vec<_> = base_w(digest, log_w).collect();
+        debug_assert_eq!(digits.len(), self.len1);
 
-        // same
-        let checksum: usize = counts.iter()
-            .map(|&m| self.w - 1 - m as usize)
+        let checksum: usize = digits.iter()
+            .map(|&m| self.w - 1 - m)
             .sum();
-        self.push_base_w(bytes_of(&checksum), &mut counts);
+        digits.extend(base_w(&(checksum as u64).to_le_bytes(), log_w).take(self.len2));
 
-        counts
+        digits
     }
 }
 
-impl SignatureScheme for Winternitz {
+impl<H: Hasher<Node = U256>> SignatureScheme for Winternitz<H> {
     type Private = U256;
     type Public = Key;
     type Signature = Key;
@@ -84,31 +232,260 @@ impl SignatureScheme for Winternitz {
             Some(s) => s,
         };
 
-        let private = self.gen_private(seed);
+        let mut public = self.gen_private(seed);
+        chains::<H>(&mut public.0, &vec![0; self.len], &vec![self.w - 1; self.len]);
+
+        (seed, public)
+    }
+
+    fn sign(&self, msg: &[u8], private: &Self::Private) -> Self::Signature {
+        let counts = self.hash_counts(msg);
+        let mut sig = self.gen_private(*private);
+        chains::<H>(&mut sig.0, &vec![0; self.len], &counts);
+
+        sig
+    }
 
-        let mut public = vec![[0; 32]; self.len];
-        for (i, pk) in public.iter_mut().enumerate() {
-            *pk = hash_n(private.0[i], self.w - 1);
+    fn verify(&self, msg: &[u8], public: &Self::Public, sig: &Self::Signature) -> bool {
+        let counts = self.hash_counts(msg);
+        if public.0.len() != self.len || sig.0.len() != self.len {
+            return false;
         }
 
-        (seed, Key(public.into_boxed_slice()))
+        self.ends(&counts, sig) == public.0
+    }
+}
+
+impl<H: Hasher<Node = U256>> Winternitz<H> {
+    // The chain ends that `sig` leads to for the message digits `counts`
+    fn ends(&self, counts: &[usize], sig: &Key) -> Box<[U256]> {
+        let mut ends = sig.0.clone();
+        chains::<H>(&mut ends, counts, &vec![self.w - 1; self.len]);
+        ends
+    }
+}
+
+impl<H: Hasher<Node = U256>> Encode for Winternitz<H> {
+    fn write_signature(&self, sig: &Key, out: &mut Vec<u8>) {
+        out.extend_from_slice(sig.as_ref());
+    }
+
+    fn read_signature(&self, input: &mut Reader) -> Result<Key, Error> {
+        Ok(Key(input.nodes(self.len)?))
+    }
+
+    fn write_public(&self, public: &Key, out: &mut Vec<u8>) {
+        out.extend_from_slice(public.as_ref());
+    }
+
+    fn read_public(&self, input: &mut Reader) -> Result<Key, Error> {
+        Ok(Key(input.nodes(self.len)?))
+    }
+}
+
+impl<H: Hasher<Node = U256>> Estimate for Winternitz<H> {
+    fn public_size(&self) -> usize {
+        self.len * 32
+    }
+
+    fn signature_size(&self) -> usize {
+        self.len * 32
+    }
+
+    fn verify_hashes(&self) -> usize {
+        1 + self.len * (self.w - 1)
+    }
+}
+
+
+/// `Winternitz` with the public key compressed into a single hash of the
+/// chain ends. `verify` recomputes the ends from the signature and compares
+/// their hash, so signatures stay the same while the key a Merkle leaf
+/// carries shrinks to 32 bytes.
+#[derive(Clone, Copy)]
+pub struct CompressedWinternitz<H = Sha256> {
+    winternitz: Winternitz<H>,
+}
+
+impl CompressedWinternitz {
+    pub fn new(w: usize) -> Self {
+        Self { winternitz: Winternitz::new(w) }
+    }
+}
+
+impl<H: Hasher<Node = U256>> CompressedWinternitz<H> {
+    pub fn with_hasher<H2: Hasher<Node = U256>>(self) -> CompressedWinternitz<H2> {
+        CompressedWinternitz { winternitz: self.winternitz.with_hasher() }
+    }
+
+    /// The compressed form of a full `Winternitz` public key
+    pub fn compress(public: &Key) -> U256 {
+        H::hash_in(Domain::PublicKey, public)
+    }
+}
+
+impl<H: Hasher<Node = U256>> SignatureScheme for CompressedWinternitz<H> {
+    type Private = U256;
+    type Public = U256;
+    type Signature = Key;
+
+    fn gen_keys(&self, seed: Option<U256>) -> (Self::Private, Self::Public) {
+        let (private, public) = self.winternitz.gen_keys(seed);
+        (private, Self::compress(&public))
     }
 
     fn sign(&self, msg: &[u8], private: &Self::Private) -> Self::Signature {
-        let counts = self.hash_counts(msg);
-        let private = self.gen_private(*private);
+        self.winternitz.sign(msg, private)
+    }
 
-        let mut sig = Vec::with_capacity(self.len);
-        for (&sk, count) in private.0.iter().zip(counts) {
-            sig.push(hash_n(sk, count));
+    fn verify(&self, msg: &[u8], public: &Self::Public, sig: &Self::Signature) -> bool {
+        let counts = self.winternitz.hash_counts(msg);
+        if sig.0.len() != self.winternitz.len {
+            return false;
         }
- 
-        Key(sig.into_boxed_slice())
+
+        Self::compress(&Key(self.winternitz.ends(&counts, sig))) == *public
+    }
+}
+
+impl<H: Hasher<Node = U256>> Encode for CompressedWinternitz<H> {
+    fn write_signature(&self, sig: &Key, out: &mut Vec<u8>) {
+        self.winternitz.write_signature(sig, out)
+    }
+
+    fn read_signature(&self, input: &mut Reader) -> Result<Key, Error> {
+        self.winternitz.read_signature(input)
+    }
+
+    fn write_public(&self, public: &U256, out: &mut Vec<u8>) {
+        out.extend_from_slice(public);
+    }
+
+    fn read_public(&self, input: &mut Reader) -> Result<U256, Error> {
+        input.node()
+    }
+}
+
+impl<H: Hasher<Node = U256>> Estimate for CompressedWinternitz<H> {
+    fn public_size(&self) -> usize {
+        32
+    }
+
+    fn signature_size(&self) -> usize {
+        self.winternitz.signature_size()
+    }
+
+    fn verify_hashes(&self) -> usize {
+        self.winternitz.verify_hashes() + 1
+    }
+}
+
+
+/// WOTS+ as in XMSS and SPHINCS+, with the chains of `Winternitz` keyed and
+/// masked by a public seed and an address. The masks let its security rest
+/// on second-preimage resistance rather than collision resistance.
+#[derive(Clone, Copy)]
+pub struct WotsPlus<H = Sha256> {
+    winternitz: Winternitz<H>,
+    address: Address,
+}
+
+impl WotsPlus {
+    pub fn new(w: usize) -> Self {
+        Self { winternitz: Winternitz::new(w), address: Address::default() }
+    }
+}
+
+impl<H: Hasher<Node = U256>> WotsPlus<H> {
+    pub fn with_hasher<H2: Hasher<Node = U256>>(self) -> WotsPlus<H2> {
+        WotsPlus { winternitz: self.winternitz.with_hasher(), address: self.address }
+    }
+
+    /// Where keys of this scheme sit, e.g. in a hypertree
+    pub fn with_address(self, address: Address) -> Self {
+        Self { address: Address { chain: 0, hash: 0, key_and_mask: 0, ..address }, ..self }
+    }
+
+    fn chain(&self, i: usize, start: U256, from_step: usize, to_step: usize, seed: &U256) -> U256 {
+        chain_plus::<H>(start, from_step, to_step, seed, self.address.with_chain(i as u32))
+    }
+}
+
+impl<H: Hasher<Node = U256>> SignatureScheme for WotsPlus<H> {
+    /// The private seed, from which the public seed is derived as well
+    type Private = U256;
+    type Public = PlusKey;
+    type Signature = Key;
+
+    fn gen_keys(&self, seed: Option<U256>) -> (Self::Private, Self::Public) {
+        let seed = match seed {
+            None => StdRng::from_entropy().gen(),
+            Some(s) => s,
+        };
+
+        let public_seed = H::hash_in(Domain::Mask, seed);
+        let private = self.winternitz.gen_private(seed);
+        let ends = private.0.iter()
+            .enumerate()
+            .map(|(i, &sk)| self.chain(i, sk, 0, self.winternitz.w - 1, &public_seed));
+
+        (seed, PlusKey(std::iter::once(public_seed).chain(ends).collect()))
+    }
+
+    fn sign(&self, msg: &[u8], private: &Self::Private) -> Self::Signature {
+        let counts = self.winternitz.hash_counts(msg);
+        let public_seed = H::hash_in(Domain::Mask, private);
+
+        let sig = self.winternitz.gen_private(*private).0.iter()
+            .zip(counts)
+            .enumerate()
+            .map(|(i, (&sk, count))| self.chain(i, sk, 0, count, &public_seed))
+            .collect();
+        Key(sig)
     }
 
     fn verify(&self, msg: &[u8], public: &Self::Public, sig: &Self::Signature) -> bool {
-        self.hash_counts(msg).iter().enumerate()
-            .all(|(i, &count)| public.0[i] == hash_n(sig.0[i], self.w - 1 - count))
+        let counts = self.winternitz.hash_counts(msg);
+        if public.0.len() != 1 + self.winternitz.len || sig.0.len() != self.winternitz.len {
+            return false;
+        }
+
+        counts.iter().zip(public.ends().iter().zip(sig.0.iter()))
+            .enumerate()
+            .all(|(i, (&count, (pk, &s)))| *pk == self.chain(i, s, count, self.winternitz.w - 1, public.seed()))
+    }
+}
+
+impl<H: Hasher<Node = U256>> Encode for WotsPlus<H> {
+    fn write_signature(&self, sig: &Key, out: &mut Vec<u8>) {
+        self.winternitz.write_signature(sig, out)
+    }
+
+    fn read_signature(&self, input: &mut Reader) -> Result<Key, Error> {
+        self.winternitz.read_signature(input)
+    }
+
+    fn write_public(&self, public: &PlusKey, out: &mut Vec<u8>) {
+        out.extend_from_slice(public.as_ref());
+    }
+
+    fn read_public(&self, input: &mut Reader) -> Result<PlusKey, Error> {
+        Ok(PlusKey(input.nodes(1 + self.winternitz.len)?))
+    }
+}
+
+impl<H: Hasher<Node = U256>> Estimate for WotsPlus<H> {
+    fn public_size(&self) -> usize {
+        (1 + self.winternitz.len) * 32
+    }
+
+    fn signature_size(&self) -> usize {
+        self.winternitz.len * 32
+    }
+
+    /// Each step derives a key and a mask besides the chain hash
+    fn verify_hashes(&self) -> usize {
+        1 + self.winternitz.len * (self.winternitz.w - 1) * 3
     }
 }
 
@@ -133,4 +510,137 @@ mod tests {
 
         assert!(!winternitz.verify(msg1, &public, &sig));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn chains() {
+        let winternitz = Winternitz::new(16);
+        let (private, public) = winternitz.gen_keys(None);
+        assert_eq!(chain::<Sha256>(chain::<Sha256>(private, 0, 3), 3, 15), chain::<Sha256>(private, 0, 15));
+
+        let start = winternitz.gen_private(private).0[0];
+        assert_eq!(chain::<Sha256>(start, 0, 15), public.0[0]);
+
+        // Batched walks, of different lengths or none at all, match single ones
+        let mut values = [start, private, [7; 32]];
+        super::chains::<Sha256>(&mut values, &[0, 2, 5], &[15, 9, 5]);
+        assert_eq!(values, [public.0[0], chain::<Sha256>(private, 2, 9), [7; 32]]);
+    }
+
+    #[test]
+    fn malformed() {
+        let msg = b"My OS update";
+
+        let winternitz = Winternitz::new(16);
+        let (private, public) = winternitz.gen_keys(None);
+        let sig = winternitz.sign(msg, &private);
+
+        assert!(!winternitz.verify(msg, &public, &Key(sig.0[1..].into())));
+        assert!(!winternitz.verify(msg, &Key(Box::new([])), &sig));
+        assert!(!Winternitz::new(4).verify(msg, &public, &sig));
+    }
+
+    #[test]
+    fn fixed_length() {
+        let winternitz = Winternitz::new(16);
+
+        // The checksum of 64 zero digits is 64 * 15 = 0x3c0
+        let digits = winternitz.digits(&[0; 32]);
+        assert_eq!(digits.len(), 67);
+        assert_eq!(digits[64..], [0x0, 0xc, 0x3]);
+
+        // A zero checksum still takes up its digits
+        let digits = winternitz.digits(&[0xff; 32]);
+        assert_eq!(digits.len(), 67);
+        assert_eq!(digits[64..], [0, 0, 0]);
+
+        let (private, _) = winternitz.gen_keys(None);
+        for msg in [&b"My OS update"[..], b"", b"My important message"] {
+            assert_eq!(winternitz.sign(msg, &private).0.len(), winternitz.len);
+        }
+        for &w in &[2, 8, 32, 256] {
+            let winternitz = Winternitz::new(w);
+            assert_eq!(winternitz.digits(&[0x5a; 32]).len(), winternitz.len);
+        }
+    }
+
+    #[test]
+    fn wots_plus() {
+        let msg = b"My OS update";
+        let wots = WotsPlus::new(16);
+        let (private, public) = wots.gen_keys(Some([7; 32]));
+        let sig = wots.sign(msg, &private);
+        assert!(wots.verify(msg, &public, &sig));
+        assert!(!wots.verify(b"My OS apdate", &public, &sig));
+        assert!(!wots.verify(msg, &PlusKey(public.0[..1].into()), &sig));
+
+        // Masks and keys differ between addresses, and from the plain chains
+        let seed = *public.seed();
+        let address = Address { layer: 1, tree: 2, key_pair: 3, ..Address::default() };
+        let elsewhere = wots.with_address(address);
+        assert!(!elsewhere.verify(msg, &public, &sig));
+        assert_ne!(elsewhere.gen_keys(Some([7; 32])).1.ends(), public.ends());
+        let start = wots.winternitz.gen_private(private).0[0];
+        assert_ne!(chain::<Sha256>(start, 0, 15), public.ends()[0]);
+
+        let address = address.with_chain(5);
+        assert_eq!(chain_plus::<Sha256>(chain_plus::<Sha256>(start, 0, 3, &seed, address), 3, 15, &seed, address),
+                   chain_plus::<Sha256>(start, 0, 15, &seed, address));
+        assert_eq!(&address.to_bytes()[..4], [0, 0, 0, 1]);
+        assert_eq!(address.to_bytes()[23], 5);
+        assert_eq!(address.with_hash(9).to_bytes()[27], 9);
+
+        // Callers can reproduce the chains of a key from its addresses
+        let counts = wots.winternitz.hash_counts(msg);
+        for i in [0, 7] {
+            let address = Address::default().with_chain(i as u32);
+            let start = wots.winternitz.gen_private(private).0[i];
+            assert_eq!(chain_plus::<Sha256>(start, 0, 15, &seed, address), public.ends()[i]);
+            assert_eq!(chain_plus::<Sha256>(sig.0[i], counts[i], 15, &seed, address), public.ends()[i]);
+        }
+
+        assert_eq!(crate::validate_composition(&crate::merkle::Merkle::new(3, wots)), Ok(()));
+    }
+
+    #[test]
+    fn compressed() {
+        let msg = b"My OS update";
+        let compressed = CompressedWinternitz::new(16);
+        let (private, public) = compressed.gen_keys(Some([7; 32]));
+        let sig = compressed.sign(msg, &private);
+        assert!(compressed.verify(msg, &public, &sig));
+        assert!(!compressed.verify(b"My OS apdate", &public, &sig));
+        assert!(!compressed.verify(msg, &public, &Key(sig.0[1..].into())));
+
+        // The same chains as the full key, down to the signatures
+        let winternitz = Winternitz::new(16);
+        let (_, full) = winternitz.gen_keys(Some([7; 32]));
+        assert_eq!(public, CompressedWinternitz::<Sha256>::compress(&full));
+        assert!(winternitz.verify(msg, &full, &sig));
+
+        // Merkle signatures carry a 32-byte leaf key instead of 67 chain ends
+        let merkle = crate::merkle::Merkle::new(3, compressed);
+        let full_size = crate::merkle::Merkle::new(3, winternitz).signature_size();
+        assert_eq!(merkle.signature_size(), full_size - 66 * 32);
+        assert_eq!(crate::validate_composition(&merkle), Ok(()));
+    }
+
+    #[test]
+    fn large_w() {
+        let lens: Vec<_> = [2, 4, 8, 16, 32, 64, 128, 256].iter()
+            .map(|&w| {
+                let winternitz = Winternitz::new(w);
+                (winternitz.len1, winternitz.len2)
+            })
+            .collect();
+        assert_eq!(lens, [(256, 9), (128, 5), (86, 4), (64, 3), (52, 3), (43, 2), (37, 2), (32, 2)]);
+
+        let msg = b"My OS update";
+        for &w in &[32, 256] {
+            let winternitz = Winternitz::new(w);
+            let (private, public) = winternitz.gen_keys(None);
+            let sig = winternitz.sign(msg, &private);
+            assert!(winternitz.verify(msg, &public, &sig));
+            assert!(!winternitz.verify(b"My OS apdate", &public, &sig));
+        }
+    }
+}
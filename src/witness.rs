@@ -0,0 +1,67 @@
+use crate::SignatureScheme;
+use crate::util::{HashCall, record_hashes, to_hex};
+
+/// Trace of a verification: every hash invocation in the order it was made,
+/// along with the verification result.
+pub struct Witness {
+    valid: bool,
+    calls: Vec<HashCall>,
+}
+
+impl Witness {
+    pub fn valid(&self) -> bool {
+        self.valid
+    }
+
+    pub fn calls(&self) -> &[HashCall] {
+        &self.calls
+    }
+
+    /// Renders the witness as JSON, with inputs and outputs as lowercase hex:
+    /// `{"valid":true,"calls":[{"input":"..","output":".."},..]}`
+    pub fn to_json(&self) -> String {
+        let calls: Vec<_> = self.calls.iter()
+            .map(|call| format!(r#"{{"input":"{}","output":"{}"}}"#, to_hex(&call.input), to_hex(&call.output)))
+            .collect();
+
+        format!(r#"{{"valid":{},"calls":[{}]}}"#, self.valid, calls.join(","))
+    }
+}
+
+pub(crate) fn export<S: SignatureScheme>(scheme: &S, msg: &[u8], public: &S::Public, sig: &S::Signature) -> Witness {
+    let (valid, calls) = record_hashes(|| scheme.verify(msg, public, sig));
+    Witness { valid, calls }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::merkle::Merkle;
+    use crate::util::{hash, hash_in, Domain};
+    use crate::winternitz::Winternitz;
+    use crate::SignatureScheme;
+
+    #[test]
+    fn it_works() {
+        let msg = b"My OS update";
+
+        let winternitz = Winternitz::new(16);
+        let (private, public) = winternitz.gen_keys(None);
+        let sig = winternitz.sign(msg, &private);
+
+        let witness = winternitz.export_witness(msg, &public, &sig);
+        assert!(witness.valid());
+        // The message digest comes first, followed by the chain steps
+        assert_eq!(witness.calls()[0].output[..], hash_in(Domain::Message, msg));
+        assert!(witness.calls()[1..].iter().all(|call| call.output[..] == hash(&call.input)));
+
+        let merkle = Merkle::new(3, winternitz);
+        let (private, public) = merkle.gen_keys(None);
+        let sig = merkle.sign(msg, &private);
+
+        let witness = merkle.export_witness(msg, &public, &sig);
+        assert!(witness.valid());
+        assert_eq!(witness.calls().last().unwrap().output[..], public);
+        assert!(witness.to_json().starts_with(r#"{"valid":true,"calls":[{"input":""#));
+    }
+}